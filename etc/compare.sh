@@ -0,0 +1,41 @@
+#!/bin/sh
+# Compare the compressed sizes of several models on a set of files.
+# Usage: etc/compare.sh "ybs dark" file1 file2 ...
+# Prints a markdown table, verifying that every file decodes back.
+# The intermediate files are written into the current directory.
+# Sizes given by another compressor, such as the published ones of
+# the original YBS, are added as the last column with REFERENCE=file,
+# which lists a file name and its compressed size per line, and the
+# column is named by REFERENCE_NAME.
+set -e
+DARK=${DARK:-target/release/dark}
+REFERENCE_NAME=${REFERENCE_NAME:-reference}
+MODELS=$1
+shift
+
+printf "| File | Size |"
+for m in $MODELS; do printf " %s |" "$m"; done
+[ -n "$REFERENCE" ] && printf " %s |" "$REFERENCE_NAME"
+printf "\n|------|------|"
+for m in $MODELS; do printf "%s|" "------"; done
+[ -n "$REFERENCE" ] && printf "%s|" "------"
+printf "\n"
+
+for f in "$@"; do
+    name=$(basename "$f")
+    base="${name%.*}"
+    printf "| %s | %s |" "$name" "$(wc -c < "$f" | tr -d ' ')"
+    for m in $MODELS; do
+        $DARK -m "$m" "$f"
+        size=$(wc -c < "$base.dark" | tr -d ' ')
+        $DARK "$base.dark"
+        cmp -s "$f" "$base.orig" || { echo "Roundtrip failed: $m on $f" >&2; exit 1; }
+        printf " %s |" "$size"
+    done
+    if [ -n "$REFERENCE" ]; then
+        size=$(awk -v name="$name" '$1 == name {print $2}' "$REFERENCE")
+        printf " %s |" "${size:--}"
+    fi
+    printf "\n"
+    rm -f "$base.dark" "$base.orig"
+done
@@ -6,7 +6,7 @@
 typedef unsigned char uchar;
 typedef unsigned short ushort;
 typedef unsigned int uint;
-typedef unsigned long ulong;
+typedef unsigned int ulong;	//a 32-bit word, also on 64-bit targets
 
 namespace ark	{
 	void Set(char,FILE*);
@@ -0,0 +1,152 @@
+/*!
+
+Alphabet reordering
+
+BWT sorts the contexts in the order of their symbols, so the symbols
+that are followed by similar contexts should better be neighbours in
+the alphabet. The natural byte order interleaves vowels with consonants
+and separates the letter cases, which breaks these groups apart for text.
+
+The permutation is applied to the input before the suffix sorting,
+and reverted after the BWT is undone.
+
+*/
+
+use std::fmt;
+use saca::Symbol;
+
+const ALPHABET_SIZE: usize = 0x100;
+/// Letters ordered by the similarity of their following contexts
+const LETTERS: &'static [u8] = b"aeioubcdgfhrlsmnpqjktwvxyz";
+
+
+/// Symbol order applied before the suffix sorting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Order {
+    /// Natural byte order
+    Natural,
+    /// Text-friendly order: vowels grouped together, followed by consonants,
+    /// with upper-case letters forming a separate group of the same order
+    Text,
+}
+
+impl Order {
+    /// Parse the order name
+    pub fn from_name(name: &str) -> Option<Order> {
+        match name {
+            "natural"   => Some(Order::Natural),
+            "text"      => Some(Order::Text),
+            _           => None,
+        }
+    }
+
+    /// Return the identifier stored in the header
+    pub fn to_id(&self) -> u8 {
+        match *self {
+            Order::Natural  => 0,
+            Order::Text     => 1,
+        }
+    }
+
+    /// Restore the order from a header identifier
+    pub fn from_id(id: u8) -> Option<Order> {
+        match id {
+            0   => Some(Order::Natural),
+            1   => Some(Order::Text),
+            _   => None,
+        }
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Order::Natural  => "natural",
+            Order::Text     => "text",
+        })
+    }
+}
+
+
+/// Reversible symbol permutation
+pub struct Permutation {
+    forward : [Symbol; ALPHABET_SIZE],
+    backward: [Symbol; ALPHABET_SIZE],
+}
+
+impl Permutation {
+    /// Create a new permutation for a given order
+    pub fn new(order: Order) -> Permutation {
+        // list of symbols in the order of their new codes
+        let mut list: Vec<Symbol> = Vec::with_capacity(ALPHABET_SIZE);
+        match order {
+            Order::Natural => {
+                list.extend((0..ALPHABET_SIZE).map(|i| i as Symbol));
+            },
+            Order::Text => {
+                let is_letter = |s: Symbol| (s|0x20) >= b'a' && (s|0x20) <= b'z';
+                list.extend((0..0x80).map(|i| i as Symbol).filter(|&s| !is_letter(s)));
+                list.extend(LETTERS.iter().cloned());
+                list.extend(LETTERS.iter().map(|&s| s & !0x20));
+                list.extend((0x80..ALPHABET_SIZE).map(|i| i as Symbol));
+            },
+        }
+        assert_eq!(list.len(), ALPHABET_SIZE);
+        let mut perm = Permutation {
+            forward : [0; ALPHABET_SIZE],
+            backward: [0; ALPHABET_SIZE],
+        };
+        for (code, &sym) in list.iter().enumerate() {
+            perm.forward[sym as usize] = code as Symbol;
+            perm.backward[code] = sym;
+        }
+        perm
+    }
+
+    /// Translate the original symbols into the permuted alphabet
+    pub fn encode(&self, data: &mut [Symbol]) {
+        for sym in data.iter_mut() {
+            *sym = self.forward[*sym as usize];
+        }
+    }
+
+    /// Translate the permuted symbols back into the original alphabet
+    pub fn decode(&self, data: &mut [Symbol]) {
+        for sym in data.iter_mut() {
+            *sym = self.backward[*sym as usize];
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::{Order, Permutation};
+
+    const TEXT: &'static [u8] = include_bytes!("../LICENSE");
+
+    fn roundtrip(order: Order) {
+        let perm = Permutation::new(order);
+        let mut data = TEXT.to_vec();
+        perm.encode(&mut data);
+        perm.decode(&mut data);
+        assert_eq!(&data[..], TEXT);
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(Order::Natural);
+        roundtrip(Order::Text);
+    }
+
+    #[test]
+    fn bijective() {
+        let perm = Permutation::new(Order::Text);
+        let mut used = [false; 0x100];
+        for &code in perm.forward.iter() {
+            assert!(!used[code as usize]);
+            used[code as usize] = true;
+        }
+        assert_eq!(Order::from_id(Order::Text.to_id()), Some(Order::Text));
+    }
+}
@@ -0,0 +1,204 @@
+/*!
+
+Clustering of the context cells
+
+The records are split into cells by their symbol, the range of the last
+rank and the range of the distance log. The cells whose distances are all
+zero form a single group, and every other cell starts as a group of its own.
+The groups whose distance distributions are the closest, by the mean and
+the variance of `ln(1 + log2(d+1))`, are merged until the closest pair
+differs by more than a threshold.
+
+*/
+
+use std::{fmt, io};
+
+use model::Symbol;
+use super::Value;
+
+const RANK_LIMITS: [u32; 4] = [0, 4, 16, 256];
+const DIST_LIMITS: [f32; 14] = [0.0, 0.5, 0.75, 1.0, 1.25, 1.5,
+    1.75, 2.0, 2.25, 2.5, 2.75, 3.0, 3.5, 4.0];
+const NUM_CELLS: usize = (RANK_LIMITS.len() * DIST_LIMITS.len()) << 8;
+
+/// Context cell of a record
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    /// Context symbol
+    pub symbol: Symbol,
+    /// Upper limit index of the last rank
+    pub rank: usize,
+    /// Upper limit index of the distance log
+    pub dist: usize,
+}
+
+impl Cell {
+    fn new(v: &Value) -> Cell {
+        let dlog = (v.dist_log + 1.0).ln();
+        Cell {
+            symbol: v.symbol,
+            rank: RANK_LIMITS.iter().position(|&rl| (v.last_rank as u32) < rl).unwrap(),
+            dist: DIST_LIMITS.iter().position(|&dl| dlog < dl).unwrap(),
+        }
+    }
+
+    fn encode(&self) -> usize {
+        self.symbol as usize + (self.rank << 8) + (self.dist << 8) * RANK_LIMITS.len()
+    }
+
+    fn decode(id: usize) -> Cell {
+        Cell {
+            symbol: id as Symbol,
+            rank: (id >> 8) % RANK_LIMITS.len(),
+            dist: (id >> 8) / RANK_LIMITS.len(),
+        }
+    }
+
+    /// Order by the symbol, then the rank, then the distance
+    fn order(&self) -> usize {
+        self.dist + DIST_LIMITS.len() * (self.rank + RANK_LIMITS.len() * self.symbol as usize)
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Context(sym:{},\trank:{}-{},\tdist:{:.2}-{:.2})",
+            self.symbol, RANK_LIMITS[self.rank-1], RANK_LIMITS[self.rank],
+            DIST_LIMITS[self.dist-1].exp() - 1.0, DIST_LIMITS[self.dist].exp() - 1.0)
+    }
+}
+
+/// Moments of the distance distribution
+#[derive(Clone, Copy, Debug)]
+struct DistSet {
+    m0: f32,
+    m1: f32,
+    m2: f32,
+    avg: f32,
+}
+
+impl DistSet {
+    fn new() -> DistSet {
+        DistSet { m0: 0.0, m1: 0.0, m2: 0.0, avg: 0.0 }
+    }
+
+    fn add(&mut self, x: f32) {
+        self.m0 += 1.0;
+        self.m1 += x;
+        self.m2 += x*x;
+        self.avg = self.m1 / self.m0;
+    }
+
+    fn merge(&mut self, other: &DistSet) {
+        self.m0 += other.m0;
+        self.m1 += other.m1;
+        self.m2 += other.m2;
+        self.avg = self.m1 / self.m0;
+    }
+
+    fn get_variance(&self) -> f32 {
+        self.m2 / self.m0 - self.avg*self.avg
+    }
+
+    fn get_distance(&self, other: &DistSet) -> f32 {
+        let d1 = self.avg - other.avg;
+        let d2 = self.get_variance() - other.get_variance();
+        d1*d1 + d2*d2
+    }
+}
+
+/// A group of cells
+struct Group {
+    dist: DistSet,
+    cells: Vec<Cell>,
+}
+
+impl Group {
+    fn consume(&mut self, other: Group) {
+        self.dist.merge(&other.dist);
+        self.cells.extend(other.cells);
+    }
+}
+
+/// Merge the cells into groups while the closest ones differ
+/// by no more than a threshold, and write the groups out, the largest first
+pub fn process<W: io::Write>(values: &[Value], threshold: f32, out: &mut W) -> io::Result<()> {
+    // populate groups
+    let mut sets = vec![DistSet::new(); NUM_CELLS];
+    for v in values.iter() {
+        sets[Cell::new(v).encode()].add((v.dist_log + 1.0).ln());
+    }
+    let (mut groups, zero): (Vec<_>, Vec<_>) = sets.iter().enumerate()
+        .filter(|&(_, set)| set.m0 > 0.0)
+        .map(|(i, set)| Group {
+            dist: *set,
+            cells: vec![Cell::decode(i)],
+        }).partition(|g| g.dist.m1 != 0.0);
+    let mut zero = zero.into_iter();
+    if let Some(mut first) = zero.next() {
+        for g in zero {
+            first.consume(g);
+        }
+        groups.push(first);
+    }
+    info!("Base {}/{} groups", groups.len(), NUM_CELLS);
+    // merge iteratively
+    while groups.len() > 1 {
+        let mut best = (0, 0, ::std::f32::MAX);
+        for i in 0 .. groups.len()-1 {
+            for j in i+1 .. groups.len() {
+                let diff = groups[i].dist.get_distance(&groups[j].dist);
+                if diff < best.2 {
+                    best = (i, j, diff);
+                }
+            }
+        }
+        if best.2 > threshold {
+            break
+        }
+        let removed = groups.swap_remove(best.1);
+        groups[best.0].consume(removed);
+    }
+    // dump
+    groups.sort_by(|a, b| b.dist.m0.partial_cmp(&a.dist.m0).unwrap());
+    try!(writeln!(out, "Clustering into {} groups", groups.len()));
+    for g in groups.iter_mut() {
+        g.cells.sort_by(|a, b| a.order().cmp(&b.order()));
+        try!(writeln!(out, "Group of {} ({})\n\tDistLog: {}\t(mean {}, var {})",
+            g.cells.len(), g.dist.m0,
+            g.dist.avg.exp() - 1.0, g.dist.avg, g.dist.get_variance()));
+        for cell in g.cells.iter() {
+            try!(writeln!(out, "\t{}", cell));
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use analyze::test::make_values;
+
+    fn count_groups(threshold: f32) -> usize {
+        let distances: Vec<u32> = (0 .. 200).map(|i| (i * i) % 37).collect();
+        let mut out = Vec::new();
+        super::process(&make_values(&distances), threshold, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        text.matches("Group of").count()
+    }
+
+    #[test]
+    fn thresholds() {
+        let all = count_groups(-1.0);
+        assert!(all > 10);
+        assert!(count_groups(0.01) < all);
+        assert_eq!(count_groups(1000.0), 1);
+    }
+
+    #[test]
+    fn cells() {
+        for id in 0 .. super::NUM_CELLS {
+            assert_eq!(super::Cell::decode(id).encode(), id);
+        }
+    }
+}
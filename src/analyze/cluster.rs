@@ -0,0 +1,123 @@
+/*!
+
+Bottom-up clustering of the records
+
+Every record starts as a group of its own. The two groups with the closest
+means are merged, until a single group is left, and the groups are written
+out whenever their number reaches one of the requested sizes. The search
+is quadratic in the number of groups, so it is only practical for small
+dumps.
+
+*/
+
+use std::io;
+
+use super::Value;
+
+const NUM_FEATURES: usize = 5;
+const NAMES: [&'static str; NUM_FEATURES] = [
+    "DistLog", "Symbol", "Rank avg", "DistLimLog", "DistLog avg"];
+
+fn get_features(v: &Value) -> [f32; NUM_FEATURES] {
+    [v.dist_log, v.symbol as f32, v.rank_avg as f32, v.dist_lim_log, v.dist_log_avg]
+}
+
+/// Difference between the mean features of two groups. The symbols and the rank
+/// averages are only compared for equality, by the integer parts of their means.
+fn get_distance(a: &[f32; NUM_FEATURES], b: &[f32; NUM_FEATURES]) -> f32 {
+    (a[0] - b[0]).abs() +
+    (if a[1].floor() == b[1].floor() {0.0} else {1.0}) +
+    (if a[2].floor() == b[2].floor() {0.0} else {0.4}) +
+    (a[3] - b[3]).abs() * 0.2 +
+    (a[4] - b[4]).abs()
+}
+
+/// A group of records
+#[derive(Clone, Debug)]
+pub struct Group {
+    /// Number of the records
+    pub count: usize,
+    sum: [f32; NUM_FEATURES],
+    sum_sqr: [f32; NUM_FEATURES],
+    mean: [f32; NUM_FEATURES],
+}
+
+impl Group {
+    fn new(v: &Value) -> Group {
+        let f = get_features(v);
+        let mut sqr = f;
+        for x in sqr.iter_mut() {
+            *x *= *x;
+        }
+        Group {
+            count: 1,
+            sum: f,
+            sum_sqr: sqr,
+            mean: f,
+        }
+    }
+
+    fn merge(&mut self, other: &Group) {
+        self.count += other.count;
+        for i in 0 .. NUM_FEATURES {
+            self.sum[i] += other.sum[i];
+            self.sum_sqr[i] += other.sum_sqr[i];
+            self.mean[i] = self.sum[i] / self.count as f32;
+        }
+    }
+
+    fn write<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        try!(writeln!(out, "Group of {}", self.count));
+        for i in 0 .. NUM_FEATURES {
+            let dispersion = self.sum_sqr[i] / self.count as f32 - self.mean[i] * self.mean[i];
+            try!(writeln!(out, "\t{}: {}\t({})", NAMES[i], self.mean[i], dispersion));
+        }
+        Ok(())
+    }
+}
+
+/// Merge the records into groups, writing the groups out, the largest first,
+/// when their number gets to each of the given sizes
+pub fn process<W: io::Write>(values: &[Value], sizes: &[usize], out: &mut W) -> io::Result<()> {
+    let mut groups: Vec<Group> = values.iter().map(Group::new).collect();
+    while groups.len() > 1 {
+        let mut best = (0, 0, ::std::f32::MAX);
+        for i in 0 .. groups.len()-1 {
+            for j in i+1 .. groups.len() {
+                let diff = get_distance(&groups[i].mean, &groups[j].mean);
+                if diff < best.2 {
+                    best = (i, j, diff);
+                }
+            }
+        }
+        let removed = groups.swap_remove(best.1);
+        groups[best.0].merge(&removed);
+        if sizes.contains(&groups.len()) {
+            let mut sorted: Vec<&Group> = groups.iter().collect();
+            sorted.sort_by(|a, b| b.count.cmp(&a.count));
+            try!(writeln!(out, "Clustering into {} groups", groups.len()));
+            for g in sorted.iter() {
+                try!(g.write(out));
+            }
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use analyze::test::make_values;
+
+    #[test]
+    fn groups() {
+        // symbols go in circles of 7, so the first 7 values are all different
+        let values = make_values(&[0, 1, 2, 3, 4, 5, 6]);
+        let mut out = Vec::new();
+        super::process(&values, &[6, 1], &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.matches("Clustering into").count(), 2);
+        assert_eq!(text.matches("Group of 1\n").count(), 5);
+        assert!(text.contains("Group of 2\n") && text.contains("Group of 7\n"));
+    }
+}
@@ -0,0 +1,121 @@
+/*!
+
+DC context analysis
+
+Research tools working on the dumps of the `rawdc` model, described in
+`model::raw`, in order to find the contexts worth telling apart in the
+DC models. Each record is extended with the running averages of the last
+rank and of the distance log before it, and passed to one of the methods:
+
+- `cluster` merges the records bottom-up by the similarity of their contexts
+- `cell` merges the cells of the symbol, rank and distance average contexts
+- `print` writes the records as CSV
+- `report` writes the distance distributions per symbol as CSV and SVG
+- `stat` correlates the distances with their running average predictions
+
+*/
+
+use std::io;
+
+use model::{Distance, Symbol};
+use model::raw::Reader;
+
+/// Bottom-up clustering of the records
+pub mod cluster;
+/// Clustering of the context cells
+pub mod cell;
+/// Distance distribution report
+pub mod report;
+/// Distance statistics and predictions
+pub mod stat;
+
+/// A dump record with the context derived from the previous ones
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Value {
+    /// Coded distance
+    pub distance    : Distance,
+    /// Binary log of the distance plus one
+    pub dist_log    : f32,
+    /// Context symbol
+    pub symbol      : Symbol,
+    /// Last rank of the symbol
+    pub last_rank   : u8,
+    /// Distance limit of the context
+    pub dist_limit  : usize,
+    /// Running average of the last ranks
+    pub rank_avg    : u32,
+    /// Binary log of the distance limit plus one
+    pub dist_lim_log: f32,
+    /// Running average of the distance logs, before this record
+    pub dist_log_avg: f32,
+}
+
+/// Binary log of a value plus one
+pub fn get_log(d: usize) -> f32 {
+    ((d+1) as f32).log2()
+}
+
+/// Read all the records of a dump
+pub fn read_values<R: io::Read>(reader: Reader<R>) -> io::Result<Vec<Value>> {
+    let mut rank_avg = 0u32;
+    let mut dist_log_avg = 0f32;
+    reader.map(|record| record.map(|(d, ctx)| {
+        let dist_log = get_log(d as usize);
+        rank_avg = (3*rank_avg + ctx.last_rank as u32) >> 2;
+        let value = Value {
+            distance    : d,
+            dist_log    : dist_log,
+            symbol      : ctx.symbol,
+            last_rank   : ctx.last_rank,
+            dist_limit  : ctx.distance_limit,
+            rank_avg    : rank_avg,
+            dist_lim_log: get_log(ctx.distance_limit),
+            dist_log_avg: dist_log_avg,
+        };
+        dist_log_avg = (2.0*dist_log_avg + dist_log) / 3.0;
+        value
+    })).collect()
+}
+
+/// Write the records as CSV lines of the distance, symbol, last rank and distance limit
+pub fn print<W: io::Write>(values: &[Value], out: &mut W) -> io::Result<()> {
+    for v in values.iter() {
+        try!(writeln!(out, "{}, {}, {}, {}", v.distance, v.symbol, v.last_rank, v.dist_limit));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use compress::bwt::dc::Context;
+    use model::raw;
+    use super::Value;
+
+    /// Values of the given distances, with the symbols and ranks going in circles
+    pub fn make_values(distances: &[u32]) -> Vec<Value> {
+        let mut dump = Vec::new();
+        for (i, &d) in distances.iter().enumerate() {
+            let ctx = Context {
+                symbol: (i % 7) as u8,
+                last_rank: (i % 5) as u8,
+                distance_limit: 1000,
+            };
+            raw::write_record(&mut dump, d, &ctx).unwrap();
+        }
+        super::read_values(raw::Reader::new(&dump[..])).unwrap()
+    }
+
+    #[test]
+    fn values() {
+        let values = make_values(&[0, 3, 7, 1]);
+        assert_eq!(values.len(), 4);
+        assert_eq!(values[1].dist_log, 2.0);
+        assert_eq!(values[2].dist_log_avg, 2.0 / 3.0);
+        assert_eq!(values[2].rank_avg, (3*0 + 2) >> 2);
+        assert_eq!(values[3].rank_avg, (3*0 + 3) >> 2);
+        let mut csv = Vec::new();
+        super::print(&values[.. 2], &mut csv).unwrap();
+        assert_eq!(&csv[..], &b"0, 0, 0, 1000\n3, 1, 1, 1000\n"[..]);
+    }
+}
@@ -0,0 +1,194 @@
+/*!
+
+Distance distribution report
+
+Replaces the Octave script of `etc/stat`. The distances of a dump are
+gathered per context symbol and per binary exponent (see
+`block::stats::get_exponent`), and written as CSV tables and SVG plots:
+
+- `symbols.csv`: the count, the mean distance and the mean distance log per symbol
+- `histograms.csv`: the exponent histogram of every symbol, one per line,
+  preceded by the histogram of all the distances
+- `symbols.svg`: the mean distance per symbol
+- `histogram.svg`: the exponent histogram of all the distances
+
+*/
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use block::stats::{NUM_EXPONENTS, get_exponent};
+use super::Value;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 400.0;
+const MARGIN: f64 = 50.0;
+
+/// Distribution of the distances of a dump
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// Number of the distances per symbol and exponent
+    pub histograms: Vec<[usize; NUM_EXPONENTS]>,
+    /// Sum of the distances per symbol
+    pub sums: Vec<f64>,
+    /// Sum of the distance logs per symbol
+    pub log_sums: Vec<f64>,
+}
+
+impl Report {
+    /// Gather the distribution of the records
+    pub fn new(values: &[Value]) -> Report {
+        let mut report = Report {
+            histograms: vec![[0; NUM_EXPONENTS]; 0x100],
+            sums: vec![0.0; 0x100],
+            log_sums: vec![0.0; 0x100],
+        };
+        for v in values.iter() {
+            let sym = v.symbol as usize;
+            report.histograms[sym][get_exponent(v.distance)] += 1;
+            report.sums[sym] += v.distance as f64;
+            report.log_sums[sym] += v.dist_log as f64;
+        }
+        report
+    }
+
+    /// Number of the distances of a symbol
+    pub fn count(&self, sym: usize) -> usize {
+        self.histograms[sym].iter().fold(0, |sum, c| sum + c)
+    }
+
+    /// Mean distance of a symbol, zero if it has none
+    pub fn mean(&self, sym: usize) -> f64 {
+        match self.count(sym) {
+            0 => 0.0,
+            n => self.sums[sym] / n as f64,
+        }
+    }
+
+    /// Exponent histogram of all the distances
+    pub fn histogram(&self) -> [usize; NUM_EXPONENTS] {
+        let mut total = [0; NUM_EXPONENTS];
+        for hist in self.histograms.iter() {
+            for (t, c) in total.iter_mut().zip(hist.iter()) {
+                *t += *c;
+            }
+        }
+        total
+    }
+
+    /// Write the per symbol statistics as CSV, skipping the absent symbols
+    pub fn write_symbols<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        try!(writeln!(out, "symbol,count,mean,mean_log"));
+        for sym in 0 .. 0x100 {
+            let count = self.count(sym);
+            if count != 0 {
+                try!(writeln!(out, "{},{},{:.3},{:.3}", sym, count, self.mean(sym),
+                    self.log_sums[sym] / count as f64));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the exponent histograms as CSV, the total one
+    /// in the `all` line, then the present symbols
+    pub fn write_histograms<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        fn line<W: io::Write>(out: &mut W, name: &str, hist: &[usize]) -> io::Result<()> {
+            try!(write!(out, "{}", name));
+            for c in hist.iter() {
+                try!(write!(out, ",{}", c));
+            }
+            writeln!(out, "")
+        }
+        try!(write!(out, "symbol"));
+        for e in 0 .. NUM_EXPONENTS {
+            try!(write!(out, ",{}", e));
+        }
+        try!(writeln!(out, ""));
+        try!(line(out, "all", &self.histogram()));
+        for (sym, hist) in self.histograms.iter().enumerate() {
+            if hist.iter().any(|&c| c != 0) {
+                try!(line(out, &sym.to_string(), hist));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write all the tables and plots into a directory, creating it if needed
+    pub fn write_all(&self, dir: &Path) -> io::Result<()> {
+        try!(fs::create_dir_all(dir));
+        let create = |name: &str| File::create(dir.join(name)).map(io::BufWriter::new);
+        try!(self.write_symbols(&mut try!(create("symbols.csv"))));
+        try!(self.write_histograms(&mut try!(create("histograms.csv"))));
+        let means: Vec<f64> = (0 .. 0x100).map(|sym| self.mean(sym)).collect();
+        try!(write_bars(&mut try!(create("symbols.svg")), "Avg distance per symbol",
+            "Symbol", "Avg distance", &means));
+        let histogram: Vec<f64> = self.histogram().iter().map(|&c| c as f64).collect();
+        write_bars(&mut try!(create("histogram.svg")), "Distance log histogram",
+            "Exponent", "Count", &histogram)
+    }
+}
+
+/// Write a bar plot of the values as SVG
+pub fn write_bars<W: io::Write>(out: &mut W, title: &str, x_label: &str, y_label: &str,
+                  values: &[f64]) -> io::Result<()> {
+    let max = values.iter().fold(0.0, |m: f64, &v| m.max(v));
+    let (left, bottom) = (MARGIN, HEIGHT - MARGIN);
+    let step = (WIDTH - 2.0*MARGIN) / values.len() as f64;
+    try!(writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">",
+        WIDTH, HEIGHT));
+    try!(writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>"));
+    try!(writeln!(out, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+        WIDTH / 2.0, MARGIN / 2.0, title));
+    for (i, &v) in values.iter().enumerate() {
+        let h = if max > 0.0 {v / max * (bottom - MARGIN)} else {0.0};
+        try!(writeln!(out, "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"steelblue\"/>",
+            left + i as f64 * step, bottom - h, step, h));
+    }
+    try!(writeln!(out, "<path d=\"M{} {} V{} H{}\" stroke=\"black\" fill=\"none\"/>",
+        left, MARGIN, bottom, WIDTH - MARGIN));
+    try!(writeln!(out, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+        WIDTH / 2.0, HEIGHT - MARGIN / 4.0, x_label));
+    try!(writeln!(out, "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>",
+        left - 4.0, bottom, 0));
+    try!(writeln!(out, "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{:.1}</text>",
+        left - 4.0, MARGIN + 4.0, max));
+    try!(writeln!(out, "<text transform=\"translate({} {}) rotate(-90)\" text-anchor=\"middle\">{}</text>",
+        MARGIN / 4.0, HEIGHT / 2.0, y_label));
+    writeln!(out, "</svg>")
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use analyze::test::make_values;
+
+    #[test]
+    fn distribution() {
+        // symbols go in circles of 7
+        let values = make_values(&[0, 1, 2, 3, 4, 5, 6, 7, 0, 3]);
+        let report = super::Report::new(&values);
+        assert_eq!(report.count(0), 2);
+        assert_eq!(report.mean(0), 3.5);
+        assert_eq!(report.mean(8), 0.0);
+        assert_eq!(&report.histogram()[.. 4], &[2, 2, 5, 1]);
+        let mut csv = Vec::new();
+        report.write_symbols(&mut csv).unwrap();
+        let text = String::from_utf8(csv).unwrap();
+        assert_eq!(text.lines().count(), 1 + 7);
+        assert!(text.contains("\n0,2,3.500,1.500\n"));
+        let mut csv = Vec::new();
+        report.write_histograms(&mut csv).unwrap();
+        let text = String::from_utf8(csv).unwrap();
+        assert!(text.lines().nth(1).unwrap().starts_with("all,2,2,5,1,0,"));
+    }
+
+    #[test]
+    fn bars() {
+        let mut svg = Vec::new();
+        super::write_bars(&mut svg, "Title", "x", "y", &[1.0, 0.0, 2.0]).unwrap();
+        let text = String::from_utf8(svg).unwrap();
+        assert!(text.starts_with("<svg") && text.ends_with("</svg>\n"));
+        assert_eq!(text.matches("fill=\"steelblue\"").count(), 3);
+    }
+}
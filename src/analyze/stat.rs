@@ -0,0 +1,111 @@
+/*!
+
+Distance statistics and predictions
+
+The distances are taken as `ln(1 + log2(d+1))`. Their mean and standard
+deviation are printed, followed by the correlation of the distances with
+the running averages of the distances before them: over all the records,
+per context symbol and per last rank. Each correlation is given as the
+plain one and as the one measured against the rolling means.
+
+*/
+
+use std::io;
+
+use super::Value;
+
+/// Rate of the running averages
+const ADAPT: f32 = 0.25;
+
+/// Count, mean and standard deviation
+fn variate(values: &[f32]) -> (f32, f32, f32) {
+    let (s0, s1, s2) = values.iter().fold((0.0, 0.0, 0.0), |(s0, s1, s2), &v| {
+        (s0 + 1.0, s1 + v, s2 + v*v)
+    });
+    let m1 = s1 / s0;
+    let m2 = (s2 / s0 - m1*m1).sqrt();
+    (s0, m1, m2)
+}
+
+/// Pearson correlation
+fn correlate(u: &[f32], v: &[f32]) -> f32 {
+    let (num, mu1, mu2) = variate(u);
+    let (_, mv1, mv2) = variate(v);
+    let uv = u.iter().zip(v.iter()).fold(0.0, |uv, (&a, &b)| {
+        uv + (a - mu1) * (b - mv1)
+    });
+    uv / (num * mu2 * mv2)
+}
+
+/// Correlation of the deviations from the rolling means
+fn correlate_roll(u: &[f32], v: &[f32], adapt: f32) -> f32 {
+    let (_, _, uu, vv, uv) = u.iter().zip(v.iter()).fold((0.0, 0.0, 0.0, 0.0, 0.0),
+        |(u1, v1, uu, vv, uv), (&a, &b)| {
+        let (du, dv) = (a - u1, b - v1);
+        (u1 + adapt*du, v1 + adapt*dv, uu + du*du, vv + dv*dv, uv + du*dv)
+    });
+    2.0 * uv / (uu + vv)
+}
+
+/// Predict every distance by the running average of the previous ones in its context
+fn predict<F: Fn(&Value) -> usize>(distances: &[f32], values: &[Value], num_contexts: usize,
+           get_context: F) -> Vec<f32> {
+    let mut avg = vec![0.5f32; num_contexts];
+    distances.iter().zip(values.iter()).map(|(&d, v)| {
+        let a = &mut avg[get_context(v)];
+        let old = *a;
+        *a = ADAPT*d + (1.0 - ADAPT) * *a;
+        old
+    }).collect()
+}
+
+/// Distances of the records, as used by the statistics
+pub fn get_distances(values: &[Value]) -> Vec<f32> {
+    values.iter().map(|v| (v.dist_log + 1.0).ln()).collect()
+}
+
+/// Write the statistics of the records
+pub fn process<W: io::Write>(values: &[Value], out: &mut W) -> io::Result<()> {
+    let distances = get_distances(values);
+    let (_, mean, deviation) = variate(&distances);
+    try!(writeln!(out, "\tMean: {}, Deviation: {}", mean, deviation));
+    let predictors: [(&str, usize, fn(&Value) -> usize); 3] = [
+        ("global", 1, |_| 0),
+        ("symbol", 0x100, |v| v.symbol as usize),
+        ("rank", 0x100, |v| v.last_rank as usize),
+    ];
+    for &(name, num_contexts, get_context) in predictors.iter() {
+        let prediction = predict(&distances, values, num_contexts, get_context);
+        try!(writeln!(out, "\tCorrelation(dist, predict_{}) = {}\t| {}", name,
+            correlate(&distances, &prediction),
+            correlate_roll(&distances, &prediction, ADAPT)));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use analyze::test::make_values;
+
+    #[test]
+    fn correlations() {
+        let u = [1.0, 2.0, 4.0, 3.0];
+        let v = [3.0, 1.0, -3.0, -1.0];
+        assert!((super::correlate(&u, &u) - 1.0).abs() < 1e-6);
+        assert!((super::correlate(&u, &v) + 1.0).abs() < 1e-6);
+        assert!((super::correlate_roll(&u, &u, 0.5) - 1.0).abs() < 1e-6);
+        let (count, mean, deviation) = super::variate(&u);
+        assert_eq!((count, mean), (4.0, 2.5));
+        assert!((deviation - 1.25f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn report() {
+        let distances: Vec<u32> = (0 .. 100).map(|i| i % 10).collect();
+        let mut out = Vec::new();
+        super::process(&make_values(&distances), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), 4);
+    }
+}
@@ -0,0 +1,224 @@
+/*!
+
+Corpus benchmark
+
+Every model is run over every file of a corpus directory, and the round
+trip is verified. The results are printed as a table of the compressed
+sizes, bits per byte and coding speeds, followed by the totals of each
+model over the corpus, and can be exported as JSON to track regressions.
+A model that failed on some files gets no total, as it would not be
+comparable with the others, and is listed instead.
+
+*/
+
+use std::io;
+use std::time::Duration;
+
+/// Models that code a file without any extra inputs
+pub const MODELS: &'static [&'static str] = &[
+    "bbb", "dark", "exp", "fixed", "mix", "mtf", "mtf1", "qlfc", "simple", "wfc", "ybs"];
+
+/// Result of a model on a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// File name, or `total` for the sums over the corpus
+    pub file: String,
+    /// Model name
+    pub model: String,
+    /// Original size in bytes
+    pub size: usize,
+    /// Compressed size in bytes, including the header
+    pub compressed: usize,
+    /// Encoding time in seconds
+    pub encode_time: f64,
+    /// Decoding time in seconds
+    pub decode_time: f64,
+}
+
+fn get_speed(size: usize, time: f64) -> f64 {
+    if time > 0.0 {size as f64 / time * 1.0e-6} else {0.0}
+}
+
+impl Entry {
+    /// Compressed bits per original byte
+    pub fn bits_per_byte(&self) -> f64 {
+        if self.size != 0 {self.compressed as f64 * 8.0 / self.size as f64} else {0.0}
+    }
+
+    /// Encoding speed in MB/s of the original data
+    pub fn encode_speed(&self) -> f64 {
+        get_speed(self.size, self.encode_time)
+    }
+
+    /// Decoding speed in MB/s of the original data
+    pub fn decode_speed(&self) -> f64 {
+        get_speed(self.size, self.decode_time)
+    }
+}
+
+/// Seconds of a duration
+pub fn get_seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1.0e-9
+}
+
+/// Number of the files of each model, in the order of the first appearance of the models
+fn count_files(entries: &[Entry]) -> Vec<(&str, usize)> {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for e in entries.iter() {
+        match counts.iter().position(|&(model, _)| model == e.model) {
+            Some(i) => counts[i].1 += 1,
+            None => counts.push((&e.model, 1)),
+        }
+    }
+    counts
+}
+
+/// Models that lack some of the files the others have, which failed on them
+pub fn get_incomplete(entries: &[Entry]) -> Vec<String> {
+    let counts = count_files(entries);
+    let max = counts.iter().map(|&(_, count)| count).max().unwrap_or(0);
+    counts.iter().filter(|&&(_, count)| count < max).map(|&(model, _)| model.to_string()).collect()
+}
+
+/// Sum the entries of each model, in the order of the first appearance of the models,
+/// leaving out the incomplete models, whose totals are not comparable with the others
+pub fn get_totals(entries: &[Entry]) -> Vec<Entry> {
+    let incomplete = get_incomplete(entries);
+    let mut totals: Vec<Entry> = Vec::new();
+    for e in entries.iter().filter(|e| !incomplete.contains(&e.model)) {
+        match totals.iter().position(|t| t.model == e.model) {
+            Some(i) => {
+                let t = &mut totals[i];
+                t.size += e.size;
+                t.compressed += e.compressed;
+                t.encode_time += e.encode_time;
+                t.decode_time += e.decode_time;
+            },
+            None => totals.push(Entry {
+                file: "total".to_string(),
+                .. e.clone()
+            }),
+        }
+    }
+    totals
+}
+
+/// Write the entries and the totals of the models as a markdown table,
+/// followed by the models left out of the totals
+pub fn write_table<W: io::Write>(entries: &[Entry], out: &mut W) -> io::Result<()> {
+    try!(writeln!(out, "| File | Model | Size | Compressed | Bits/byte | Encode MB/s | Decode MB/s |"));
+    try!(writeln!(out, "|------|-------|------|------------|-----------|-------------|-------------|"));
+    for e in entries.iter().chain(get_totals(entries).iter()) {
+        try!(writeln!(out, "| {} | {} | {} | {} | {:.3} | {:.2} | {:.2} |", e.file, e.model,
+            e.size, e.compressed, e.bits_per_byte(), e.encode_speed(), e.decode_speed()));
+    }
+    let incomplete = get_incomplete(entries);
+    if !incomplete.is_empty() {
+        try!(writeln!(out, "\nNo totals for the models failing on some files: {}", incomplete.join(", ")));
+    }
+    Ok(())
+}
+
+/// Write a string as a JSON literal
+fn write_string<W: io::Write>(s: &str, out: &mut W) -> io::Result<()> {
+    try!(write!(out, "\""));
+    for c in s.chars() {
+        match c {
+            '"'  => try!(write!(out, "\\\"")),
+            '\\' => try!(write!(out, "\\\\")),
+            c if (c as u32) < 0x20 => try!(write!(out, "\\u{:04x}", c as u32)),
+            c => try!(write!(out, "{}", c)),
+        }
+    }
+    write!(out, "\"")
+}
+
+fn write_entries<W: io::Write>(name: &str, entries: &[Entry], out: &mut W) -> io::Result<()> {
+    try!(writeln!(out, "  \"{}\": [", name));
+    for (i, e) in entries.iter().enumerate() {
+        try!(write!(out, "    {{\"file\": "));
+        try!(write_string(&e.file, out));
+        try!(write!(out, ", \"model\": "));
+        try!(write_string(&e.model, out));
+        try!(write!(out, ", \"size\": {}, \"compressed\": {}, \"bits_per_byte\": {:.4}, \"encode_seconds\": {:.6}, \"decode_seconds\": {:.6}}}",
+            e.size, e.compressed, e.bits_per_byte(), e.encode_time, e.decode_time));
+        try!(writeln!(out, "{}", if i + 1 < entries.len() {","} else {""}));
+    }
+    write!(out, "  ]")
+}
+
+/// Write the entries, the totals of the models and the models left out of them as JSON
+pub fn write_json<W: io::Write>(entries: &[Entry], out: &mut W) -> io::Result<()> {
+    try!(writeln!(out, "{{"));
+    try!(write_entries("files", entries, out));
+    try!(writeln!(out, ","));
+    try!(write_entries("totals", &get_totals(entries), out));
+    try!(write!(out, ",\n  \"incomplete\": ["));
+    for (i, model) in get_incomplete(entries).iter().enumerate() {
+        if i != 0 {
+            try!(write!(out, ", "));
+        }
+        try!(write_string(model, out));
+    }
+    writeln!(out, "]\n}}")
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::Entry;
+
+    fn make_entries() -> Vec<Entry> {
+        let make = |file: &str, model: &str, size, compressed| Entry {
+            file: file.to_string(),
+            model: model.to_string(),
+            size: size,
+            compressed: compressed,
+            encode_time: 0.5,
+            decode_time: 0.25,
+        };
+        vec![
+            make("a.txt", "exp", 1000000, 250000),
+            make("a.txt", "ybs", 1000000, 240000),
+            make("b\"c", "exp", 3000000, 500000),
+        ]
+    }
+
+    #[test]
+    fn totals() {
+        let entries = make_entries();
+        assert_eq!(entries[0].bits_per_byte(), 2.0);
+        assert_eq!(entries[0].encode_speed(), 2.0);
+        assert_eq!(entries[0].decode_speed(), 4.0);
+        // ybs failed on one of the files
+        let totals = super::get_totals(&entries);
+        assert_eq!(totals.len(), 1);
+        assert_eq!((&totals[0].file[..], &totals[0].model[..]), ("total", "exp"));
+        assert_eq!((totals[0].size, totals[0].compressed), (4000000, 750000));
+        assert_eq!(totals[0].encode_time, 1.0);
+        assert_eq!(super::get_incomplete(&entries), vec!["ybs".to_string()]);
+        assert!(super::get_incomplete(&entries[.. 2]).is_empty());
+        assert_eq!(super::get_totals(&entries[.. 2]).len(), 2);
+    }
+
+    #[test]
+    fn table() {
+        let mut out = Vec::new();
+        super::write_table(&make_entries(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), 2 + 3 + 1 + 2);
+        assert!(text.contains("| a.txt | exp | 1000000 | 250000 | 2.000 | 2.00 | 4.00 |\n"));
+        assert!(text.contains("| total | exp | 4000000 | 750000 | 1.500 | 4.00 | 8.00 |\n"));
+        assert!(text.ends_with("\nNo totals for the models failing on some files: ybs\n"));
+    }
+
+    #[test]
+    fn json() {
+        let mut out = Vec::new();
+        super::write_json(&make_entries(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("{\n  \"files\": [\n") && text.ends_with("  ],\n  \"incomplete\": [\"ybs\"]\n}\n"));
+        assert!(text.contains("{\"file\": \"b\\\"c\", \"model\": \"exp\", \"size\": 3000000,"));
+        assert_eq!(text.matches("\"file\": \"total\"").count(), 1);
+    }
+}
@@ -0,0 +1,262 @@
+/*!
+
+bbb-compatible file format
+
+Reads and writes the files of `etc/bbb/main.cpp`. There is no header:
+the whole file is a single arithmetic coded stream of bytes, predicted by
+the exact port of the bbb model. Each block is coded as its size and the BWT
+origin (4 bytes each, most significant first), followed by the BWT output.
+A zero block size marks the end of the stream.
+
+The BWT sorts the cyclic rotations of a block, so the suffix array is built
+over the block appended to itself. The output matches bbb unless the block
+is periodic, in which case the order of equal rotations may differ,
+but the result is still decoded correctly by either side.
+
+*/
+
+use std::io;
+
+use entropy::paq;
+use model::bbb::Predictor;
+use saca;
+
+
+/// Default maximum block size of bbb
+pub const BLOCK_SIZE: usize = 1<<22;
+
+fn corrupted(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Perform the cyclic BWT of a block, return the output and the origin
+pub fn transform(input: &[u8]) -> (Vec<u8>, usize) {
+    let n = input.len();
+    let doubled: Vec<u8> = input.iter().chain(input.iter()).cloned().collect();
+    let mut sac = saca::Constructor::new(2*n);
+    let mut output = Vec::with_capacity(n);
+    let mut origin = 0;
+    for &suf in sac.compute(&doubled).iter() {
+        let start = suf as usize;
+        if start < n {
+            let pos = (start + n - 1) % n;
+            if pos == 0 {
+                origin = output.len();
+            }
+            output.push(input[pos]);
+        }
+    }
+    (output, origin)
+}
+
+/// Invert the cyclic BWT of a block
+pub fn untransform(input: &[u8], mut origin: usize) -> Vec<u8> {
+    let n = input.len();
+    let mut total = [0usize; 0x100];
+    for &b in input.iter() {
+        total[b as usize] += 1;
+    }
+    let mut offset = 0;
+    for t in total.iter_mut() {
+        let count = *t;
+        *t = offset;
+        offset += count;
+    }
+    let mut next = vec![0usize; n];
+    for (i, &b) in input.iter().enumerate() {
+        next[total[b as usize]] = i;
+        total[b as usize] += 1;
+    }
+    let mut output = Vec::with_capacity(n);
+    for _ in 0 .. n {
+        output.push(input[origin]);
+        origin = next[origin];
+    }
+    output
+}
+
+
+/// bbb file encoder
+pub struct Encoder {
+    block_size: usize,
+    /// Bit prediction model
+    pub model: Predictor,
+}
+
+impl Encoder {
+    /// Create a new encoder with a given maximum block size
+    pub fn new(block_size: usize) -> Encoder {
+        assert!(block_size > 0);
+        Encoder {
+            block_size: block_size,
+            model: Predictor::new(),
+        }
+    }
+
+    fn encode_byte<W: io::Write>(&mut self, byte: u8, eh: &mut paq::Encoder<W>) -> io::Result<()> {
+        for i in (0..8).rev() {
+            let bit = (byte >> i) & 1 != 0;
+            try!(eh.encode(bit, self.model.predict()));
+            self.model.update(bit);
+        }
+        Ok(())
+    }
+
+    fn encode_u32<W: io::Write>(&mut self, value: usize, eh: &mut paq::Encoder<W>) -> io::Result<()> {
+        for i in (0..4).rev() {
+            try!(self.encode_byte((value >> (i*8)) as u8, eh));
+        }
+        Ok(())
+    }
+
+    fn encode_all<W: io::Write>(&mut self, input: &[u8], eh: &mut paq::Encoder<W>) -> io::Result<()> {
+        for block in input.chunks(self.block_size) {
+            let (output, origin) = transform(block);
+            info!("Encoding bbb block of N: {}, origin: {}", block.len(), origin);
+            try!(self.encode_u32(block.len(), eh));
+            try!(self.encode_u32(origin, eh));
+            for &b in output.iter() {
+                try!(self.encode_byte(b, eh));
+            }
+        }
+        // end of stream
+        self.encode_u32(0, eh)
+    }
+}
+
+impl super::Encoder for Encoder {
+    fn encode<W: io::Write>(&mut self, input: &[u8], writer: W) -> (W, io::Result<()>) {
+        let mut eh = paq::Encoder::new(writer);
+        let result = self.encode_all(input, &mut eh);
+        let (writer, err) = eh.finish();
+        (writer, result.and(err))
+    }
+}
+
+
+/// bbb file decoder
+pub struct Decoder {
+    /// Maximum block size, known after the first block
+    pub block_size: usize,
+    /// Bit prediction model
+    pub model: Predictor,
+}
+
+impl Decoder {
+    /// Create a new decoder
+    pub fn new() -> Decoder {
+        Decoder {
+            block_size: 0,
+            model: Predictor::new(),
+        }
+    }
+
+    fn decode_byte<R: io::Read>(&mut self, dh: &mut paq::Decoder<R>) -> io::Result<u8> {
+        let mut byte = 0u8;
+        for _ in 0..8 {
+            let bit = try!(dh.decode(self.model.predict()));
+            self.model.update(bit);
+            byte = (byte << 1) + (bit as u8);
+        }
+        Ok(byte)
+    }
+
+    fn decode_u32<R: io::Read>(&mut self, dh: &mut paq::Decoder<R>) -> io::Result<usize> {
+        let mut value = 0usize;
+        for _ in 0..4 {
+            value = (value << 8) + (try!(self.decode_byte(dh)) as usize);
+        }
+        Ok(value)
+    }
+
+    fn decode_all<R: io::Read, W: io::Write>(&mut self, dh: &mut paq::Decoder<R>, writer: &mut W)
+                  -> io::Result<()> {
+        try!(dh.start());
+        loop {
+            let n = try!(self.decode_u32(dh));
+            if n == 0 {
+                return Ok(())
+            }
+            if self.block_size == 0 {
+                self.block_size = n;
+            }else if n > self.block_size {
+                return Err(corrupted("bbb block is too large"))
+            }
+            let origin = try!(self.decode_u32(dh));
+            if origin >= n {
+                return Err(corrupted("bbb block origin is out of range"))
+            }
+            info!("Decoding bbb block of N: {}, origin: {}", n, origin);
+            let mut input = Vec::with_capacity(n);
+            for _ in 0 .. n {
+                input.push(try!(self.decode_byte(dh)));
+            }
+            try!(writer.write_all(&untransform(&input, origin)));
+        }
+    }
+}
+
+impl super::Decoder for Decoder {
+    fn decode<R: io::Read, W: io::Write>(&mut self, reader: R, mut writer: W) -> (R, W, io::Result<()>) {
+        let mut dh = paq::Decoder::new(reader);
+        let result = self.decode_all(&mut dh, &mut writer);
+        (dh.finish(), writer, result)
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::Path;
+    use block::{Decoder, Encoder};
+
+    fn roundtrip(input: &[u8], block_size: usize) {
+        let (buf, err) = super::Encoder::new(block_size).encode(input, Vec::new());
+        err.unwrap();
+        let (_, output, err) = super::Decoder::new().decode(&buf[..], Vec::new());
+        err.unwrap();
+        assert_eq!(&input[..], &output[..]);
+    }
+
+    #[test]
+    fn transform() {
+        let (output, origin) = super::transform(b"banana");
+        assert_eq!((&output[..], origin), (&b"nnbaaa"[..], 2));
+        assert_eq!(&super::untransform(&output, origin)[..], b"banana");
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(b"", 10);
+        roundtrip(b"a", 10);
+        roundtrip(b"abababab", 10);
+        roundtrip(b"abracadabra, abracadabra, abracadabra", 10);
+        let input: Vec<u8> = (0 .. 5000u32).map(|i| (i*i % 7 + i % 13) as u8).collect();
+        roundtrip(&input, 1000);
+    }
+
+    /// Checks the files produced by `make -C etc/bbb golden`
+    #[test]
+    fn golden() {
+        let dir = Path::new("etc/bbb/golden");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |ext| ext != "bbb") {
+                continue
+            }
+            let mut packed = Vec::new();
+            File::open(&path).unwrap().read_to_end(&mut packed).unwrap();
+            let mut original = Vec::new();
+            File::open(path.with_extension("")).unwrap().read_to_end(&mut original).unwrap();
+            let mut decoder = super::Decoder::new();
+            let (_, output, err) = decoder.decode(&packed[..], Vec::new());
+            err.unwrap();
+            assert!(output == original, "{:?} decodes incorrectly", path);
+            let (repacked, err) = super::Encoder::new(decoder.block_size).encode(&original, Vec::new());
+            err.unwrap();
+            assert!(repacked == packed, "{:?} encodes differently", path);
+        }
+    }
+}
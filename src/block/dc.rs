@@ -5,12 +5,16 @@ DC-based block encoding/decoding routines
 */
 
 use byteorder::WriteBytesExt;
-use std::io;
+use std::{cmp, io};
 
 use compress::bwt;
 use compress::entropy::ari;
-use model::{Distance, DistanceModel};
+use entropy::{self, carry, legacy, rans, table, BitDecoder, BitEncoder, Coder, Part};
+use entropy::meter::Meter;
+use header::LegacyHeader;
+use model::{Distance, DistanceModel, State, dark};
 use saca;
+use super::stats::{Report, Stage};
 
 
 const CTX_0: bwt::dc::Context = bwt::dc::Context {
@@ -23,6 +27,10 @@ pub struct Encoder<M> {
     mtf: bwt::mtf::MTF,
     /// Distance encoding model
     pub model: M,
+    /// Entropy coder backend
+    pub coder: Coder,
+    /// Coding statistics, gathered if present
+    pub stats: Option<Report>,
 }
 
 impl<M: DistanceModel> Encoder<M> {
@@ -33,61 +41,154 @@ impl<M: DistanceModel> Encoder<M> {
             sac     : saca::Constructor::new(n),
             mtf     : bwt::mtf::MTF::new(),
             model   : model,
+            coder   : Coder::Range,
+            stats   : None,
         }
     }
-}
 
-impl<M: DistanceModel> super::Encoder for Encoder<M> {
-    fn encode<W: io::Write>(&mut self, input: &[u8], writer: W) -> (W, io::Result<()>) {
-        let block_size = input.len();
-        assert!(block_size <= self.sac.capacity());
-        // perform BWT and DC
-        let (output, origin) = {
-            let suf = self.sac.compute(input);
-            let mut iter = bwt::TransformIterator::new(input, suf);
-            let out: Vec<u8> = iter.by_ref().collect();
-            (out, iter.get_origin())
-        };
-        let suf = &mut self.sac.reuse()[.. block_size];
-        let dc_iter = bwt::dc::encode(&output, suf, &mut self.mtf);
-        let mut eh = ari::Encoder::new(writer);
-        {   // encode init distances
-            let mut cur_active = true;
-            let mut i = 0usize;
-            while i<0xFF {
-                let base = i;
-                if cur_active {
-                    while i<0xFF && dc_iter.get_init()[i]<block_size {
-                        i += 1;
-                    }
-                    let num = (if base==0 {i} else {i-base-1}) as Distance;
-                    debug!("Init fill num {}", num);
-                    self.model.encode(num, &CTX_0, &mut eh).unwrap();
-                    for (sym,d) in dc_iter.get_init().iter().enumerate().skip(base).take(i-base) {
-                        let ctx = bwt::dc::Context::new(sym as u8, 0, input.len());
-                        self.model.encode(*d as Distance, &ctx, &mut eh).unwrap();
-                        debug!("Init {} for {}", *d, sym);
+    /// Encode a block with a given entropy encoder
+    pub fn encode_block<E: BitEncoder>(&mut self, input: &[u8], eh: &mut E) {
+        let model = &mut self.model;
+        match self.stats {
+            Some(ref mut report) => {
+                let mut meter = Meter::new(eh);
+                transform_stages(input, &mut self.sac, &mut self.mtf, |stage, d, ctx| {
+                    let before = meter.bits;
+                    meter.set_part(Part::Other);
+                    model.encode(d, ctx, &mut meter).unwrap();
+                    let mut parts = meter.bits;
+                    for (b, old) in parts.iter_mut().zip(before.iter()) {
+                        *b -= *old;
                     }
-                    cur_active = false;
-                }else {
-                    while {i+=1; i<0xFF && dc_iter.get_init()[i] == block_size} {}
-                    let num = (i-base-1) as Distance;
-                    debug!("Init empty num {}", num);
-                    self.model.encode(num, &CTX_0, &mut eh).unwrap();
-                    cur_active = true;
+                    report.add(stage, d, ctx, &parts);
+                });
+            },
+            None => {
+                transform(input, &mut self.sac, &mut self.mtf, |d, ctx| {
+                    model.encode(d, ctx, eh).unwrap();
+                });
+            },
+        }
+    }
+}
+
+/// Perform BWT and DC of a block, passing the resulting distances
+/// with their contexts into a given function, in the coding order
+pub fn transform<F>(input: &[u8], sac: &mut saca::Constructor, mtf: &mut bwt::mtf::MTF, mut fun: F)
+    where F: FnMut(Distance, &bwt::dc::Context)
+{
+    transform_stages(input, sac, mtf, |_, d, ctx| fun(d, ctx))
+}
+
+/// Perform BWT and DC of a block like `transform`,
+/// passing the stage of the block along with every distance
+pub fn transform_stages<F>(input: &[u8], sac: &mut saca::Constructor, mtf: &mut bwt::mtf::MTF, mut fun: F)
+    where F: FnMut(Stage, Distance, &bwt::dc::Context)
+{
+    let block_size = input.len();
+    assert!(block_size <= sac.capacity());
+    // perform BWT and DC
+    let (output, origin) = {
+        let suf = sac.compute(input);
+        let mut iter = bwt::TransformIterator::new(input, suf);
+        let out: Vec<u8> = iter.by_ref().collect();
+        (out, iter.get_origin())
+    };
+    let suf = &mut sac.reuse()[.. block_size];
+    let dc_iter = bwt::dc::encode(&output, suf, mtf);
+    {   // init distances
+        let mut cur_active = true;
+        let mut i = 0usize;
+        while i<0x100 {
+            let base = i;
+            if cur_active {
+                while i<0x100 && dc_iter.get_init()[i]<block_size {
+                    i += 1;
+                }
+                let num = (if base==0 {i} else {i-base-1}) as Distance;
+                debug!("Init fill num {}", num);
+                fun(Stage::Init, num, &CTX_0);
+                for (sym,d) in dc_iter.get_init().iter().enumerate().skip(base).take(i-base) {
+                    let ctx = bwt::dc::Context::new(sym as u8, 0, input.len());
+                    fun(Stage::Init, *d as Distance, &ctx);
+                    debug!("Init {} for {}", *d, sym);
                 }
+                cur_active = false;
+            }else {
+                while {i+=1; i<0x100 && dc_iter.get_init()[i] == block_size} {}
+                let num = (i-base-1) as Distance;
+                debug!("Init empty num {}", num);
+                fun(Stage::Init, num, &CTX_0);
+                cur_active = true;
             }
         }
-        // encode distances
-        for (d,ctx) in dc_iter {
-            debug!("Distance {} for {}", d, ctx.symbol);
-            self.model.encode(d, &ctx, &mut eh).unwrap();
+    }
+    // distances
+    for (d,ctx) in dc_iter {
+        debug!("Distance {} for {}", d, ctx.symbol);
+        fun(Stage::Distance, d, &ctx);
+    }
+    // done
+    info!("Origin: {}", origin);
+    fun(Stage::Origin, origin as Distance, &CTX_0);
+}
+
+impl<M: DistanceModel> super::Encoder for Encoder<M> {
+    fn encode<W: io::Write>(&mut self, input: &[u8], writer: W) -> (W, io::Result<()>) {
+        match self.coder {
+            Coder::Range => {
+                let mut eh = ari::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                super::print_stats(&eh);
+                eh.finish()
+            },
+            Coder::Rans => {
+                let mut eh = rans::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                eh.finish()
+            },
+            Coder::Binary => {
+                let mut eh = entropy::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                eh.finish()
+            },
+            Coder::Carry => {
+                let mut eh = carry::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                eh.finish()
+            },
+            Coder::Table => {
+                let mut eh = table::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                eh.finish()
+            },
         }
-        // done
-        info!("Origin: {}", origin);
-        self.model.encode(origin as Distance, &CTX_0, &mut eh).unwrap();
-        super::print_stats(&eh);
-        eh.finish()
+    }
+}
+
+impl<M: DistanceModel + State> super::Reusable for Encoder<M> {
+    fn resize(&mut self, n: usize) {
+        if n != self.sac.capacity() {
+            self.sac = saca::Constructor::new(n);
+        }
+    }
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+
+    fn set_coder(&mut self, coder: Coder) -> bool {
+        self.coder = coder;
+        true
+    }
+
+    fn gather_stats(&mut self) -> bool {
+        self.stats = Some(Report::new());
+        true
+    }
+
+    fn stats(&self) -> Option<&Report> {
+        self.stats.as_ref()
     }
 }
 
@@ -99,6 +200,8 @@ pub struct Decoder<M> {
     mtf         : bwt::mtf::MTF,
     /// Distance decoding model
     pub model   : M,
+    /// Entropy coder backend
+    pub coder   : Coder,
 }
 
 impl<M: DistanceModel> Decoder<M> {
@@ -111,27 +214,27 @@ impl<M: DistanceModel> Decoder<M> {
             suffixes: repeat(0 as saca::Suffix).take(n).collect(),
             mtf     : bwt::mtf::MTF::new(),
             model   : model,
+            coder   : Coder::Range,
         }
     }
-}
 
-impl<M: DistanceModel> super::Decoder for Decoder<M> {
-    fn decode<R: io::Read, W: io::Write>(&mut self, reader: R, mut writer: W) -> (R, W, io::Result<()>) {
+    /// Decode a block with a given entropy decoder
+    pub fn decode_block<D: BitDecoder, W: io::Write>(&mut self, dh: &mut D, writer: &mut W)
+                        -> io::Result<()> {
         let model = &mut self.model;
-        let mut dh = ari::Decoder::new(reader);
         // decode init distances
         let init = {
             let mut init = [self.input.len(); 0x100];
             let mut cur_active = true;
             let mut i = 0usize;
-            while i<0xFF {
+            while i<0x100 {
                 let add  = if i==0 && cur_active {0usize} else {1usize};
-                let num = model.decode(&CTX_0, &mut dh).unwrap() as usize + add;
+                let num = model.decode(&CTX_0, dh).unwrap() as usize + add;
                 debug!("Init num {}", num);
                 if cur_active {
                     for (sym,d) in init.iter_mut().enumerate().skip(i).take(num)    {
                         let ctx = bwt::dc::Context::new(sym as u8, 0, self.input.len());
-                        *d = model.decode(&ctx, &mut dh).unwrap() as usize;
+                        *d = model.decode(&ctx, dh).unwrap() as usize;
                         debug!("Init {} for {}", *d, sym);
                     }
                     cur_active = false;
@@ -144,19 +247,230 @@ impl<M: DistanceModel> super::Decoder for Decoder<M> {
         };
         // decode distances
         bwt::dc::decode(init, &mut self.input, &mut self.mtf, |ctx| {
-            let d = model.decode(&ctx, &mut dh).unwrap();
+            let d = model.decode(&ctx, dh).unwrap();
             debug!("Distance {} for {}", d, ctx.symbol);
             Ok(d as usize)
         }).unwrap();
-        let origin = model.decode(&CTX_0, &mut dh).unwrap() as usize;
+        let origin = model.decode(&CTX_0, dh).unwrap() as usize;
         info!("Origin: {}", origin);
         // undo BWT and write output
         for b in bwt::decode(&self.input, origin, &mut self.suffixes) {
             writer.write_u8(b).unwrap();
         }
-        let result = writer.flush();
-        let (r, err) = dh.finish();
-        (r, writer, result.and(err))
+        writer.flush()
+    }
+}
+
+impl<M: DistanceModel> super::Decoder for Decoder<M> {
+    fn decode<R: io::Read, W: io::Write>(&mut self, reader: R, mut writer: W) -> (R, W, io::Result<()>) {
+        let (r, result) = match self.coder {
+            Coder::Range => {
+                let mut dh = ari::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+            Coder::Rans => {
+                let mut dh = rans::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+            Coder::Binary => {
+                let mut dh = entropy::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+            Coder::Carry => {
+                let mut dh = carry::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+            Coder::Table => {
+                let mut dh = table::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+        };
+        (r, writer, result)
+    }
+}
+
+impl<M: DistanceModel + State> super::Reusable for Decoder<M> {
+    fn resize(&mut self, n: usize) {
+        self.input.resize(n, 0);
+        self.suffixes.resize(n, 0);
+    }
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+
+    fn set_coder(&mut self, coder: Coder) -> bool {
+        self.coder = coder;
+        true
+    }
+}
+
+
+fn legacy_error(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Dark-0.51 decoder, reading the blocks produced by the original C version.
+/// The file is split into blocks of up to `block_size` bytes,
+/// and the model state carries over from one block to the next.
+/// Each block is the DC-coded BWT output of the reversed data,
+/// where distances skip the symbols with closer next runs.
+pub struct LegacyDecoder {
+    size        : usize,
+    input       : Vec<u8>,
+    suffixes    : Vec<saca::Suffix>,
+    /// Distance decoding model
+    pub model   : dark::Legacy,
+}
+
+impl LegacyDecoder {
+    /// Create a new decoder for a file described by a given header
+    pub fn new(header: &LegacyHeader) -> LegacyDecoder {
+        use std::iter::repeat;
+        LegacyDecoder {
+            size    : header.size,
+            input   : repeat(0u8).take(header.block_size).collect(),
+            suffixes: repeat(0 as saca::Suffix).take(header.block_size).collect(),
+            model   : dark::Legacy::new(),
+        }
+    }
+
+    /// Decode the run-ordered DC stream of a block, return the block size
+    fn decode_dc<R: io::Read>(&mut self, dh: &mut legacy::Decoder<R>) -> io::Result<usize> {
+        let model = &mut self.model;
+        let n = try!(model.decode(0, dh)) as usize;
+        if n == 0 {
+            return Ok(0)
+        }
+        if n > self.input.len() {
+            return Err(legacy_error("block is too large"))
+        }
+        // read the number of runs and the first position of each symbol
+        let mut runs = [0usize; 0x100];
+        let mut next = [0usize; 0x100];
+        let mut list = [0u8; 0x100];    // sorted by the next run position
+        let mut num = 0;
+        for sym in 0 .. 0x100 {
+            runs[sym] = try!(model.decode(0, dh)) as usize;
+            if runs[sym] == 0 {
+                continue
+            }
+            let pos = try!(model.decode(sym as u8, dh)) as usize;
+            next[sym] = pos;
+            let mut i = num;
+            while i>0 && pos < next[list[i-1] as usize] {
+                list[i] = list[i-1];
+                i -= 1;
+            }
+            list[i] = sym as u8;
+            num += 1;
+        }
+        let block = &mut self.input[..n];
+        if num == 0 {
+            return Err(legacy_error("block has no symbols"))
+        }
+        if num == 1 {
+            for b in block.iter_mut() {
+                *b = list[0];
+            }
+            return Ok(n)
+        }
+        // read all the other runs
+        let mut i = 0;
+        while i < n {
+            let sym = list[0];
+            let end = next[list[1] as usize];
+            while i < cmp::min(end, n) {
+                block[i] = sym;
+                i += 1;
+            }
+            if runs[sym as usize] == 0 {
+                return Err(legacy_error("unexpected run"))
+            }
+            runs[sym as usize] -= 1;
+            let pos = if runs[sym as usize] == 0 {n} else {
+                end + try!(model.decode(sym, dh)) as usize
+            };
+            // each symbol with a closer next run takes one more position
+            let mut j = 1;
+            while j < num && pos + j > next[list[j] as usize] {
+                list[j-1] = list[j];
+                j += 1;
+            }
+            list[j-1] = sym;
+            next[sym as usize] = pos + j - 1;
+        }
+        Ok(n)
+    }
+
+    /// Decode a single block into a given writer, return the block size
+    pub fn decode_block<R: io::Read, W: io::Write>(&mut self, dh: &mut legacy::Decoder<R>,
+                        writer: &mut W) -> io::Result<usize> {
+        let n = try!(self.decode_dc(dh));
+        if n == 0 {
+            return Ok(0)
+        }
+        let origin = try!(self.model.decode(0, dh)) as usize;
+        info!("Origin: {}", origin);
+        if origin >= n {
+            return Err(legacy_error("origin is out of the block"))
+        }
+        // undo BWT, using the original symbol order
+        let input = &self.input[..n];
+        let suf = &mut self.suffixes[..n];
+        let mut offsets = [0usize; 0x100];
+        for &b in input.iter() {
+            offsets[b as usize] += 1;
+        }
+        let mut total = 0;
+        for off in offsets.iter_mut() {
+            let count = *off;
+            *off = total;
+            total += count;
+        }
+        for i in Some(origin).into_iter().chain(0 .. origin).chain(origin+1 .. n) {
+            let off = &mut offsets[input[i] as usize];
+            suf[*off] = i as saca::Suffix;
+            *off += 1;
+        }
+        let mut pos = origin;
+        for _ in 0 .. n {
+            pos = suf[pos] as usize;
+            try!(writer.write_u8(input[pos]));
+        }
+        Ok(n)
+    }
+
+    fn decode_all<R: io::Read, W: io::Write>(&mut self, dh: &mut legacy::Decoder<R>,
+                  writer: &mut W) -> io::Result<()> {
+        try!(dh.start());
+        let mut left = self.size;
+        while left != 0 {
+            let n = try!(self.decode_block(dh, writer));
+            if n == 0 || n > left {
+                return Err(legacy_error("block size mismatch"))
+            }
+            left -= n;
+        }
+        writer.flush()
+    }
+}
+
+impl super::Decoder for LegacyDecoder {
+    fn decode<R: io::Read, W: io::Write>(&mut self, reader: R, mut writer: W) -> (R, W, io::Result<()>) {
+        let mut dh = legacy::Decoder::new(reader);
+        let result = self.decode_all(&mut dh, &mut writer);
+        (dh.finish(), writer, result)
     }
 }
 
@@ -164,21 +478,31 @@ impl<M: DistanceModel> super::Decoder for Decoder<M> {
 #[cfg(test)]
 pub mod test {
     use std::io;
+    use std::path::Path;
     #[cfg(feature="unstable")]
     use std::iter::repeat;
     #[cfg(feature="unstable")]
     use test::Bencher;
+    #[cfg(feature="unstable")]
+    use model::Model;
     use block::{Encoder, Decoder};
+    use entropy::Coder;
     use model::{DistanceModel, exp, ybs};
 
     const TEXT: &'static [u8] = include_bytes!("../../LICENSE");
 
     fn roundtrip<M: DistanceModel>(model: M, bytes: &[u8]) {
+        roundtrip_with(model, Coder::Range, bytes)
+    }
+
+    fn roundtrip_with<M: DistanceModel>(model: M, coder: Coder, bytes: &[u8]) {
         let mut enc = super::Encoder::new(bytes.len(), model);
+        enc.coder = coder;
         let (writer, err) = enc.encode(bytes, Vec::new());
         err.unwrap();
         let reader = io::BufReader::new(io::Cursor::new(&writer[..]));
         let mut dec = super::Decoder::new(bytes.len(), enc.model);
+        dec.coder = coder;
         let (_, output, err) = dec.decode(reader, Vec::new());
         err.unwrap();
         assert_eq!(&bytes[..], &output[..]);
@@ -191,6 +515,71 @@ pub mod test {
         roundtrip(ybs::Model::new(), TEXT);
     }
 
+    #[test]
+    fn full_alphabet() {
+        let bytes: Vec<u8> = (0 .. 0x200).map(|i| (i * 7) as u8).collect();
+        roundtrip(exp::Model::new(), &bytes);
+        roundtrip(exp::Model::new(), b"\xFFab\xFF");
+        roundtrip(ybs::Model::new(), b"\x00\xFF\x00");
+    }
+
+    #[test]
+    fn coders() {
+        for &coder in [Coder::Binary, Coder::Carry, Coder::Rans, Coder::Table].iter() {
+            roundtrip_with(exp::Model::new(), coder, TEXT);
+        }
+    }
+
+    #[test]
+    fn stats() {
+        use block::Reusable;
+        use block::stats::Stage;
+        use entropy::Part;
+        let mut enc = super::Encoder::new(TEXT.len(), ybs::Model::new());
+        assert!(enc.gather_stats());
+        let (writer, err) = enc.encode(TEXT, Vec::new());
+        err.unwrap();
+        let report = enc.stats().unwrap();
+        let distances = report.stages[Stage::Distance as usize].count;
+        assert_eq!(report.stages[Stage::Origin as usize].count, 1);
+        assert_eq!(report.symbols.iter().fold(0, |sum, c| sum + c.count), distances);
+        assert_eq!(report.exponents.iter().fold(0, |sum, c| sum + c.count), distances);
+        assert_eq!(report.parts[Part::Other as usize], 0.0);
+        // the estimate is within a few bytes from the output
+        let bytes = report.total() / 8.0;
+        assert!((bytes - writer.len() as f64).abs() < 16.0,
+            "estimated {} vs coded {}", bytes, writer.len());
+    }
+
+    /// Decode the archives produced by the original C version,
+    /// kept in the tree and regenerated by `make -C etc/dark-c golden`
+    #[test]
+    fn legacy_golden() {
+        use std::fs;
+        use std::io::Read;
+        use header::LegacyHeader;
+        let dir = Path::new("etc/dark-c/golden");
+        let mut count = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |ext| ext != "dark") {
+                continue
+            }
+            let mut reader = io::BufReader::new(fs::File::open(&path).unwrap());
+            let header = LegacyHeader::read(&mut reader).unwrap();
+            let mut original = Vec::new();
+            fs::File::open(dir.join(&header.name)).unwrap()
+                .read_to_end(&mut original).unwrap();
+            assert_eq!(header.size, original.len());
+            let mut dec = super::LegacyDecoder::new(&header);
+            let (_, output, err) = dec.decode(reader, Vec::new());
+            err.unwrap();
+            assert!(&original[..] == &output[..], "Mismatch on {:?}", path);
+            count += 1;
+        }
+        assert!(count > 0, "No golden files found, run `make -C etc/dark-c golden`");
+    }
+
     #[cfg(feature="unstable")]
     #[bench]
     fn encode_speed(bh: &mut Bencher) {
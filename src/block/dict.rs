@@ -0,0 +1,245 @@
+/*!
+
+Block coding in the context of a dictionary
+
+The block is not joined to the dictionary. The dictionary suffixes are
+sorted once and for all by `dict::Dictionary`, and the block ones are kept
+apart, sorted by their first `MAX_ORDER` symbols, as the coding goes.
+The symbol preceding a block suffix is coded with the symbols preceding
+its closest neighbours in both orders, up to `WINDOW` on each side, as
+the context, so only the part of the BWT belonging to the block is
+transmitted, and the block gets the context of its own repeats as well as
+of the dictionary.
+
+The dictionary is only used by this coder, with the `dict` model:
+the BWT-DC coders don't take it.
+
+The symbols are coded from the end of the block to the start, so that
+the decoder always knows the suffix following the next symbol, and the
+suffixes after it are already in place.
+
+*/
+
+use std::collections::BTreeSet;
+use std::{cmp, io};
+use compress::entropy::ari;
+
+use dict::{common_prefix, Dictionary};
+use model::{Model, State, Symbol};
+use model::dict::Context;
+
+
+/// Maximum length of the block suffix prefix compared with the others
+const MAX_ORDER : usize = 16;
+/// Number of the sorted suffixes looked at on each side of the place
+const WINDOW    : usize = 3;
+
+/// Sort key of a block suffix: its first symbols, zero padded, and their number
+type Key = ([u8; MAX_ORDER], usize);
+
+fn make_key(suffix: &[u8]) -> Key {
+    let mut key = [0; MAX_ORDER];
+    let length = cmp::min(suffix.len(), MAX_ORDER);
+    key[.. length].copy_from_slice(&suffix[.. length]);
+    (key, length)
+}
+
+/// Block suffixes placed so far, sorted by their keys, then by position
+pub struct Suffixes {
+    sorted  : BTreeSet<(Key, usize)>,
+}
+
+impl Suffixes {
+    /// Create an empty set
+    pub fn new() -> Suffixes {
+        Suffixes {
+            sorted  : BTreeSet::new(),
+        }
+    }
+
+    /// Remove all the suffixes
+    pub fn clear(&mut self) {
+        self.sorted.clear();
+    }
+
+    /// Place the block suffix starting at a given position
+    pub fn insert(&mut self, block: &[u8], pos: usize) {
+        self.sorted.insert((make_key(&block[pos ..]), pos));
+    }
+}
+
+/// Collect the candidate symbols for the one preceding a block suffix,
+/// out of the dictionary and of the block suffixes placed before
+pub fn neighbours(dictionary: &Dictionary, suffixes: &Suffixes, block: &[u8], pos: usize) -> Context {
+    let key = &block[pos .. cmp::min(block.len(), pos + MAX_ORDER)];
+    // longer matches first, then the block ones, then the closer ones
+    let mut rows: Vec<(usize, bool, usize, Symbol)> = Vec::with_capacity(4*WINDOW);
+    let rank = dictionary.locate(key);
+    let lo = if rank > WINDOW {rank - WINDOW} else {0};
+    let hi = cmp::min(rank + WINDOW, dictionary.len());
+    rows.extend((lo .. hi).map(|r| {
+        let distance = if r >= rank {2*(r-rank)} else {2*(rank-r)-1};
+        (MAX_ORDER - common_prefix(key, dictionary.suffix(r)), true, distance, dictionary.preceding(r))
+    }));
+    let place = (make_key(key), 0);
+    let mut add_block = |k: usize, &(ref other, p): &(Key, usize)| {
+        let miss = MAX_ORDER - common_prefix(key, &other.0[.. other.1]);
+        rows.push((miss, false, k, block[p-1]));
+    };
+    for (k, entry) in suffixes.sorted.range(place ..).take(WINDOW).enumerate() {
+        add_block(2*k, entry);
+    }
+    for (k, entry) in suffixes.sorted.range(.. place).rev().take(WINDOW).enumerate() {
+        add_block(2*k+1, entry);
+    }
+    rows.sort();
+    let mut ctx = Context::new();
+    for &(miss, _, _, sym) in rows.iter() {
+        ctx.add(sym, MAX_ORDER - miss);
+    }
+    ctx
+}
+
+
+/// Dictionary context block encoder
+pub struct Encoder<M> {
+    dictionary  : Dictionary,
+    suffixes    : Suffixes,
+    /// Symbol encoding model
+    pub model   : M,
+}
+
+impl<M: Model<Symbol, Context>> Encoder<M> {
+    /// Create a new Encoder instance
+    pub fn new(dictionary: Dictionary, mut model: M) -> Encoder<M> {
+        model.reset();
+        Encoder {
+            dictionary  : dictionary,
+            suffixes    : Suffixes::new(),
+            model       : model,
+        }
+    }
+}
+
+impl<M: Model<Symbol, Context>> super::Encoder for Encoder<M> {
+    fn encode<W: io::Write>(&mut self, input: &[u8], writer: W) -> (W, io::Result<()>) {
+        let mut eh = ari::Encoder::new(writer);
+        self.suffixes.clear();
+        for i in (0 .. input.len()).rev() {
+            let ctx = neighbours(&self.dictionary, &self.suffixes, input, i+1);
+            self.model.encode(input[i], &ctx, &mut eh).unwrap();
+            self.suffixes.insert(input, i+1);
+        }
+        super::print_stats(&eh);
+        eh.finish()
+    }
+}
+
+impl<M: Model<Symbol, Context> + State> super::Reusable for Encoder<M> {
+    fn resize(&mut self, _n: usize) {}
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+}
+
+/// Dictionary context block decoder
+pub struct Decoder<M> {
+    dictionary  : Dictionary,
+    suffixes    : Suffixes,
+    output      : Vec<u8>,
+    /// Symbol decoding model
+    pub model   : M,
+}
+
+impl<M: Model<Symbol, Context>> Decoder<M> {
+    /// Create a new Decoder instance
+    pub fn new(n: usize, dictionary: Dictionary, mut model: M) -> Decoder<M> {
+        model.reset();
+        Decoder {
+            dictionary  : dictionary,
+            suffixes    : Suffixes::new(),
+            output      : vec![0; n],
+            model       : model,
+        }
+    }
+}
+
+impl<M: Model<Symbol, Context>> super::Decoder for Decoder<M> {
+    fn decode<R: io::Read, W: io::Write>(&mut self, reader: R, mut writer: W) -> (R, W, io::Result<()>) {
+        let mut dh = ari::Decoder::new(reader);
+        self.suffixes.clear();
+        for i in (0 .. self.output.len()).rev() {
+            let ctx = neighbours(&self.dictionary, &self.suffixes, &self.output, i+1);
+            self.output[i] = self.model.decode(&ctx, &mut dh).unwrap();
+            self.suffixes.insert(&self.output, i+1);
+        }
+        let result = writer.write_all(&self.output).and_then(|_| writer.flush());
+        let (r, err) = dh.finish();
+        (r, writer, result.and(err))
+    }
+}
+
+impl<M: Model<Symbol, Context> + State> super::Reusable for Decoder<M> {
+    fn resize(&mut self, n: usize) {
+        self.output.resize(n, 0);
+    }
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    use block::{Encoder, Decoder};
+    use dict::Dictionary;
+    use model::dict::Model;
+
+    const TEXT: &'static [u8] = include_bytes!("../../LICENSE");
+
+    fn roundtrip(dictionary: &[u8], bytes: &[u8]) -> usize {
+        let dictionary = Dictionary::new(dictionary.to_vec());
+        let mut enc = super::Encoder::new(dictionary.clone(), Model::new());
+        let (writer, err) = enc.encode(bytes, Vec::new());
+        err.unwrap();
+        let reader = io::BufReader::new(io::Cursor::new(&writer[..]));
+        let mut dec = super::Decoder::new(bytes.len(), dictionary, Model::new());
+        let (_, output, err) = dec.decode(reader, Vec::new());
+        err.unwrap();
+        assert_eq!(&bytes[..], &output[..]);
+        writer.len()
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(b"", b"abracababra");
+        roundtrip(b"abracadabra", b"");
+        roundtrip(b"cadabra", b"abracababra");
+        roundtrip(&TEXT[.. 500], &TEXT[500 ..]);
+    }
+
+    #[test]
+    fn context() {
+        // a similar record, not a copy
+        let mut record = TEXT[300 .. 600].to_vec();
+        for i in 0 .. 10 {
+            record[i*30] = b'#';
+        }
+        let with = roundtrip(TEXT, &record);
+        let without = roundtrip(b"", &record);
+        assert!(with * 2 < without, "{} with the dictionary vs {} without", with, without);
+    }
+
+    #[test]
+    fn repeats() {
+        // the dictionary has nothing to do with the block, which repeats itself
+        let period: Vec<u8> = TEXT[.. 200].iter().rev().cloned().collect();
+        let block: Vec<u8> = (0 .. 20).flat_map(|_| period.iter().cloned()).collect();
+        let size = roundtrip(b"0123456789 {}[]", &block);
+        assert!(size < 2 * period.len(), "{} bytes for {} repeats of {}",
+            size, 20, period.len());
+    }
+}
@@ -5,20 +5,56 @@ Block encoding/decoding
 
 use std::io;
 use compress::entropy::ari;
+use entropy::{BitDecoder, BitEncoder, Coder};
+use model::State;
 
+/// bbb-compatible file format
+pub mod bbb;
 /// DC based
 pub mod dc;
+/// Dictionary context based
+pub mod dict;
+/// Multi-block stream container
+pub mod multi;
+/// QLFC based
+pub mod qlfc;
+/// Adaptive list update (MTF, WFC) based
+pub mod rank;
 /// Raw
 pub mod raw;
+/// Coding statistics
+pub mod stats;
 
 
-#[cfg(feature="tune")]
+/// Encode the BWT origin as 32 equiprobable bits
+pub fn encode_origin<E: BitEncoder>(origin: usize, eh: &mut E) -> io::Result<()> {
+    let flat = ari::apm::Bit::new_equal();
+    for i in (0..32).rev() {
+        try!(eh.encode_bit((origin>>i) & 1 != 0, &flat));
+    }
+    Ok(())
+}
+
+/// Decode the BWT origin from 32 equiprobable bits
+pub fn decode_origin<D: BitDecoder>(dh: &mut D) -> io::Result<usize> {
+    let flat = ari::apm::Bit::new_equal();
+    let mut origin = 0usize;
+    for _ in 0..32 {
+        let bit = try!(dh.decode_bit(&flat));
+        origin = (origin<<1) + (bit as usize);
+    }
+    Ok(origin)
+}
+
+
+// requires `compress` to be built with `--cfg tune`
+#[cfg(tune)]
 fn print_stats<W: io::Write>(eh: &ari::Encoder<W>) {
     let (b0, b1) = eh.get_bytes_lost();
     info!("Bytes lost on threshold cut: {}, on divisions: {}", b0, b1);
 }
 
-#[cfg(not(feature="tune"))]
+#[cfg(not(tune))]
 fn print_stats<W: io::Write>(_eh: &ari::Encoder<W>) {
     //empty
 }
@@ -34,3 +70,23 @@ pub trait Decoder {
 	/// Decode a block by reading from a given Reader into some Writer
 	fn decode<R: io::Read, W: io::Write>(&mut self, R, W) -> (R, W, io::Result<()>);
 }
+
+/// Block coder, which can be reused for the following blocks of a stream
+pub trait Reusable {
+	/// Prepare for a block of a given size, keeping the model statistics
+	fn resize(&mut self, n: usize);
+	/// Model, carrying the statistics from one block to the next
+	fn state(&mut self) -> &mut State;
+	/// Select the entropy coder backend, return false if it's not supported
+	fn set_coder(&mut self, coder: Coder) -> bool {
+		coder == Coder::Range
+	}
+	/// Start gathering the coding statistics, return false if it's not supported
+	fn gather_stats(&mut self) -> bool {
+		false
+	}
+	/// Statistics gathered so far
+	fn stats(&self) -> Option<&stats::Report> {
+		None
+	}
+}
@@ -0,0 +1,163 @@
+/*!
+
+Multi-block stream container
+
+The input is split into blocks of a fixed maximum size, which are coded
+one after another by the same block coder. Each coded block is preceded by
+its length in bytes (32 bits, little endian). The original block sizes follow
+from the total size and the block size, both stored in the header.
+
+The model statistics either continue from the previous block, or are restored
+to the initial state (the default or a primed one) for every block,
+keeping the blocks independent.
+
+*/
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{cmp, io};
+use std::io::Read;
+
+use super::{Decoder, Encoder, Reusable};
+
+
+fn save_initial<C: Reusable>(coder: &mut C, continued: bool) -> io::Result<Option<Vec<u8>>> {
+    if continued {
+        return Ok(None)
+    }
+    let mut state = Vec::new();
+    try!(coder.state().save(&mut state));
+    Ok(Some(state))
+}
+
+fn prepare<C: Reusable>(coder: &mut C, index: usize, n: usize, initial: &Option<Vec<u8>>) -> io::Result<()> {
+    if index > 0 {
+        if let Some(ref state) = *initial {
+            try!(coder.state().load(&mut &state[..]));
+        }
+    }
+    coder.resize(n);
+    Ok(())
+}
+
+fn encode_blocks<E, W>(coder: &mut E, input: &[u8], block_size: usize, continued: bool,
+                 writer: &mut W) -> io::Result<()>
+    where E: Encoder + Reusable, W: io::Write
+{
+    assert!(block_size > 0);
+    let initial = try!(save_initial(coder, continued));
+    for (i, block) in input.chunks(block_size).enumerate() {
+        try!(prepare(coder, i, block.len(), &initial));
+        let (buf, err) = coder.encode(block, Vec::new());
+        try!(err);
+        info!("Block {} of N: {} is coded into {} bytes", i, block.len(), buf.len());
+        try!(writer.write_u32::<LittleEndian>(buf.len() as u32));
+        try!(writer.write_all(&buf));
+    }
+    Ok(())
+}
+
+fn decode_blocks<D, R, W>(coder: &mut D, size: usize, block_size: usize, continued: bool,
+                 reader: &mut R, writer: &mut W) -> io::Result<()>
+    where D: Decoder + Reusable, R: io::Read, W: io::Write
+{
+    assert!(block_size > 0);
+    let initial = try!(save_initial(coder, continued));
+    let mut offset = 0;
+    let mut i = 0;
+    while offset < size {
+        let n = cmp::min(block_size, size - offset);
+        try!(prepare(coder, i, n, &initial));
+        let length = try!(reader.read_u32::<LittleEndian>()) as usize;
+        let mut buf = Vec::with_capacity(length);
+        try!(reader.by_ref().take(length as u64).read_to_end(&mut buf));
+        if buf.len() != length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated block"))
+        }
+        info!("Block {} of N: {} is decoded from {} bytes", i, n, length);
+        let (_, _, err) = coder.decode(&buf[..], &mut *writer);
+        try!(err);
+        offset += n;
+        i += 1;
+    }
+    writer.flush()
+}
+
+/// Encode an input as a sequence of blocks of up to a given size
+pub fn encode<E, W>(coder: &mut E, input: &[u8], block_size: usize, continued: bool,
+              mut writer: W) -> (W, io::Result<()>)
+    where E: Encoder + Reusable, W: io::Write
+{
+    let result = encode_blocks(coder, input, block_size, continued, &mut writer);
+    (writer, result)
+}
+
+/// Decode a sequence of blocks of up to a given size, making a given total size
+pub fn decode<D, R, W>(coder: &mut D, size: usize, block_size: usize, continued: bool,
+              mut reader: R, mut writer: W) -> (R, W, io::Result<()>)
+    where D: Decoder + Reusable, R: io::Read, W: io::Write
+{
+    let result = decode_blocks(coder, size, block_size, continued, &mut reader, &mut writer);
+    (reader, writer, result)
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use block::{dc, qlfc, raw};
+    use block::{Decoder, Encoder, Reusable};
+    use model::{bbb, exp, qlfc as qlfc_model, State};
+
+    const TEXT: &'static [u8] = include_bytes!("../../LICENSE");
+
+    fn roundtrip<E, D>(mut encoder: E, mut decoder: D, block_size: usize, continued: bool) -> usize
+        where E: Encoder + Reusable, D: Decoder + Reusable
+    {
+        let (buf, err) = super::encode(&mut encoder, TEXT, block_size, continued, Vec::new());
+        err.unwrap();
+        let (_, output, err) = super::decode(&mut decoder, TEXT.len(), block_size, continued, &buf[..], Vec::new());
+        err.unwrap();
+        assert_eq!(&output[..], TEXT);
+        buf.len()
+    }
+
+    #[test]
+    fn roundtrips() {
+        let n = 1000;
+        roundtrip(dc::Encoder::new(n, exp::Model::new()), dc::Decoder::new(n, exp::Model::new()), n, false);
+        roundtrip(dc::Encoder::new(n, exp::Model::new()), dc::Decoder::new(n, exp::Model::new()), n, true);
+        roundtrip(qlfc::Encoder::new(n, qlfc_model::Model::new()), qlfc::Decoder::new(n, qlfc_model::Model::new()), n, true);
+        roundtrip(raw::Encoder::new(n, bbb::Model::new()), raw::Decoder::new(n, bbb::Model::new()), n, true);
+    }
+
+    #[test]
+    fn single_byte_tail() {
+        let n = TEXT.len() - 1;
+        roundtrip(dc::Encoder::new(n, exp::Model::new()), dc::Decoder::new(n, exp::Model::new()), n, false);
+        roundtrip(qlfc::Encoder::new(n, qlfc_model::Model::new()), qlfc::Decoder::new(n, qlfc_model::Model::new()), n, true);
+        roundtrip(raw::Encoder::new(n, bbb::Model::new()), raw::Decoder::new(n, bbb::Model::new()), n, false);
+    }
+
+    #[test]
+    fn continued() {
+        let n = 1000;
+        let independent = roundtrip(dc::Encoder::new(n, exp::Model::new()), dc::Decoder::new(n, exp::Model::new()), n, false);
+        let continued = roundtrip(dc::Encoder::new(n, exp::Model::new()), dc::Decoder::new(n, exp::Model::new()), n, true);
+        assert!(continued < independent, "continued {} vs independent {}", continued, independent);
+    }
+
+    #[test]
+    fn primed() {
+        let n = 1000;
+        let mut teacher = dc::Encoder::new(TEXT.len(), exp::Model::new());
+        teacher.encode(TEXT, Vec::new()).1.unwrap();
+        let mut state = Vec::new();
+        teacher.model.save(&mut state).unwrap();
+        let mut encoder = dc::Encoder::new(n, exp::Model::new());
+        let mut decoder = dc::Decoder::new(n, exp::Model::new());
+        encoder.model.load(&mut &state[..]).unwrap();
+        decoder.model.load(&mut &state[..]).unwrap();
+        let primed = roundtrip(encoder, decoder, n, false);
+        let plain = roundtrip(dc::Encoder::new(n, exp::Model::new()), dc::Decoder::new(n, exp::Model::new()), n, false);
+        assert!(primed < plain, "primed {} vs plain {}", primed, plain);
+    }
+}
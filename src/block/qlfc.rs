@@ -0,0 +1,186 @@
+/*!
+
+QLFC block encoding/decoding routines
+
+The BWT output is split into runs of identical symbols. Each run is coded
+as the MTF rank of its symbol, followed by the run length.
+
+*/
+
+use byteorder::WriteBytesExt;
+use std::io;
+
+use compress::bwt;
+use compress::entropy::ari;
+use model::{Length, Model, Rank, RankContext, RunContext, RunModel, State};
+use saca;
+
+
+fn reset_model<M: RunModel>(model: &mut M) {
+    Model::<Rank, RankContext>::reset(model);
+    Model::<Length, RunContext>::reset(model);
+}
+
+
+/// QLFC block encoder
+pub struct Encoder<M> {
+    sac: saca::Constructor,
+    mtf: bwt::mtf::MTF,
+    /// Run coding model
+    pub model: M,
+}
+
+impl<M: RunModel> Encoder<M> {
+    /// Create a new Encoder instance
+    pub fn new(n: usize, mut model: M) -> Encoder<M> {
+        reset_model(&mut model);
+        Encoder {
+            sac     : saca::Constructor::new(n),
+            mtf     : bwt::mtf::MTF::new(),
+            model   : model,
+        }
+    }
+}
+
+impl<M: RunModel> super::Encoder for Encoder<M> {
+    fn encode<W: io::Write>(&mut self, input: &[u8], writer: W) -> (W, io::Result<()>) {
+        let block_size = input.len();
+        assert!(block_size <= self.sac.capacity());
+        // perform BWT
+        let (output, origin) = {
+            let suf = self.sac.compute(input);
+            let mut iter = bwt::TransformIterator::new(input, suf);
+            let out: Vec<u8> = iter.by_ref().collect();
+            (out, iter.get_origin())
+        };
+        let mut eh = ari::Encoder::new(writer);
+        info!("Origin: {}", origin);
+        super::encode_origin(origin, &mut eh).unwrap();
+        // encode runs
+        self.mtf.reset_alphabetical();
+        let mut rctx = RankContext { last_rank: 0, last_length: 0 };
+        let mut i = 0;
+        while i < block_size {
+            let symbol = output[i];
+            let base = i;
+            while i < block_size && output[i] == symbol {
+                i += 1;
+            }
+            let length = (i - base) as Length;
+            let rank = self.mtf.encode(symbol);
+            debug!("Run of {} with rank {} and length {}", symbol, rank, length);
+            Model::<Rank, RankContext>::encode(&mut self.model, rank, &rctx, &mut eh).unwrap();
+            let lctx = RunContext { symbol: symbol, rank: rank };
+            Model::<Length, RunContext>::encode(&mut self.model, length, &lctx, &mut eh).unwrap();
+            rctx = RankContext { last_rank: rank, last_length: length };
+        }
+        // done
+        super::print_stats(&eh);
+        eh.finish()
+    }
+}
+
+impl<M: RunModel + State> super::Reusable for Encoder<M> {
+    fn resize(&mut self, n: usize) {
+        if n != self.sac.capacity() {
+            self.sac = saca::Constructor::new(n);
+        }
+    }
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+}
+
+
+/// QLFC block decoder
+pub struct Decoder<M> {
+    input       : Vec<u8>,
+    suffixes    : Vec<saca::Suffix>,
+    mtf         : bwt::mtf::MTF,
+    /// Run decoding model
+    pub model   : M,
+}
+
+impl<M: RunModel> Decoder<M> {
+    /// Create a new Decoder instance
+    pub fn new(n: usize, mut model: M) -> Decoder<M> {
+        use std::iter::repeat;
+        reset_model(&mut model);
+        Decoder {
+            input   : repeat(0u8).take(n).collect(),
+            suffixes: repeat(0 as saca::Suffix).take(n).collect(),
+            mtf     : bwt::mtf::MTF::new(),
+            model   : model,
+        }
+    }
+}
+
+impl<M: RunModel> super::Decoder for Decoder<M> {
+    fn decode<R: io::Read, W: io::Write>(&mut self, reader: R, mut writer: W) -> (R, W, io::Result<()>) {
+        let mut dh = ari::Decoder::new(reader);
+        let origin = super::decode_origin(&mut dh).unwrap();
+        info!("Origin: {}", origin);
+        // decode runs
+        self.mtf.reset_alphabetical();
+        let mut rctx = RankContext { last_rank: 0, last_length: 0 };
+        let mut i = 0;
+        while i < self.input.len() {
+            let rank = Model::<Rank, RankContext>::decode(&mut self.model, &rctx, &mut dh).unwrap();
+            let symbol = self.mtf.decode(rank);
+            let lctx = RunContext { symbol: symbol, rank: rank };
+            let length = Model::<Length, RunContext>::decode(&mut self.model, &lctx, &mut dh).unwrap();
+            debug!("Run of {} with rank {} and length {}", symbol, rank, length);
+            for sym in self.input[i .. i + length as usize].iter_mut() {
+                *sym = symbol;
+            }
+            i += length as usize;
+            rctx = RankContext { last_rank: rank, last_length: length };
+        }
+        // undo BWT and write output
+        for b in bwt::decode(&self.input, origin, &mut self.suffixes) {
+            writer.write_u8(b).unwrap();
+        }
+        let result = writer.flush();
+        let (r, err) = dh.finish();
+        (r, writer, result.and(err))
+    }
+}
+
+impl<M: RunModel + State> super::Reusable for Decoder<M> {
+    fn resize(&mut self, n: usize) {
+        self.input.resize(n, 0);
+        self.suffixes.resize(n, 0);
+    }
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    use block::{Encoder, Decoder};
+    use model::{RunModel, qlfc};
+
+    const TEXT: &'static [u8] = include_bytes!("../../LICENSE");
+
+    fn roundtrip<M: RunModel>(model: M, bytes: &[u8]) {
+        let mut enc = super::Encoder::new(bytes.len(), model);
+        let (writer, err) = enc.encode(bytes, Vec::new());
+        err.unwrap();
+        let reader = io::BufReader::new(io::Cursor::new(&writer[..]));
+        let mut dec = super::Decoder::new(bytes.len(), enc.model);
+        let (_, output, err) = dec.decode(reader, Vec::new());
+        err.unwrap();
+        assert_eq!(&bytes[..], &output[..]);
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(qlfc::Model::new(), b"abracababra");
+        roundtrip(qlfc::Model::new(), TEXT);
+    }
+}
@@ -0,0 +1,351 @@
+/*!
+
+Adaptive list update block encoding/decoding routines
+
+Every BWT output symbol is replaced by its rank in an adaptively ordered list
+of symbols. The way the list is updated is defined by a `Policy`:
+plain Move-To-Front, MTF-1, or Weighted Frequency Count (WFC).
+
+*/
+
+use byteorder::WriteBytesExt;
+use std::io;
+
+use compress::bwt;
+use compress::entropy::ari;
+use model::{Rank, RankContext, RankModel, State, Symbol};
+use saca;
+
+
+const TOTAL_SYMBOLS: usize = 0x100;
+
+/// Adaptive list update policy, mapping symbols to their ranks
+pub trait Policy {
+    /// Reset the list to the alphabetical order
+    fn reset(&mut self);
+    /// Return the rank of a symbol, updating the list
+    fn encode(&mut self, Symbol) -> Rank;
+    /// Return the symbol of a rank, updating the list
+    fn decode(&mut self, Rank) -> Symbol;
+}
+
+fn find_rank(symbols: &[Symbol; TOTAL_SYMBOLS], sym: Symbol) -> Rank {
+    symbols.iter().position(|&s| s == sym).unwrap() as Rank
+}
+
+/// Move the symbol from one position of the list to a lower one
+fn move_up(symbols: &mut [Symbol; TOTAL_SYMBOLS], from: usize, to: usize) {
+    let sym = symbols[from];
+    for i in (to .. from).rev() {
+        symbols[i+1] = symbols[i];
+    }
+    symbols[to] = sym;
+}
+
+
+/// Move-To-Front policy
+pub struct Mtf(bwt::mtf::MTF);
+
+impl Mtf {
+    /// Create a new MTF policy
+    pub fn new() -> Mtf {
+        Mtf(bwt::mtf::MTF::new())
+    }
+}
+
+impl Policy for Mtf {
+    fn reset(&mut self) {
+        self.0.reset_alphabetical();
+    }
+
+    fn encode(&mut self, sym: Symbol) -> Rank {
+        self.0.encode(sym)
+    }
+
+    fn decode(&mut self, rank: Rank) -> Symbol {
+        self.0.decode(rank)
+    }
+}
+
+
+/// MTF-1 policy: the symbol of rank 1 goes to the front,
+/// symbols of higher ranks only go to the second position
+pub struct Mtf1 {
+    symbols: [Symbol; TOTAL_SYMBOLS],
+}
+
+impl Mtf1 {
+    /// Create a new MTF-1 policy
+    pub fn new() -> Mtf1 {
+        Mtf1 {
+            symbols: [0; TOTAL_SYMBOLS],
+        }
+    }
+
+    fn update(&mut self, rank: Rank) {
+        match rank {
+            0 => (),
+            1 => move_up(&mut self.symbols, 1, 0),
+            _ => move_up(&mut self.symbols, rank as usize, 1),
+        }
+    }
+}
+
+impl Policy for Mtf1 {
+    fn reset(&mut self) {
+        for (i,sym) in self.symbols.iter_mut().enumerate() {
+            *sym = i as Symbol;
+        }
+    }
+
+    fn encode(&mut self, sym: Symbol) -> Rank {
+        let rank = find_rank(&self.symbols, sym);
+        self.update(rank);
+        rank
+    }
+
+    fn decode(&mut self, rank: Rank) -> Symbol {
+        let sym = self.symbols[rank as usize];
+        self.update(rank);
+        sym
+    }
+}
+
+
+const WFC_INCREMENT_INIT    : u32 = 1<<12;
+const WFC_INCREMENT_LIMIT   : u32 = 1<<24;
+const WFC_RESCALE_SHIFT     : u32 = 12;
+
+/// Weighted Frequency Count policy: symbols are ordered by the sum of
+/// their occurrence weights, which decay exponentially with the distance
+pub struct Wfc {
+    symbols     : [Symbol; TOTAL_SYMBOLS],
+    weights     : [u32; TOTAL_SYMBOLS],
+    increment   : u32,
+    decay_shift : u32,
+}
+
+impl Wfc {
+    /// Create a new WFC policy with a given decay rate (up to 7),
+    /// the weight of an occurrence halves after about `0.7 << decay_shift` symbols
+    pub fn new(decay_shift: u32) -> Wfc {
+        assert!(decay_shift <= 7);
+        Wfc {
+            symbols     : [0; TOTAL_SYMBOLS],
+            weights     : [0; TOTAL_SYMBOLS],
+            increment   : WFC_INCREMENT_INIT,
+            decay_shift : decay_shift,
+        }
+    }
+
+    fn update(&mut self, rank: Rank) {
+        let sym = self.symbols[rank as usize];
+        self.weights[sym as usize] += self.increment;
+        // older occurrences decay relative to the new ones
+        self.increment += self.increment >> self.decay_shift;
+        if self.increment >= WFC_INCREMENT_LIMIT {
+            for w in self.weights.iter_mut() {
+                *w >>= WFC_RESCALE_SHIFT;
+            }
+            self.increment >>= WFC_RESCALE_SHIFT;
+        }
+        // only the weight of the current symbol has grown
+        let weight = self.weights[sym as usize];
+        let mut to = rank as usize;
+        while to > 0 && self.weights[self.symbols[to-1] as usize] <= weight {
+            to -= 1;
+        }
+        move_up(&mut self.symbols, rank as usize, to);
+    }
+}
+
+impl Policy for Wfc {
+    fn reset(&mut self) {
+        for (i,sym) in self.symbols.iter_mut().enumerate() {
+            *sym = i as Symbol;
+        }
+        for w in self.weights.iter_mut() {
+            *w = 0;
+        }
+        self.increment = WFC_INCREMENT_INIT;
+    }
+
+    fn encode(&mut self, sym: Symbol) -> Rank {
+        let rank = find_rank(&self.symbols, sym);
+        self.update(rank);
+        rank
+    }
+
+    fn decode(&mut self, rank: Rank) -> Symbol {
+        let sym = self.symbols[rank as usize];
+        self.update(rank);
+        sym
+    }
+}
+
+
+/// Rank block encoder
+pub struct Encoder<P, M> {
+    sac: saca::Constructor,
+    /// List update policy
+    pub policy: P,
+    /// Rank encoding model
+    pub model: M,
+}
+
+impl<P: Policy, M: RankModel> Encoder<P, M> {
+    /// Create a new Encoder instance
+    pub fn new(n: usize, policy: P, mut model: M) -> Encoder<P, M> {
+        model.reset();
+        Encoder {
+            sac     : saca::Constructor::new(n),
+            policy  : policy,
+            model   : model,
+        }
+    }
+}
+
+impl<P: Policy, M: RankModel> super::Encoder for Encoder<P, M> {
+    fn encode<W: io::Write>(&mut self, input: &[u8], writer: W) -> (W, io::Result<()>) {
+        let block_size = input.len();
+        assert!(block_size <= self.sac.capacity());
+        // perform BWT
+        let (output, origin) = {
+            let suf = self.sac.compute(input);
+            let mut iter = bwt::TransformIterator::new(input, suf);
+            let out: Vec<u8> = iter.by_ref().collect();
+            (out, iter.get_origin())
+        };
+        let mut eh = ari::Encoder::new(writer);
+        info!("Origin: {}", origin);
+        super::encode_origin(origin, &mut eh).unwrap();
+        // encode ranks
+        self.policy.reset();
+        let mut ctx = RankContext { last_rank: 0, last_length: 0 };
+        for &sym in output.iter() {
+            let rank = self.policy.encode(sym);
+            debug!("Symbol {} with rank {}", sym, rank);
+            self.model.encode(rank, &ctx, &mut eh).unwrap();
+            ctx.last_length = if rank == 0 {ctx.last_length + 1} else {0};
+            ctx.last_rank = rank;
+        }
+        // done
+        super::print_stats(&eh);
+        eh.finish()
+    }
+}
+
+impl<P: Policy, M: RankModel + State> super::Reusable for Encoder<P, M> {
+    fn resize(&mut self, n: usize) {
+        if n != self.sac.capacity() {
+            self.sac = saca::Constructor::new(n);
+        }
+    }
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+}
+
+
+/// Rank block decoder
+pub struct Decoder<P, M> {
+    input       : Vec<u8>,
+    suffixes    : Vec<saca::Suffix>,
+    /// List update policy
+    pub policy  : P,
+    /// Rank decoding model
+    pub model   : M,
+}
+
+impl<P: Policy, M: RankModel> Decoder<P, M> {
+    /// Create a new Decoder instance
+    pub fn new(n: usize, policy: P, mut model: M) -> Decoder<P, M> {
+        use std::iter::repeat;
+        model.reset();
+        Decoder {
+            input   : repeat(0u8).take(n).collect(),
+            suffixes: repeat(0 as saca::Suffix).take(n).collect(),
+            policy  : policy,
+            model   : model,
+        }
+    }
+}
+
+impl<P: Policy, M: RankModel> super::Decoder for Decoder<P, M> {
+    fn decode<R: io::Read, W: io::Write>(&mut self, reader: R, mut writer: W) -> (R, W, io::Result<()>) {
+        let mut dh = ari::Decoder::new(reader);
+        let origin = super::decode_origin(&mut dh).unwrap();
+        info!("Origin: {}", origin);
+        // decode ranks
+        self.policy.reset();
+        let mut ctx = RankContext { last_rank: 0, last_length: 0 };
+        for sym in self.input.iter_mut() {
+            let rank = self.model.decode(&ctx, &mut dh).unwrap();
+            *sym = self.policy.decode(rank);
+            debug!("Symbol {} with rank {}", *sym, rank);
+            ctx.last_length = if rank == 0 {ctx.last_length + 1} else {0};
+            ctx.last_rank = rank;
+        }
+        // undo BWT and write output
+        for b in bwt::decode(&self.input, origin, &mut self.suffixes) {
+            writer.write_u8(b).unwrap();
+        }
+        let result = writer.flush();
+        let (r, err) = dh.finish();
+        (r, writer, result.and(err))
+    }
+}
+
+impl<P: Policy, M: RankModel + State> super::Reusable for Decoder<P, M> {
+    fn resize(&mut self, n: usize) {
+        self.input.resize(n, 0);
+        self.suffixes.resize(n, 0);
+    }
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    use block::{Encoder, Decoder};
+    use model::{RankModel, qlfc};
+    use super::{Policy, Mtf, Mtf1, Wfc};
+
+    const TEXT: &'static [u8] = include_bytes!("../../LICENSE");
+
+    fn roundtrip<P: Policy, M: RankModel>(policy: P, model: M, bytes: &[u8]) {
+        let mut enc = super::Encoder::new(bytes.len(), policy, model);
+        let (writer, err) = enc.encode(bytes, Vec::new());
+        err.unwrap();
+        let reader = io::BufReader::new(io::Cursor::new(&writer[..]));
+        let mut dec = super::Decoder::new(bytes.len(), enc.policy, enc.model);
+        let (_, output, err) = dec.decode(reader, Vec::new());
+        err.unwrap();
+        assert_eq!(&bytes[..], &output[..]);
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(Mtf::new(), qlfc::Model::new(), b"abracababra");
+        roundtrip(Mtf::new(), qlfc::Model::new(), TEXT);
+        roundtrip(Mtf1::new(), qlfc::Model::new(), TEXT);
+        roundtrip(Wfc::new(4), qlfc::Model::new(), TEXT);
+    }
+
+    #[test]
+    fn wfc_rescale() {
+        let mut wfc = Wfc::new(4);
+        wfc.reset();
+        assert_eq!(wfc.encode(b'a'), b'a');
+        // the next update rescales, 'b' stays behind the heavier 'a'
+        wfc.weights[b'a' as usize] = 1<<30;
+        wfc.increment = super::WFC_INCREMENT_LIMIT - 1;
+        wfc.encode(b'b');
+        assert_eq!(wfc.encode(b'a'), 0);
+    }
+}
@@ -9,15 +9,88 @@ use std::io;
 
 use compress::bwt;
 use compress::entropy::ari;
-use model::{RawModel, Symbol};
+use entropy::{self, carry, rans, table, BitDecoder, BitEncoder, Coder};
+use model::{RawModel, State, Symbol, SymContext};
 use saca;
 
 
+const TOTAL_SYMBOLS: usize = 0x100;
+const MAX_COUNT_LOG: usize = 32;
+
+/// Encode the symbol counts (BWT bucket sizes)
+fn encode_counts<E: BitEncoder>(counts: &[usize; TOTAL_SYMBOLS], eh: &mut E) -> io::Result<()> {
+    let mut freq_log = ari::table::Model::new_flat(MAX_COUNT_LOG+1, ari::RANGE_DEFAULT_THRESHOLD >> 2);
+    let flat = ari::apm::Bit::new_equal();
+    for &count in counts.iter() {
+        let mut log = 0;
+        while count>>log != 0 {log += 1;}
+        try!(eh.encode_symbol(log, &freq_log));
+        freq_log.update(log, 5, 1);
+        for i in (0 .. if log>0 {log-1} else {0}).rev() {
+            try!(eh.encode_bit((count>>i) & 1 != 0, &flat));
+        }
+    }
+    Ok(())
+}
+
+/// Decode the symbol counts (BWT bucket sizes)
+fn decode_counts<D: BitDecoder>(dh: &mut D) -> io::Result<[usize; TOTAL_SYMBOLS]> {
+    let mut counts = [0usize; TOTAL_SYMBOLS];
+    let mut freq_log = ari::table::Model::new_flat(MAX_COUNT_LOG+1, ari::RANGE_DEFAULT_THRESHOLD >> 2);
+    let flat = ari::apm::Bit::new_equal();
+    for count in counts.iter_mut() {
+        let log = try!(dh.decode_symbol(&freq_log));
+        freq_log.update(log, 5, 1);
+        *count = if log>0 {1} else {0};
+        for _ in 1 .. log {
+            let bit = try!(dh.decode_bit(&flat));
+            *count = (*count<<1) + (bit as usize);
+        }
+    }
+    Ok(counts)
+}
+
+/// Symbol context tracker, following the BWT buckets
+struct Tracker {
+    counts  : [usize; TOTAL_SYMBOLS],
+    context : SymContext,
+}
+
+impl Tracker {
+    fn new(counts: [usize; TOTAL_SYMBOLS]) -> Tracker {
+        let mut tracker = Tracker {
+            counts  : counts,
+            context : SymContext::new(),
+        };
+        tracker.context.bucket_size = counts[0];
+        tracker.skip_empty();
+        tracker
+    }
+
+    fn skip_empty(&mut self) {
+        let ctx = &mut self.context;
+        while ctx.bucket_offset == ctx.bucket_size && (ctx.bucket as usize) < TOTAL_SYMBOLS-1 {
+            ctx.bucket += 1;
+            ctx.bucket_offset = 0;
+            ctx.bucket_size = self.counts[ctx.bucket as usize];
+        }
+    }
+
+    fn advance(&mut self, sym: Symbol) {
+        self.context.update(sym);
+        self.context.bucket_offset += 1;
+        self.skip_empty();
+    }
+}
+
+
 /// Raw BWT output encoder
 pub struct Encoder<M> {
     sac: saca::Constructor,
     /// Raw encoding model
     pub model: M,
+    /// Entropy coder backend
+    pub coder: Coder,
 }
 
 impl<M: RawModel> Encoder<M> {
@@ -27,12 +100,12 @@ impl<M: RawModel> Encoder<M> {
         Encoder {
             sac     : saca::Constructor::new(n),
             model   : model,
+            coder   : Coder::Range,
         }
     }
-}
 
-impl<M: RawModel> super::Encoder for Encoder<M> {
-	fn encode<W: io::Write>(&mut self, input: &[u8], writer: W) -> (W, io::Result<()>) {
+    /// Encode a block with a given entropy encoder
+    pub fn encode_block<E: BitEncoder>(&mut self, input: &[u8], eh: &mut E) {
         let block_size = input.len();
         assert!(block_size <= self.sac.capacity());
         // perform BWT and DC
@@ -42,20 +115,75 @@ impl<M: RawModel> super::Encoder for Encoder<M> {
             let out: Vec<u8> = iter.by_ref().collect();
             (out, iter.get_origin())
         };
-        let mut eh = ari::Encoder::new(writer);
         // encode origin
         info!("Origin: {}", origin);
-        self.model.encode((origin>>24) as Symbol, &(), &mut eh).unwrap();
-        self.model.encode((origin>>16) as Symbol, &(), &mut eh).unwrap();
-        self.model.encode((origin>>8)  as Symbol, &(), &mut eh).unwrap();
-        self.model.encode(origin as Symbol, &(), &mut eh).unwrap();
+        let ctx = SymContext::new();
+        self.model.encode((origin>>24) as Symbol, &ctx, eh).unwrap();
+        self.model.encode((origin>>16) as Symbol, &ctx, eh).unwrap();
+        self.model.encode((origin>>8)  as Symbol, &ctx, eh).unwrap();
+        self.model.encode(origin as Symbol, &ctx, eh).unwrap();
+        // encode bucket sizes
+        let mut counts = [0usize; TOTAL_SYMBOLS];
+        for sym in output.iter() {
+            counts[*sym as usize] += 1;
+        }
+        encode_counts(&counts, eh).unwrap();
         // encode symbols
+        let mut tracker = Tracker::new(counts);
         for sym in output.iter() {
-            self.model.encode(*sym as Symbol, &(), &mut eh).unwrap();
+            self.model.encode(*sym as Symbol, &tracker.context, eh).unwrap();
+            tracker.advance(*sym);
         }
-        // done
-        super::print_stats(&eh);
-        eh.finish()
+    }
+}
+
+impl<M: RawModel> super::Encoder for Encoder<M> {
+	fn encode<W: io::Write>(&mut self, input: &[u8], writer: W) -> (W, io::Result<()>) {
+        match self.coder {
+            Coder::Range => {
+                let mut eh = ari::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                super::print_stats(&eh);
+                eh.finish()
+            },
+            Coder::Rans => {
+                let mut eh = rans::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                eh.finish()
+            },
+            Coder::Binary => {
+                let mut eh = entropy::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                eh.finish()
+            },
+            Coder::Carry => {
+                let mut eh = carry::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                eh.finish()
+            },
+            Coder::Table => {
+                let mut eh = table::Encoder::new(writer);
+                self.encode_block(input, &mut eh);
+                eh.finish()
+            },
+        }
+    }
+}
+
+impl<M: RawModel + State> super::Reusable for Encoder<M> {
+    fn resize(&mut self, n: usize) {
+        if n != self.sac.capacity() {
+            self.sac = saca::Constructor::new(n);
+        }
+    }
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+
+    fn set_coder(&mut self, coder: Coder) -> bool {
+        self.coder = coder;
+        true
     }
 }
 
@@ -65,6 +193,8 @@ pub struct Decoder<M> {
     suffixes    : Vec<saca::Suffix>,
     /// Raw decoding model
     pub model   : M,
+    /// Entropy coder backend
+    pub coder   : Coder,
 }
 
 impl<M: RawModel> Decoder<M> {
@@ -76,30 +206,128 @@ impl<M: RawModel> Decoder<M> {
             input   : repeat(0u8).take(n).collect(),
             suffixes: repeat(0 as saca::Suffix).take(n).collect(),
             model   : model,
+            coder   : Coder::Range,
         }
     }
-}
 
-impl<M: RawModel> super::Decoder for Decoder<M> {
-    fn decode<R: io::Read, W: io::Write>(&mut self, reader: R, mut writer: W) -> (R, W, io::Result<()>) {
-        let mut dh = ari::Decoder::new(reader);
+    /// Decode a block with a given entropy decoder
+    pub fn decode_block<D: BitDecoder, W: io::Write>(&mut self, dh: &mut D, writer: &mut W)
+                        -> io::Result<()> {
         // decode origin
+        let ctx = SymContext::new();
         let origin =
-            ((self.model.decode(&(), &mut dh).unwrap() as usize) << 24) |
-            ((self.model.decode(&(), &mut dh).unwrap() as usize) << 16) |
-            ((self.model.decode(&(), &mut dh).unwrap() as usize) << 8)  |
-            ((self.model.decode(&(), &mut dh).unwrap() as usize));
+            ((self.model.decode(&ctx, dh).unwrap() as usize) << 24) |
+            ((self.model.decode(&ctx, dh).unwrap() as usize) << 16) |
+            ((self.model.decode(&ctx, dh).unwrap() as usize) << 8)  |
+            ((self.model.decode(&ctx, dh).unwrap() as usize));
         info!("Origin: {}", origin);
+        // decode bucket sizes
+        let counts = decode_counts(dh).unwrap();
         // decode symbols
+        let mut tracker = Tracker::new(counts);
         for sym in self.input.iter_mut() {
-            *sym = self.model.decode(&(), &mut dh).unwrap();
+            *sym = self.model.decode(&tracker.context, dh).unwrap();
+            tracker.advance(*sym);
         }
         // undo BWT and write output
         for b in bwt::decode(&self.input, origin, &mut self.suffixes) {
             writer.write_u8(b).unwrap();
         }
-        let result = writer.flush();
-        let (r, err) = dh.finish();
-        (r, writer, result.and(err))
+        writer.flush()
+    }
+}
+
+impl<M: RawModel> super::Decoder for Decoder<M> {
+    fn decode<R: io::Read, W: io::Write>(&mut self, reader: R, mut writer: W) -> (R, W, io::Result<()>) {
+        let (r, result) = match self.coder {
+            Coder::Range => {
+                let mut dh = ari::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+            Coder::Rans => {
+                let mut dh = rans::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+            Coder::Binary => {
+                let mut dh = entropy::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+            Coder::Carry => {
+                let mut dh = carry::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+            Coder::Table => {
+                let mut dh = table::Decoder::new(reader);
+                let result = self.decode_block(&mut dh, &mut writer);
+                let (r, err) = dh.finish();
+                (r, result.and(err))
+            },
+        };
+        (r, writer, result)
+    }
+}
+
+impl<M: RawModel + State> super::Reusable for Decoder<M> {
+    fn resize(&mut self, n: usize) {
+        self.input.resize(n, 0);
+        self.suffixes.resize(n, 0);
+    }
+
+    fn state(&mut self) -> &mut State {
+        &mut self.model
+    }
+
+    fn set_coder(&mut self, coder: Coder) -> bool {
+        self.coder = coder;
+        true
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    use block::{Encoder, Decoder};
+    use entropy::Coder;
+    use model::{RawModel, bbb};
+
+    const TEXT: &'static [u8] = include_bytes!("../../LICENSE");
+
+    fn roundtrip<M: RawModel>(model: M, bytes: &[u8]) {
+        roundtrip_with(model, Coder::Range, bytes)
+    }
+
+    fn roundtrip_with<M: RawModel>(model: M, coder: Coder, bytes: &[u8]) {
+        let mut enc = super::Encoder::new(bytes.len(), model);
+        enc.coder = coder;
+        let (writer, err) = enc.encode(bytes, Vec::new());
+        err.unwrap();
+        let reader = io::BufReader::new(io::Cursor::new(&writer[..]));
+        let mut dec = super::Decoder::new(bytes.len(), enc.model);
+        dec.coder = coder;
+        let (_, output, err) = dec.decode(reader, Vec::new());
+        err.unwrap();
+        assert_eq!(&bytes[..], &output[..]);
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(bbb::Model::new(), b"abracababra");
+        roundtrip(bbb::Model::new(), TEXT);
+    }
+
+    #[test]
+    fn coders() {
+        for &coder in [Coder::Binary, Coder::Carry, Coder::Rans, Coder::Table].iter() {
+            roundtrip_with(bbb::Model::new(), coder, TEXT);
+        }
     }
 }
@@ -0,0 +1,142 @@
+/*!
+
+Coding statistics of the DC block encoder
+
+The cost of every coded distance, measured by `entropy::meter`, is added
+up by the stage of the block it belongs to. The DC distances are also
+split by their symbol (the context symbol) and by their binary exponent,
+and the models marking the distance parts report the exponent and the
+mantissa separately.
+
+*/
+
+use compress::bwt::dc::Context;
+use std::fmt;
+
+use entropy::{NUM_PARTS, Part};
+use model::Distance;
+
+/// Stage of a block the coded distances belong to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    /// Initial distances of the symbols, with the runs of the present ones
+    Init,
+    /// Distances of the DC
+    Distance,
+    /// Origin of the BWT
+    Origin,
+}
+
+/// Number of the stages
+pub const NUM_STAGES: usize = 3;
+/// Number of the binary exponents of a distance
+pub const NUM_EXPONENTS: usize = 33;
+
+/// Binary exponent of a distance, the number of significant bits of `d+1` minus one
+pub fn get_exponent(d: Distance) -> usize {
+    63 - (d as u64 + 1).leading_zeros() as usize
+}
+
+/// Number of the coded values and their cost
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cost {
+    /// Number of the values
+    pub count: usize,
+    /// Total cost in bits
+    pub bits: f64,
+}
+
+impl Cost {
+    /// Create an empty cost
+    pub fn new() -> Cost {
+        Cost { count: 0, bits: 0.0 }
+    }
+
+    /// Account for a value of a given cost
+    pub fn add(&mut self, bits: f64) {
+        self.count += 1;
+        self.bits += bits;
+    }
+}
+
+/// Statistics report of the coded blocks
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// Cost per stage, indexed by `Stage`
+    pub stages: [Cost; NUM_STAGES],
+    /// Bits spent per distance part, indexed by `Part`
+    pub parts: [f64; NUM_PARTS],
+    /// Cost of the DC distances per context symbol
+    pub symbols: Vec<Cost>,
+    /// Cost of the DC distances per binary exponent
+    pub exponents: Vec<Cost>,
+}
+
+impl Report {
+    /// Create an empty report
+    pub fn new() -> Report {
+        Report {
+            stages: [Cost::new(); NUM_STAGES],
+            parts: [0.0; NUM_PARTS],
+            symbols: vec![Cost::new(); 0x100],
+            exponents: vec![Cost::new(); NUM_EXPONENTS],
+        }
+    }
+
+    /// Account for a coded distance, given its cost per part
+    pub fn add(&mut self, stage: Stage, d: Distance, ctx: &Context, parts: &[f64; NUM_PARTS]) {
+        let bits = parts.iter().fold(0.0, |sum, b| sum + b);
+        self.stages[stage as usize].add(bits);
+        for (total, b) in self.parts.iter_mut().zip(parts.iter()) {
+            *total += *b;
+        }
+        if stage == Stage::Distance {
+            self.symbols[ctx.symbol as usize].add(bits);
+            self.exponents[get_exponent(d)].add(bits);
+        }
+    }
+
+    /// Total cost in bits
+    pub fn total(&self) -> f64 {
+        self.stages.iter().fold(0.0, |sum, c| sum + c.bits)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn line(f: &mut fmt::Formatter, name: &str, cost: &Cost) -> fmt::Result {
+            let average = if cost.count != 0 {cost.bits / cost.count as f64} else {0.0};
+            writeln!(f, "{:>10} {:>10} {:>12.1} {:>8.2}", name, cost.count, cost.bits / 8.0, average)
+        }
+        try!(writeln!(f, "{:>10} {:>10} {:>12} {:>8}", "stage", "count", "bytes", "bits"));
+        for (name, cost) in ["init", "distance", "origin"].iter().zip(self.stages.iter()) {
+            try!(line(f, name, cost));
+        }
+        try!(writeln!(f, "{:>10} {:>10} {:>12.1}", "total",
+            self.stages.iter().fold(0, |sum, c| sum + c.count), self.total() / 8.0));
+        if self.parts[Part::Exponent as usize] + self.parts[Part::Mantissa as usize] > 0.0 {
+            try!(writeln!(f, "\n{:>10} {:>12}", "part", "bytes"));
+            for (name, bits) in ["other", "exponent", "mantissa"].iter().zip(self.parts.iter()) {
+                try!(writeln!(f, "{:>10} {:>12.1}", name, bits / 8.0));
+            }
+        }
+        try!(writeln!(f, "\n{:>10} {:>10} {:>12} {:>8}", "exponent", "count", "bytes", "bits"));
+        for (e, cost) in self.exponents.iter().enumerate() {
+            if cost.count != 0 {
+                try!(line(f, &e.to_string(), cost));
+            }
+        }
+        try!(writeln!(f, "\n{:>10} {:>10} {:>12} {:>8}", "symbol", "count", "bytes", "bits"));
+        for (sym, cost) in self.symbols.iter().enumerate() {
+            if cost.count != 0 {
+                let name = if sym > 0x20 && sym < 0x7F {
+                    format!("'{}'", sym as u8 as char)
+                }else {
+                    format!("0x{:02X}", sym)
+                };
+                try!(line(f, &name, cost));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+/*!
+
+Synthetic corpus
+
+Deterministic test data of several kinds, produced by a seeded generator
+of its own, so that the files stay the same on every platform and with
+any version of the dependencies:
+
+- `text`: words of a letter chain, following each other by a word chain
+- `records`: binary records of growing ids, small categories, a random
+  walk value and a few tags
+- `random`: uniform bytes
+- `runs`: runs of random lengths over a small alphabet
+
+The compressed sizes of every benchmark model on this corpus are checked
+against recorded baselines by the tests. `dark corpus DIR` writes the files
+out, so that `dark bench DIR` reproduces the numbers.
+
+*/
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+/// Names of the corpus files
+pub const NAMES: [&'static str; 4] = ["text", "records", "random", "runs"];
+/// Size of each corpus file
+pub const SIZE: usize = 0x4000;
+/// Seed of the generator
+pub const SEED: u64 = 0x5EED;
+
+const LETTERS: &'static [u8] = b"etaoinshrdlucmfwypvbgkjqxz";
+const NUM_WORDS: usize = 400;
+const NUM_FOLLOWERS: usize = 4;
+const TAGS: [&'static [u8; 4]; 4] = [b"USER", b"ITEM", b"CART", b"SHIP"];
+
+/// Pseudo-random generator (SplitMix64)
+#[derive(Clone, Debug)]
+pub struct Random(u64);
+
+impl Random {
+    /// Create a generator from a seed
+    pub fn new(seed: u64) -> Random {
+        Random(seed)
+    }
+
+    /// Next random value
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Random value below a limit
+    pub fn below(&mut self, limit: usize) -> usize {
+        (self.next() % limit as u64) as usize
+    }
+
+    /// Random value below a limit, the smaller ones being more likely
+    pub fn skewed(&mut self, limit: usize) -> usize {
+        let top = self.below(limit) + 1;
+        self.below(top)
+    }
+}
+
+/// Text-like data of words made by a letter chain, chained by the previous word
+pub fn make_text(rng: &mut Random, size: usize) -> Vec<u8> {
+    let followers: Vec<Vec<u8>> = LETTERS.iter().map(|_| {
+        (0 .. NUM_FOLLOWERS).map(|_| LETTERS[rng.skewed(LETTERS.len())]).collect()
+    }).collect();
+    let words: Vec<Vec<u8>> = (0 .. NUM_WORDS).map(|_| {
+        let length = 1 + rng.below(3) + rng.below(6);
+        let mut word = vec![LETTERS[rng.skewed(LETTERS.len())]];
+        while word.len() < length {
+            let last = LETTERS.iter().position(|&c| c == word[word.len()-1]).unwrap();
+            word.push(if rng.below(4) != 0 {
+                followers[last][rng.below(NUM_FOLLOWERS)]
+            }else {
+                LETTERS[rng.skewed(LETTERS.len())]
+            });
+        }
+        word
+    }).collect();
+    let next_words: Vec<Vec<usize>> = (0 .. NUM_WORDS).map(|_| {
+        (0 .. NUM_FOLLOWERS).map(|_| rng.skewed(NUM_WORDS)).collect()
+    }).collect();
+    let mut out = Vec::with_capacity(size + 0x20);
+    let (mut word, mut sentence, mut line) = (0, 0, 0);
+    while out.len() < size {
+        word = if rng.below(2) != 0 {
+            next_words[word][rng.below(NUM_FOLLOWERS)]
+        }else {
+            rng.skewed(NUM_WORDS)
+        };
+        let start = out.len();
+        out.extend_from_slice(&words[word]);
+        if sentence == 0 {
+            out[start] = out[start].to_ascii_uppercase();
+        }
+        sentence += 1;
+        if sentence > 5 + rng.below(10) {
+            out.push(b'.');
+            sentence = 0;
+        }else if rng.below(12) == 0 {
+            out.push(b',');
+        }
+        line += out.len() - start + 1;
+        if line > 70 {
+            out.push(b'\n');
+            line = 0;
+        }else {
+            out.push(b' ');
+        }
+    }
+    out.truncate(size);
+    out
+}
+
+/// Binary records of 16 bytes
+pub fn make_records(rng: &mut Random, size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(size + 0x10);
+    let (mut id, mut value) = (0u32, 1000u32);
+    while out.len() < size {
+        id += 1 + rng.skewed(3) as u32;
+        value = value.wrapping_add(rng.below(201) as u32).wrapping_sub(100);
+        out.write_u32::<LittleEndian>(id).unwrap();
+        out.write_u16::<LittleEndian>(rng.skewed(16) as u16).unwrap();
+        out.write_u32::<LittleEndian>(value).unwrap();
+        out.extend_from_slice(TAGS[rng.skewed(TAGS.len())]);
+        out.extend_from_slice(&[0, 0]);
+    }
+    out.truncate(size);
+    out
+}
+
+/// Uniformly random bytes
+pub fn make_random(rng: &mut Random, size: usize) -> Vec<u8> {
+    (0 .. size).map(|_| rng.next() as u8).collect()
+}
+
+/// Runs of up to 64 bytes over an alphabet of 8 symbols
+pub fn make_runs(rng: &mut Random, size: usize) -> Vec<u8> {
+    let alphabet: Vec<u8> = (0 .. 8).map(|_| rng.next() as u8).collect();
+    let mut out = Vec::with_capacity(size + 0x40);
+    while out.len() < size {
+        let symbol = alphabet[rng.skewed(alphabet.len())];
+        let length = 1 + rng.skewed(64);
+        out.extend((0 .. length).map(|_| symbol));
+    }
+    out.truncate(size);
+    out
+}
+
+/// Generate a corpus file by name
+pub fn generate(name: &str, size: usize) -> Option<Vec<u8>> {
+    let mut rng = Random::new(SEED);
+    match name {
+        "text"      => Some(make_text(&mut rng, size)),
+        "records"   => Some(make_records(&mut rng, size)),
+        "random"    => Some(make_random(&mut rng, size)),
+        "runs"      => Some(make_runs(&mut rng, size)),
+        _           => None,
+    }
+}
+
+/// Write all the corpus files into a directory, creating it if needed
+pub fn write_all(dir: &Path) -> io::Result<()> {
+    try!(fs::create_dir_all(dir));
+    for name in NAMES.iter() {
+        let data = generate(name, SIZE).unwrap();
+        try!(try!(File::create(dir.join(name))).write_all(&data));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use alphabet::Order;
+    use entropy::Coder;
+
+    /// Relative deviation of a compressed size from its baseline that is let through
+    const TOLERANCE: f64 = 0.01;
+
+    /// Compressed sizes of the corpus files, with the header, by `dark bench`
+    const BASELINES: &'static [(&'static str, &'static str, usize)] = &[
+        ("text", "bbb", 5610), ("text", "dark", 4884), ("text", "exp", 5252), ("text", "fixed", 5092),
+        ("text", "mix", 4974), ("text", "mtf", 5160), ("text", "mtf1", 5151), ("text", "qlfc", 5136),
+        ("text", "simple", 5228), ("text", "wfc", 5260), ("text", "ybs", 4956),
+        ("records", "bbb", 4864), ("records", "dark", 4853), ("records", "exp", 4990), ("records", "fixed", 5078),
+        ("records", "mix", 4711), ("records", "mtf", 4253), ("records", "mtf1", 4204), ("records", "qlfc", 4282),
+        ("records", "simple", 5243), ("records", "wfc", 4175), ("records", "ybs", 4967),
+        ("random", "bbb", 17104), ("random", "dark", 16866), ("random", "exp", 17310), ("random", "fixed", 16866),
+        ("random", "mix", 16856), ("random", "mtf", 16764), ("random", "mtf1", 16758), ("random", "qlfc", 16851),
+        ("random", "simple", 17231), ("random", "wfc", 16764), ("random", "ybs", 16886),
+        ("runs", "bbb", 1088), ("runs", "dark", 852), ("runs", "exp", 1032), ("runs", "fixed", 939),
+        ("runs", "mix", 848), ("runs", "mtf", 1037), ("runs", "mtf1", 978), ("runs", "qlfc", 968),
+        ("runs", "simple", 1062), ("runs", "wfc", 937), ("runs", "ybs", 892),
+    ];
+
+    /// FNV-1a hash
+    fn hash(data: &[u8]) -> u32 {
+        data.iter().fold(0x811C9DC5, |h, &b| (h ^ b as u32).wrapping_mul(0x01000193))
+    }
+
+    #[test]
+    fn generator() {
+        let mut rng = super::Random::new(0);
+        assert_eq!(rng.next(), 0xE220A8397B1DCDAF);
+        for _ in 0 .. 100 {
+            assert!(rng.skewed(10) < 10);
+        }
+        let hashes: Vec<u32> = super::NAMES.iter().map(|name| {
+            let data = super::generate(name, super::SIZE).unwrap();
+            assert_eq!(data.len(), super::SIZE);
+            assert_eq!(super::generate(name, super::SIZE).unwrap(), data);
+            hash(&data)
+        }).collect();
+        // the baselines are only valid for the same data
+        assert_eq!(hashes, vec![0x97F82C2E, 0x1AACDE8B, 0xED570CB6, 0x14553C37]);
+        assert!(super::generate("none", 1).is_none());
+    }
+
+    #[test]
+    fn ratios() {
+        use bench::MODELS;
+        assert_eq!(BASELINES.len(), super::NAMES.len() * MODELS.len());
+        let mut failures = Vec::new();
+        for &(name, model, baseline) in BASELINES.iter() {
+            let input = super::generate(name, super::SIZE).unwrap();
+            let entry = ::measure(model, name, &input, Order::Natural, Coder::Range, 0).unwrap();
+            let deviation = entry.compressed as f64 / baseline as f64 - 1.0;
+            if deviation.abs() > TOLERANCE {
+                failures.push(format!("{} on {}: {} bytes instead of {}",
+                    model, name, entry.compressed, baseline));
+            }
+        }
+        assert!(failures.is_empty(), "compressed sizes moved away from the baselines, \
+            update them if it is intended:\n{}", failures.join("\n"));
+    }
+}
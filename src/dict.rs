@@ -0,0 +1,198 @@
+/*!
+
+Dictionaries for small similar inputs
+
+A dictionary is a piece of text shared by the encoder and the decoder.
+The blocks are coded in the context of its BWT, which is never transmitted:
+every block suffix is placed among the sorted dictionary suffixes and
+the block ones, and the symbol preceding it is predicted from the symbols
+preceding its neighbours (see `block::dict`).
+
+A dictionary is built from sample files by picking the segments that cover
+the most substrings (of `KMER` bytes) occurring in several samples. The file
+consists of a signature followed by the dictionary bytes, and is identified
+in the stream headers by the checksum of the bytes.
+
+*/
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::{cmp, io, usize};
+
+use primer::checksum;
+use saca;
+
+
+/// Signature of the dictionary files
+pub const SIGNATURE: &'static [u8] = b"DARKDICT";
+/// Default dictionary size
+pub const DEFAULT_SIZE: usize = 1<<16;
+/// Length of the substrings counted in the samples
+pub const KMER: usize = 8;
+/// Length of the segments a dictionary is made of
+pub const SEGMENT: usize = 64;
+
+/// Identifier of a dictionary, stored in the header
+pub fn id(data: &[u8]) -> u32 {
+    checksum(data)
+}
+
+/// Read the dictionary bytes from a file
+pub fn read<R: io::Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    try!(reader.read_to_end(&mut data));
+    if !data.starts_with(SIGNATURE) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            "invalid dictionary signature"))
+    }
+    Ok(data[SIGNATURE.len() ..].to_vec())
+}
+
+/// Write the dictionary bytes into a file
+pub fn write<W: io::Write>(data: &[u8], writer: &mut W) -> io::Result<()> {
+    try!(writer.write_all(SIGNATURE));
+    writer.write_all(data)
+}
+
+
+/// Sum of the sample frequencies of the substrings not covered yet. The ones
+/// seen in a single sample don't help the others, so they are not counted.
+fn segment_score(segment: &[u8], covered: &HashSet<&[u8]>, frequency: &HashMap<&[u8], usize>) -> usize {
+    let mut seen = HashSet::new();
+    segment.windows(KMER)
+        .filter(|kmer| !covered.contains(kmer) && seen.insert(*kmer))
+        .map(|kmer| frequency[&kmer] - 1)
+        .fold(0, |a, b| a + b)
+}
+
+/// Build a dictionary of up to a given size from a set of samples
+pub fn train(samples: &[Vec<u8>], size: usize) -> Vec<u8> {
+    // number of samples containing each substring
+    let mut frequency: HashMap<&[u8], usize> = HashMap::new();
+    for sample in samples.iter() {
+        let mut seen = HashSet::new();
+        for kmer in sample.windows(KMER) {
+            if seen.insert(kmer) {
+                *frequency.entry(kmer).or_insert(0) += 1;
+            }
+        }
+    }
+    let segments: Vec<&[u8]> = samples.iter().flat_map(|s| s.chunks(SEGMENT)).collect();
+    let mut covered = HashSet::new();
+    // lazy greedy selection: the scores only go down as more gets covered
+    let mut heap: BinaryHeap<(usize, usize)> = segments.iter().enumerate()
+        .map(|(i, s)| (segment_score(s, &covered, &frequency), usize::MAX - i)).collect();
+    let mut dictionary = Vec::with_capacity(size);
+    while let Some((old_score, key)) = heap.pop() {
+        let segment = segments[usize::MAX - key];
+        let new_score = segment_score(segment, &covered, &frequency);
+        if new_score == 0 {
+            continue
+        }
+        if new_score < old_score && heap.peek().map_or(false, |&(s, _)| s > new_score) {
+            heap.push((new_score, key));
+            continue
+        }
+        if dictionary.len() + segment.len() > size {
+            break
+        }
+        dictionary.extend_from_slice(segment);
+        for kmer in segment.windows(KMER) {
+            covered.insert(kmer);
+        }
+    }
+    info!("Trained a dictionary of {} bytes from {} segments", dictionary.len(), segments.len());
+    dictionary
+}
+
+
+/// Dictionary with its suffixes sorted, ready for the lookups
+#[derive(Clone, Debug)]
+pub struct Dictionary {
+    data        : Vec<u8>,
+    suffixes    : Vec<saca::Suffix>,
+}
+
+impl Dictionary {
+    /// Sort the suffixes of the dictionary bytes
+    pub fn new(data: Vec<u8>) -> Dictionary {
+        let suffixes = if data.is_empty() {
+            Vec::new()
+        }else {
+            saca::Constructor::new(data.len()).compute(&data).to_vec()
+        };
+        Dictionary {
+            data        : data,
+            suffixes    : suffixes,
+        }
+    }
+
+    /// Number of the dictionary suffixes
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Get the suffix at a given sorted position
+    pub fn suffix(&self, rank: usize) -> &[u8] {
+        &self.data[self.suffixes[rank] as usize ..]
+    }
+
+    /// Get the symbol preceding the suffix at a given sorted position,
+    /// the first suffix being preceded by the last symbol
+    pub fn preceding(&self, rank: usize) -> u8 {
+        let pos = self.suffixes[rank] as usize;
+        self.data[if pos == 0 {self.data.len()} else {pos} - 1]
+    }
+
+    /// Find the sorted position of a key: the number of suffixes less than it
+    pub fn locate(&self, key: &[u8]) -> usize {
+        let (mut lo, mut hi) = (0, self.suffixes.len());
+        while lo < hi {
+            let mid = (lo + hi) >> 1;
+            if self.suffix(mid) < key {
+                lo = mid + 1;
+            }else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+}
+
+/// Length of the common prefix of two byte strings
+pub fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    let limit = cmp::min(a.len(), b.len());
+    a.iter().zip(b.iter()).position(|(x, y)| x != y).unwrap_or(limit)
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+
+    #[test]
+    fn locate() {
+        let dict = super::Dictionary::new(b"abracadabra".to_vec());
+        for key in [&b""[..], b"a", b"abr", b"b", b"ra", b"rz", b"zz"].iter() {
+            let rank = dict.locate(key);
+            assert!(rank == 0 || dict.suffix(rank-1) < *key);
+            assert!(rank == dict.len() || dict.suffix(rank) >= *key);
+        }
+        assert_eq!(dict.preceding(dict.locate(b"bra")), b'a');
+        assert_eq!(super::common_prefix(b"abc", b"abd"), 2);
+        assert_eq!(super::common_prefix(b"ab", b"abd"), 2);
+    }
+
+    #[test]
+    fn train() {
+        let samples: Vec<Vec<u8>> = (0 .. 20).map(|i|
+            format!("{{\"user\": \"u{}\", \"status\": \"active\", \"score\": {}}}", i, i*13).into_bytes()
+            ).collect();
+        let data = super::train(&samples, 1000);
+        assert!(!data.is_empty() && data.len() <= 1000);
+        let text = String::from_utf8_lossy(&data).into_owned();
+        assert!(text.contains("\"status\": \"active\""));
+        let mut buf = Vec::new();
+        super::write(&data, &mut buf).unwrap();
+        assert_eq!(super::read(&mut io::Cursor::new(&buf[..])).unwrap(), data);
+    }
+}
@@ -0,0 +1,353 @@
+/*!
+
+Range coder with carry propagation
+
+The low border is kept in 64 bits and the range in 32 bits. Unlike the
+carry-less coders of `compress` and `table`, the range is never cut
+when the top byte can't settle: the bytes that may still change are held
+back, and a carry out of the low border is added to them once it happens.
+The held bytes are the last settled one and the run of `0xFF` bytes after
+it, which is kept as a counter. The last value of a frequency table gets
+the remainder of the range division.
+
+The range is normalized to at least `TOP`, so the total frequency of a
+model can't exceed `MAX_TOTAL`. The stream ends with the 4 bytes of the
+low border, like the other range coders of `entropy`.
+
+*/
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use compress::entropy::ari;
+use std::fmt::Display;
+use std::io;
+
+/// Range border type
+pub type Border = ari::Border;
+
+const TOP: Border = 1<<24;
+/// Maximum total frequency a model can have
+pub const MAX_TOTAL: Border = TOP;
+
+
+/// Range encoder with carry propagation
+pub struct Encoder<W> {
+    stream: W,
+    low: u64,
+    range: Border,
+    /// the last byte that may still receive a carry
+    cache: u8,
+    /// number of the held bytes, the cached one and the `0xFF` run after it
+    pending: usize,
+}
+
+impl<W: io::Write> Encoder<W> {
+    /// Create a new encoder on top of a given writer
+    pub fn new(stream: W) -> Encoder<W> {
+        Encoder {
+            stream: stream,
+            low: 0,
+            range: !0,
+            cache: 0,
+            pending: 0,
+        }
+    }
+
+    fn shift_low(&mut self) -> io::Result<()> {
+        // the top byte is settled unless it's 0xFF without a carry
+        if self.low < 0xFF00_0000 || self.low >> 32 != 0 || self.pending == 0 {
+            let carry = (self.low >> 32) as u8;
+            if self.pending != 0 {
+                try!(self.stream.write_u8(self.cache.wrapping_add(carry)));
+                for _ in 1 .. self.pending {
+                    try!(self.stream.write_u8(0xFFu8.wrapping_add(carry)));
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+            self.pending = 0;
+        }
+        self.pending += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+        Ok(())
+    }
+
+    /// Encode a frequency range out of a total
+    pub fn encode_range(&mut self, offset: Border, freq: Border, total: Border) -> io::Result<()> {
+        debug_assert!(freq > 0 && offset + freq <= total && total <= MAX_TOTAL);
+        let r = self.range / total;
+        self.low += (r * offset) as u64;
+        self.range = if offset + freq == total {
+            self.range - r * offset
+        }else {
+            r * freq
+        };
+        while self.range < TOP {
+            self.range <<= 8;
+            try!(self.shift_low());
+        }
+        Ok(())
+    }
+
+    /// Encode a value with a given model
+    pub fn encode<V: Copy + Display, M: ari::Model<V>>(&mut self, value: V, model: &M) -> io::Result<()> {
+        let (lo, hi) = model.get_range(value);
+        self.encode_range(lo, hi - lo, model.get_denominator())
+    }
+
+    /// Finish encoding by writing the held bytes and the low border
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let mut result = Ok(());
+        for _ in 0 .. 5 {
+            result = result.and(self.shift_low());
+        }
+        result = result.and(self.stream.flush());
+        (self.stream, result)
+    }
+}
+
+/// Range decoder with carry propagation
+pub struct Decoder<R> {
+    stream: R,
+    range: Border,
+    /// code relative to the low border
+    code: Border,
+    /// range per frequency unit of the value being decoded
+    scale: Border,
+    total: Border,
+    started: bool,
+}
+
+impl<R: io::Read> Decoder<R> {
+    /// Create a new decoder on top of a given reader
+    pub fn new(stream: R) -> Decoder<R> {
+        Decoder {
+            stream: stream,
+            range: !0,
+            code: 0,
+            scale: 0,
+            total: 0,
+            started: false,
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            for _ in 0 .. 4 {
+                self.code = (self.code << 8) | (try!(self.stream.read_u8()) as Border);
+            }
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    /// Get the frequency offset of the current code out of a total,
+    /// must be followed by `decode_range` with the range found
+    pub fn decode_offset(&mut self, total: Border) -> io::Result<Border> {
+        debug_assert!(total <= MAX_TOTAL);
+        try!(self.start());
+        self.scale = self.range / total;
+        self.total = total;
+        let offset = self.code / self.scale;
+        Ok(if offset < total {offset} else {total - 1})
+    }
+
+    /// Narrow down to the range of the decoded value
+    pub fn decode_range(&mut self, offset: Border, freq: Border) -> io::Result<()> {
+        self.code -= self.scale * offset;
+        self.range = if offset + freq == self.total {
+            self.range - self.scale * offset
+        }else {
+            self.scale * freq
+        };
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | (try!(self.stream.read_u8()) as Border);
+        }
+        Ok(())
+    }
+
+    /// Decode a value with a given model
+    pub fn decode<V: Copy + Display, M: ari::Model<V>>(&mut self, model: &M) -> io::Result<V> {
+        let offset = try!(self.decode_offset(model.get_denominator()));
+        let (value, lo, hi) = model.find_value(offset);
+        try!(self.decode_range(lo, hi - lo));
+        Ok(value)
+    }
+
+    /// Finish decoding
+    pub fn finish(mut self) -> (R, io::Result<()>) {
+        let result = self.start();
+        (self.stream, result)
+    }
+}
+
+impl<W: io::Write> super::BitEncoder for Encoder<W> {
+    fn encode_bit<M: ari::Model<bool>>(&mut self, bit: bool, model: &M) -> io::Result<()> {
+        self.encode(bit, model)
+    }
+
+    fn encode_symbol<M: ari::Model<usize>>(&mut self, value: usize, model: &M) -> io::Result<()> {
+        self.encode(value, model)
+    }
+}
+
+impl<R: io::Read> super::BitDecoder for Decoder<R> {
+    fn decode_bit<M: ari::Model<bool>>(&mut self, model: &M) -> io::Result<bool> {
+        self.decode(model)
+    }
+
+    fn decode_symbol<M: ari::Model<usize>>(&mut self, model: &M) -> io::Result<usize> {
+        self.decode(model)
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    use rand::{Rng, SeedableRng, StdRng};
+    use compress::entropy::ari;
+    use super::Border;
+
+    /// Straightforward coder with the same arithmetic, adding a carry
+    /// to the bytes already written as soon as it happens
+    struct Reference {
+        low: u64,
+        range: Border,
+        bytes: Vec<u8>,
+    }
+
+    impl Reference {
+        fn new() -> Reference {
+            Reference { low: 0, range: !0, bytes: Vec::new() }
+        }
+
+        fn encode_range(&mut self, offset: Border, freq: Border, total: Border) {
+            let r = self.range / total;
+            self.low += (r * offset) as u64;
+            self.range = if offset + freq == total {self.range - r * offset} else {r * freq};
+            if self.low >> 32 != 0 {
+                self.low &= 0xFFFF_FFFF;
+                for b in self.bytes.iter_mut().rev() {
+                    *b = b.wrapping_add(1);
+                    if *b != 0 {
+                        break
+                    }
+                }
+            }
+            while self.range < super::TOP {
+                self.bytes.push((self.low >> 24) as u8);
+                self.low = (self.low & 0x00FF_FFFF) << 8;
+                self.range <<= 8;
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            for i in 0 .. 4 {
+                self.bytes.push((self.low >> (24 - i*8)) as u8);
+            }
+            self.bytes
+        }
+    }
+
+    /// Random frequency ranges, mostly very skewed ones
+    fn gen_ranges(count: usize, seed: usize) -> Vec<(Border, Border, Border)> {
+        let seed: &[_] = &[seed, 2, 3];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        (0 .. count).map(|_| {
+            let total = match rng.gen_range(0, 4) {
+                0 => 2,
+                1 => 1 << 12,
+                2 => super::MAX_TOTAL,
+                _ => rng.gen_range(2, super::MAX_TOTAL),
+            };
+            let offset = if rng.gen_weighted_bool(3) {
+                rng.gen_range(0, total)
+            }else if rng.gen() {0} else {total - 1};
+            let freq = if rng.gen_weighted_bool(3) {
+                rng.gen_range(1, total - offset + 1)
+            }else {1};
+            (offset, freq, total)
+        }).collect()
+    }
+
+    fn encode(input: &[(Border, Border, Border)]) -> Vec<u8> {
+        let mut eh = super::Encoder::new(Vec::new());
+        for &(offset, freq, total) in input.iter() {
+            eh.encode_range(offset, freq, total).unwrap();
+        }
+        let (buf, err) = eh.finish();
+        err.unwrap();
+        buf
+    }
+
+    #[test]
+    fn reference() {
+        for seed in 0 .. 20 {
+            let input = gen_ranges(5000, seed);
+            let mut reference = Reference::new();
+            for &(offset, freq, total) in input.iter() {
+                reference.encode_range(offset, freq, total);
+            }
+            assert!(encode(&input) == reference.finish(), "Mismatch with seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn roundtrip_ranges() {
+        let input = gen_ranges(20000, 1);
+        let buf = encode(&input);
+        let mut dh = super::Decoder::new(io::Cursor::new(&buf[..]));
+        for &(offset, freq, total) in input.iter() {
+            let found = dh.decode_offset(total).unwrap();
+            assert!(offset <= found && found < offset + freq);
+            dh.decode_range(offset, freq).unwrap();
+        }
+        let (cursor, err) = dh.finish();
+        err.unwrap();
+        assert_eq!(cursor.position() as usize, buf.len());
+        // an empty stream is the low border alone
+        assert_eq!(encode(&[]).len(), 4);
+    }
+
+    #[test]
+    fn carries() {
+        // the codes close to the top of the range keep producing carries
+        let input: Vec<_> = (0 .. 10000).map(|i|
+            if i % 7 == 0 {(0, 1, 3)} else {(254, 1, 255)}
+            ).collect();
+        let mut reference = Reference::new();
+        for &(offset, freq, total) in input.iter() {
+            reference.encode_range(offset, freq, total);
+        }
+        let buf = encode(&input);
+        assert!(buf == reference.finish());
+        let mut dh = super::Decoder::new(&buf[..]);
+        for &(offset, _, total) in input.iter() {
+            assert_eq!(dh.decode_offset(total).unwrap(), offset);
+            dh.decode_range(offset, 1).unwrap();
+        }
+    }
+
+    #[test]
+    fn compare_range() {
+        // confident and mostly right bit predictions,
+        // where the carry-less coder cuts the range the most
+        let seed: &[_] = &[4, 3, 2, 1];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let bits: Vec<bool> = (0 .. 100000).map(|_| rng.gen_weighted_bool(500)).collect();
+        let model = ari::apm::Bit::from_flat(ari::apm::FLAT_TOTAL as ari::apm::FlatProbability - 8);
+        let mut eh = super::Encoder::new(Vec::new());
+        let mut eh_range = ari::Encoder::new(Vec::new());
+        for &bit in bits.iter() {
+            eh.encode(bit, &model).unwrap();
+            eh_range.encode(bit, &model).unwrap();
+        }
+        let buf = eh.finish().0;
+        let range = eh_range.finish().0.len();
+        assert!(buf.len() < range, "carry {} vs range {}", buf.len(), range);
+        let mut dh = super::Decoder::new(&buf[..]);
+        for &bit in bits.iter() {
+            assert_eq!(dh.decode(&model).unwrap(), bit);
+        }
+    }
+}
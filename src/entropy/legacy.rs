@@ -0,0 +1,103 @@
+/*!
+
+Dark-0.51 range decoder
+
+A port of the `ark` namespace from `etc/dark-c/src/low.cpp`.
+The range is carry-less: when the top byte can not settle
+and the range gets too small, it is cut at the byte border.
+The models operate on the range directly, so the primitives are exposed.
+
+*/
+
+use byteorder::ReadBytesExt;
+use std::io;
+
+/// Range border type
+pub type Border = u32;
+
+const BOTTOM: Border = 1<<14;
+const TOP_SHIFT: usize = 24;
+
+
+/// Legacy range decoder
+pub struct Decoder<R> {
+    stream: R,
+    lo: Border,
+    range: Border,
+    code: Border,
+}
+
+impl<R: io::Read> Decoder<R> {
+    /// Create a new decoder on top of a given reader
+    pub fn new(stream: R) -> Decoder<R> {
+        Decoder {
+            stream: stream,
+            lo: 0,
+            range: !0,
+            code: 0,
+        }
+    }
+
+    /// Start decoding by reading the initial code word
+    pub fn start(&mut self) -> io::Result<()> {
+        for _ in 0..4 {
+            self.code = (self.code<<8) | (try!(self.stream.read_u8()) as Border);
+        }
+        Ok(())
+    }
+
+    /// Divide the range by a total frequency,
+    /// return the frequency offset of the current code
+    pub fn divide(&mut self, total: Border) -> Border {
+        self.range /= total;
+        self.code.wrapping_sub(self.lo) / self.range
+    }
+
+    /// Shift the range down by a number of bits
+    pub fn shift(&mut self, bits: usize) {
+        self.range >>= bits;
+    }
+
+    /// Check if the current code is below a given offset of the range
+    pub fn is_below(&self, offset: Border) -> bool {
+        self.code.wrapping_sub(self.lo) < offset.wrapping_mul(self.range)
+    }
+
+    /// Narrow the range down to `[offset, offset+size)`, reading new bytes
+    pub fn parse(&mut self, offset: Border, size: Border) -> io::Result<()> {
+        self.lo = self.lo.wrapping_add(self.range.wrapping_mul(offset));
+        self.range = self.range.wrapping_mul(size);
+        loop {
+            let mut hi = self.lo.wrapping_add(self.range);
+            if (self.lo ^ hi) >= 1<<TOP_SHIFT {
+                if self.range > BOTTOM {
+                    break
+                }
+                let lim = hi & 0xFF000000;
+                if hi.wrapping_sub(lim) >= lim.wrapping_sub(self.lo) {
+                    self.lo = lim;
+                }else {
+                    hi = lim.wrapping_sub(1);
+                }
+            }
+            loop {
+                self.code = (self.code<<8) | (try!(self.stream.read_u8()) as Border);
+                self.lo <<= 8;
+                hi <<= 8;
+                if (self.lo ^ hi) >= 1<<TOP_SHIFT {
+                    break
+                }
+            }
+            self.range = hi.wrapping_sub(self.lo);
+            if self.range >= BOTTOM {
+                break
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish decoding, returning the underlying stream
+    pub fn finish(self) -> R {
+        self.stream
+    }
+}
@@ -0,0 +1,93 @@
+/*!
+
+Cost meter
+
+A wrapper over an entropy encoder, measuring the information content
+of the coded values under their models: a value of probability `p` costs
+`-log2(p)` bits. This is what an ideal coder would spend, the real output
+differs from it by the coder precision and the stream tail.
+
+The cost is accumulated separately for every `Part` of the distances,
+as marked by the models with `BitEncoder::set_part`.
+
+*/
+
+use compress::entropy::ari;
+use std::io;
+
+use super::{BitEncoder, NUM_PARTS, Part};
+
+/// Cost of coding a range out of a total, in bits
+pub fn cost(lo: ari::Border, hi: ari::Border, total: ari::Border) -> f64 {
+    debug_assert!(lo < hi && hi <= total);
+    (total as f64 / (hi - lo) as f64).log2()
+}
+
+/// Encoder wrapper accumulating the cost of the coded values
+pub struct Meter<'a, E: 'a> {
+    inner: &'a mut E,
+    part: Part,
+    /// Bits spent on each part, indexed by `Part`
+    pub bits: [f64; NUM_PARTS],
+}
+
+impl<'a, E: BitEncoder> Meter<'a, E> {
+    /// Create a new meter over a given encoder
+    pub fn new(inner: &'a mut E) -> Meter<'a, E> {
+        Meter {
+            inner: inner,
+            part: Part::Other,
+            bits: [0.0; NUM_PARTS],
+        }
+    }
+
+    /// Total bits spent
+    pub fn total(&self) -> f64 {
+        self.bits.iter().fold(0.0, |sum, b| sum + b)
+    }
+}
+
+impl<'a, E: BitEncoder> BitEncoder for Meter<'a, E> {
+    fn encode_bit<M: ari::Model<bool>>(&mut self, bit: bool, model: &M) -> io::Result<()> {
+        let (lo, hi) = model.get_range(bit);
+        self.bits[self.part as usize] += cost(lo, hi, model.get_denominator());
+        self.inner.encode_bit(bit, model)
+    }
+
+    fn encode_symbol<M: ari::Model<usize>>(&mut self, value: usize, model: &M) -> io::Result<()> {
+        let (lo, hi) = model.get_range(value);
+        self.bits[self.part as usize] += cost(lo, hi, model.get_denominator());
+        self.inner.encode_symbol(value, model)
+    }
+
+    fn set_part(&mut self, part: Part) {
+        self.part = part;
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use compress::entropy::ari;
+    use entropy::{BitEncoder, Part};
+
+    #[test]
+    fn parts() {
+        let mut eh = ari::Encoder::new(Vec::new());
+        {
+            let mut meter = super::Meter::new(&mut eh);
+            let flat = ari::apm::Bit::new_equal();
+            meter.encode_bit(true, &flat).unwrap();
+            meter.set_part(Part::Exponent);
+            let table = ari::table::Model::new_flat(16, ari::RANGE_DEFAULT_THRESHOLD);
+            meter.encode_symbol(3, &table).unwrap();
+            meter.set_part(Part::Mantissa);
+            for _ in 0 .. 5 {
+                meter.encode_bit(false, &flat).unwrap();
+            }
+            assert_eq!(meter.bits, [1.0, 4.0, 5.0]);
+            assert_eq!(meter.total(), 10.0);
+        }
+        eh.finish().1.unwrap();
+    }
+}
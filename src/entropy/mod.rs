@@ -1,12 +1,188 @@
 pub mod ari;
+pub mod carry;
+pub mod legacy;
+pub mod meter;
+pub mod paq;
+pub mod rans;
+pub mod table;
 
 use byteorder::ReadBytesExt;
+use compress::entropy::ari as cari;
 use compress::entropy::ari::apm;
-use std::io;
+use std::{fmt, io};
 
 const BORDER_BYTES: usize = 4;
 
 
+/// Entropy coder backend
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coder {
+    /// Range coder of `compress`, the default
+    Range,
+    /// Interleaved rANS coder
+    Rans,
+    /// Binary range coder of `Encoder` and `Decoder`
+    Binary,
+    /// Range coder with carry propagation, keeping the full precision
+    Carry,
+    /// Carry-less multi-symbol range coder of `table`
+    Table,
+}
+
+impl Coder {
+    /// Parse the coder name
+    pub fn from_name(name: &str) -> Option<Coder> {
+        match name {
+            "range" => Some(Coder::Range),
+            "rans"  => Some(Coder::Rans),
+            "binary"=> Some(Coder::Binary),
+            "carry" => Some(Coder::Carry),
+            "table" => Some(Coder::Table),
+            _       => None,
+        }
+    }
+
+    /// Return the identifier stored in the header
+    pub fn to_id(&self) -> u8 {
+        match *self {
+            Coder::Range    => 0,
+            Coder::Rans     => 1,
+            Coder::Binary   => 2,
+            Coder::Carry    => 3,
+            Coder::Table    => 4,
+        }
+    }
+
+    /// Find the coder by its header identifier
+    pub fn from_id(id: u8) -> Option<Coder> {
+        match id {
+            0   => Some(Coder::Range),
+            1   => Some(Coder::Rans),
+            2   => Some(Coder::Binary),
+            3   => Some(Coder::Carry),
+            4   => Some(Coder::Table),
+            _   => None,
+        }
+    }
+}
+
+impl fmt::Display for Coder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Coder::Range    => "range",
+            Coder::Rans     => "rans",
+            Coder::Binary   => "binary",
+            Coder::Carry    => "carry",
+            Coder::Table    => "table",
+        })
+    }
+}
+
+
+/// Part of a distance a model is coding, for the statistics
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Part {
+    /// Not split into parts by the model, or none of them
+    Other,
+    /// Binary order of the distance
+    Exponent,
+    /// Bits of the distance below its order
+    Mantissa,
+}
+
+/// Number of the distance parts
+pub const NUM_PARTS: usize = 3;
+
+/// Entropy encoder interface shared by the coding backends,
+/// so that the models don't depend on a particular one
+pub trait BitEncoder {
+    /// Encode a binary decision by a given probability model
+    fn encode_bit<M: cari::Model<bool>>(&mut self, bit: bool, model: &M) -> io::Result<()>;
+
+    /// Tell which part of a distance the following decisions belong to.
+    /// Only used for the statistics, ignored by the coding backends.
+    fn set_part(&mut self, _part: Part) {}
+
+    /// Encode a value by a given frequency model. Without a symbol coder
+    /// of the backend, the value is found by binary decisions, each halving
+    /// the range of the candidates.
+    fn encode_symbol<M: cari::Model<usize>>(&mut self, value: usize, model: &M) -> io::Result<()> {
+        let (mut lo, mut hi) = (0, num_values(model));
+        while hi - lo > 1 {
+            let mid = (lo + hi) >> 1;
+            let upper = value >= mid;
+            try!(self.encode_bit(upper, &split(model, lo, mid, hi)));
+            if upper {lo = mid} else {hi = mid}
+        }
+        Ok(())
+    }
+}
+
+/// Entropy decoder interface shared by the coding backends
+pub trait BitDecoder {
+    /// Decode a binary decision by a given probability model
+    fn decode_bit<M: cari::Model<bool>>(&mut self, model: &M) -> io::Result<bool>;
+
+    /// Decode a value by a given frequency model, see `BitEncoder::encode_symbol`
+    fn decode_symbol<M: cari::Model<usize>>(&mut self, model: &M) -> io::Result<usize> {
+        let (mut lo, mut hi) = (0, num_values(model));
+        while hi - lo > 1 {
+            let mid = (lo + hi) >> 1;
+            if try!(self.decode_bit(&split(model, lo, mid, hi))) {lo = mid} else {hi = mid}
+        }
+        Ok(lo)
+    }
+}
+
+/// Number of the values a frequency model can produce
+fn num_values<M: cari::Model<usize>>(model: &M) -> usize {
+    model.find_value(model.get_denominator() - 1).0 + 1
+}
+
+/// Probability of the value to be below `mid`, given it is within `[lo, hi)`
+fn split<M: cari::Model<usize>>(model: &M, lo: usize, mid: usize, hi: usize) -> apm::Bit {
+    let base = model.get_range(lo).0 as u64;
+    let lower = model.get_range(mid).0 as u64 - base;
+    let total = model.get_range(hi - 1).1 as u64 - base;
+    if total == 0 {
+        return apm::Bit::new_equal()
+    }
+    apm::Bit::from_flat(((lower << apm::FLAT_BITS) / total) as apm::FlatProbability)
+}
+
+/// Convert a binary model into a flat probability of the zero bit
+fn to_probability<M: cari::Model<bool>>(model: &M) -> apm::Bit {
+    let (lo, hi) = model.get_range(false);
+    let total = model.get_denominator();
+    if total == apm::FLAT_TOTAL as cari::Border {
+        // already in the flat scale, as for the `apm::Bit` models
+        return apm::Bit::from_flat((hi - lo) as apm::FlatProbability)
+    }
+    let zero = (hi - lo) as u64;
+    apm::Bit::from_flat(((zero << apm::FLAT_BITS) / total as u64) as apm::FlatProbability)
+}
+
+impl<W: io::Write> BitEncoder for cari::Encoder<W> {
+    fn encode_bit<M: cari::Model<bool>>(&mut self, bit: bool, model: &M) -> io::Result<()> {
+        self.encode(bit, model)
+    }
+
+    fn encode_symbol<M: cari::Model<usize>>(&mut self, value: usize, model: &M) -> io::Result<()> {
+        self.encode(value, model)
+    }
+}
+
+impl<R: io::Read> BitDecoder for cari::Decoder<R> {
+    fn decode_bit<M: cari::Model<bool>>(&mut self, model: &M) -> io::Result<bool> {
+        self.decode(model)
+    }
+
+    fn decode_symbol<M: cari::Model<usize>>(&mut self, model: &M) -> io::Result<usize> {
+        self.decode(model)
+    }
+}
+
+
 /// An arithmetic encoder helper
 pub struct Encoder<W> {
     stream: W,
@@ -81,3 +257,15 @@ impl<R: io::Read> Decoder<R> {
         (self.stream, result)
     }
 }
+
+impl<W: io::Write> BitEncoder for Encoder<W> {
+    fn encode_bit<M: cari::Model<bool>>(&mut self, bit: bool, model: &M) -> io::Result<()> {
+        self.encode(bit as ari::Bit, to_probability(model))
+    }
+}
+
+impl<R: io::Read> BitDecoder for Decoder<R> {
+    fn decode_bit<M: cari::Model<bool>>(&mut self, model: &M) -> io::Result<bool> {
+        self.decode(to_probability(model)).map(|bit| bit != 0)
+    }
+}
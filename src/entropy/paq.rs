@@ -0,0 +1,154 @@
+/*!
+
+Binary arithmetic coder of PAQ
+
+A port of the `Encoder` from `etc/bbb/main.cpp`. The range is kept as
+32-bit `[x1, x2]` and split by a 12-bit probability of the bit being 1.
+Leading bytes are shifted out as soon as they are equal in both ends,
+so there is no carry. Reading past the end of the stream yields 0xFF bytes.
+
+*/
+
+use std::io;
+use byteorder::WriteBytesExt;
+
+/// Probability of a bit being 1, in 12 bits
+pub type Probability = u32;
+
+/// Number of bits in the probability
+pub const PROBABILITY_BITS: usize = 12;
+
+fn split(x1: u32, x2: u32, p1: Probability) -> u32 {
+    debug_assert!(p1 < 1<<PROBABILITY_BITS);
+    let p = p1 + (p1 < 1<<(PROBABILITY_BITS-1)) as u32;
+    let range = x2 - x1;
+    let xmid = x1 + (range >> PROBABILITY_BITS) * p + (((range & 0xFFF) * p) >> PROBABILITY_BITS);
+    debug_assert!(xmid >= x1 && xmid < x2);
+    xmid
+}
+
+
+/// Binary arithmetic encoder
+pub struct Encoder<W> {
+    stream: W,
+    x1: u32,
+    x2: u32,
+}
+
+impl<W: io::Write> Encoder<W> {
+    /// Create a new encoder on top of a given writer
+    pub fn new(stream: W) -> Encoder<W> {
+        Encoder {
+            stream: stream,
+            x1: 0,
+            x2: !0,
+        }
+    }
+
+    /// Encode a bit with a given probability of it being 1
+    pub fn encode(&mut self, bit: bool, p1: Probability) -> io::Result<()> {
+        let xmid = split(self.x1, self.x2, p1);
+        if bit {
+            self.x2 = xmid;
+        }else {
+            self.x1 = xmid + 1;
+        }
+        while (self.x1 ^ self.x2) & 0xFF000000 == 0 {
+            try!(self.stream.write_u8((self.x2 >> 24) as u8));
+            self.x1 <<= 8;
+            self.x2 = (self.x2 << 8) + 0xFF;
+        }
+        Ok(())
+    }
+
+    /// Finish encoding by writing the first unequal byte of the range
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.stream.write_u8((self.x1 >> 24) as u8)
+                                .map_err(From::from);
+        let result = result.and(self.stream.flush());
+        (self.stream, result)
+    }
+}
+
+
+/// Binary arithmetic decoder
+pub struct Decoder<R> {
+    stream: R,
+    x1: u32,
+    x2: u32,
+    x: u32,
+}
+
+impl<R: io::Read> Decoder<R> {
+    /// Create a new decoder on top of a given reader
+    pub fn new(stream: R) -> Decoder<R> {
+        Decoder {
+            stream: stream,
+            x1: 0,
+            x2: !0,
+            x: 0,
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<u32> {
+        let mut buf = [0u8];
+        match try!(self.stream.read(&mut buf)) {
+            0 => Ok(0xFF),
+            _ => Ok(buf[0] as u32),
+        }
+    }
+
+    /// Start decoding by reading the initial code word
+    pub fn start(&mut self) -> io::Result<()> {
+        for _ in 0..4 {
+            self.x = (self.x << 8) + try!(self.next_byte());
+        }
+        Ok(())
+    }
+
+    /// Decode a bit with a given probability of it being 1
+    pub fn decode(&mut self, p1: Probability) -> io::Result<bool> {
+        let xmid = split(self.x1, self.x2, p1);
+        let bit = self.x <= xmid;
+        if bit {
+            self.x2 = xmid;
+        }else {
+            self.x1 = xmid + 1;
+        }
+        while (self.x1 ^ self.x2) & 0xFF000000 == 0 {
+            self.x1 <<= 8;
+            self.x2 = (self.x2 << 8) + 0xFF;
+            self.x = (self.x << 8) + try!(self.next_byte());
+        }
+        Ok(bit)
+    }
+
+    /// Finish decoding, returning the underlying stream
+    pub fn finish(self) -> R {
+        self.stream
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+
+    #[test]
+    fn roundtrip() {
+        let bits: Vec<(bool, u32)> = (0 .. 1000u32).map(|i|
+            ((i * 7) % 5 == 0, (i * 37) % 4096)
+            ).collect();
+        let mut eh = super::Encoder::new(Vec::new());
+        for &(bit, p1) in bits.iter() {
+            eh.encode(bit, p1).unwrap();
+        }
+        let (buf, err) = eh.finish();
+        err.unwrap();
+        let mut dh = super::Decoder::new(io::Cursor::new(&buf[..]));
+        dh.start().unwrap();
+        for &(bit, p1) in bits.iter() {
+            assert_eq!(dh.decode(p1).unwrap(), bit);
+        }
+    }
+}
@@ -0,0 +1,500 @@
+/*!
+
+Interleaved rANS coder
+
+An alternative to the binary range coder of `entropy::Encoder`/`Decoder`,
+with the same interface. The values are spread over several rANS states
+in turn (value `i` of a chunk goes to state `i % N`), so the decoding steps
+of the neighbouring values don't depend on each other, and the byte stream
+of a chunk is read at once instead of byte by byte.
+
+Both the binary decisions and the values of the frequency models are coded
+directly, with their ranges scaled to a power of two total. A value of
+a frequency model takes a single coding step, instead of a binary decision
+per bit of the value on the other binary backends. Scaling keeps every
+value of the model codable, at the cost of a single slot out of the total.
+
+rANS is last-in-first-out: the encoder keeps the ranges of a chunk of up to
+`CHUNK_SIZE` values and codes them in reverse once the chunk is full.
+The stream consists of the number of states (1 byte), followed by the chunks.
+A chunk is made of the number of its values (4 bytes), the number of
+the renormalization bytes (4 bytes), the final states (4 bytes each)
+and the renormalization bytes in the decoding order. All the numbers are
+little endian.
+
+*/
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use compress::entropy::ari;
+use compress::entropy::ari::apm;
+use std::io;
+use std::io::Read;
+
+use super::ari::Bit;
+
+/// State type
+pub type State = u32;
+
+/// Total of the scaled frequencies
+const PROB_BITS: u32 = 15;
+const PROB_TOTAL: State = 1 << PROB_BITS;
+/// Scaling of the binary probabilities to the total
+const FLAT_SHIFT: u32 = PROB_BITS - apm::FLAT_BITS as u32;
+/// Lower bound of a normalized state
+const LOWER: State = 1 << 23;
+/// Maximum number of the interleaved states
+pub const MAX_STATES: usize = 4;
+/// Number of the interleaved states used by default
+pub const DEFAULT_STATES: usize = 2;
+/// Maximum number of the values coded in a chunk
+pub const CHUNK_SIZE: usize = 1 << 18;
+
+/// Probability of the zero bit, kept within the coding range
+#[inline]
+fn zero_frequency(model: apm::Bit) -> State {
+    let flat = model.to_flat() as State;
+    let total = apm::FLAT_TOTAL as State;
+    (if flat == 0 {1} else if flat >= total {total-1} else {flat}) << FLAT_SHIFT
+}
+
+/// Scaled cumulative frequency of the values below a given one
+#[inline]
+fn scaled_start<M: ari::Model<usize>>(model: &M, value: usize, num_values: usize) -> State {
+    if value == num_values {
+        return PROB_TOTAL
+    }
+    let start = model.get_range(value).0 as u64;
+    let total = model.get_denominator() as u64;
+    let space = (PROB_TOTAL as usize - num_values) as u64;
+    ((start * space / total) as usize + value) as State
+}
+
+fn corrupted(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+/// An interleaved rANS encoder
+pub struct Encoder<W> {
+    stream: W,
+    num_states: usize,
+    started: bool,
+    /// Scaled ranges of the values of the current chunk
+    ranges: Vec<(u16, u16)>,
+}
+
+impl<W: io::Write> Encoder<W> {
+    /// Create a new encoder on top of a given writer
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder::with_states(w, DEFAULT_STATES)
+    }
+
+    /// Create a new encoder with a given number of interleaved states
+    pub fn with_states(w: W, num_states: usize) -> Encoder<W> {
+        assert!(num_states > 0 && num_states <= MAX_STATES);
+        Encoder {
+            stream: w,
+            num_states: num_states,
+            started: false,
+            ranges: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, start: State, freq: State) -> io::Result<()> {
+        debug_assert!(freq > 0 && start + freq <= PROB_TOTAL);
+        // the full range can't be stored, but it takes no coding either
+        if freq == PROB_TOTAL {
+            return Ok(())
+        }
+        if self.ranges.len() == CHUNK_SIZE {
+            try!(self.write_chunk());
+        }
+        self.ranges.push((start as u16, freq as u16));
+        Ok(())
+    }
+
+    /// Encode a bit
+    pub fn encode(&mut self, bit: Bit, model: apm::Bit) -> io::Result<()> {
+        let freq0 = zero_frequency(model);
+        if bit != 0 {
+            self.push(freq0, PROB_TOTAL - freq0)
+        }else {
+            self.push(0, freq0)
+        }
+    }
+
+    /// Encode a value by a given frequency model
+    pub fn encode_value<M: ari::Model<usize>>(&mut self, value: usize, model: &M) -> io::Result<()> {
+        let num_values = super::num_values(model);
+        assert!(num_values < PROB_TOTAL as usize);
+        let start = scaled_start(model, value, num_values);
+        let end = scaled_start(model, value + 1, num_values);
+        self.push(start, end - start)
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if !self.started {
+            try!(self.stream.write_u8(self.num_states as u8));
+            self.started = true;
+        }
+        let mut states = [LOWER; MAX_STATES];
+        let mut bytes = Vec::with_capacity(self.ranges.len() / 8);
+        for (i, &(start, freq)) in self.ranges.iter().enumerate().rev() {
+            let (start, freq) = (start as State, freq as State);
+            let x = &mut states[i % self.num_states];
+            let x_max = ((LOWER >> PROB_BITS) << 8) * freq;
+            while *x >= x_max {
+                bytes.push(*x as u8);
+                *x >>= 8;
+            }
+            *x = ((*x / freq) << PROB_BITS) + (*x % freq) + start;
+        }
+        try!(self.stream.write_u32::<LittleEndian>(self.ranges.len() as u32));
+        try!(self.stream.write_u32::<LittleEndian>(bytes.len() as u32));
+        for &x in states[.. self.num_states].iter() {
+            try!(self.stream.write_u32::<LittleEndian>(x));
+        }
+        bytes.reverse();
+        self.ranges.clear();
+        self.stream.write_all(&bytes)
+    }
+
+    /// Finish encoding by writing the last chunk,
+    /// which is only empty if there is no other one
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = if self.started && self.ranges.is_empty() {
+            Ok(())
+        }else {
+            self.write_chunk()
+        };
+        let result = result.and_then(|_| self.stream.flush());
+        (self.stream, result)
+    }
+}
+
+
+/// An interleaved rANS decoder
+pub struct Decoder<R> {
+    stream: R,
+    started: bool,
+    states: [State; MAX_STATES],
+    num_states: usize,
+    current: usize,
+    /// Number of the values left in the current chunk
+    remaining: usize,
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl<R: io::Read> Decoder<R> {
+    /// Create a decoder on top of a given reader
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            stream: r,
+            started: false,
+            states: [LOWER; MAX_STATES],
+            num_states: 1,
+            current: 0,
+            remaining: 0,
+            bytes: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn is_chunk_over(&self) -> bool {
+        self.remaining == 0 && self.position == self.bytes.len() &&
+            self.states[.. self.num_states].iter().all(|&x| x == LOWER)
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.num_states = try!(self.stream.read_u8()) as usize;
+            if self.num_states == 0 || self.num_states > MAX_STATES {
+                return Err(corrupted("invalid number of rans states"))
+            }
+            self.started = true;
+        }else if !self.is_chunk_over() {
+            return Err(corrupted("rans chunk does not end where expected"))
+        }
+        self.remaining = try!(self.stream.read_u32::<LittleEndian>()) as usize;
+        if self.remaining > CHUNK_SIZE {
+            return Err(corrupted("rans chunk is too long"))
+        }
+        let num_bytes = try!(self.stream.read_u32::<LittleEndian>()) as usize;
+        for x in self.states[.. self.num_states].iter_mut() {
+            *x = try!(self.stream.read_u32::<LittleEndian>());
+        }
+        self.bytes.clear();
+        try!(self.stream.by_ref().take(num_bytes as u64).read_to_end(&mut self.bytes));
+        if self.bytes.len() != num_bytes {
+            return Err(corrupted("rans stream is truncated"))
+        }
+        self.position = 0;
+        self.current = 0;
+        Ok(())
+    }
+
+    /// Return the slot of the current state, starting a new chunk if needed
+    #[inline]
+    fn peek(&mut self) -> io::Result<State> {
+        if self.remaining == 0 {
+            try!(self.read_chunk());
+            if self.remaining == 0 {
+                return Err(corrupted("rans chunk is empty"))
+            }
+        }
+        Ok(self.states[self.current] & (PROB_TOTAL - 1))
+    }
+
+    /// Advance the current state past a decoded range
+    #[inline]
+    fn pop(&mut self, start: State, freq: State) -> io::Result<()> {
+        let x = &mut self.states[self.current];
+        *x = freq * (*x >> PROB_BITS) + (*x & (PROB_TOTAL - 1)) - start;
+        while *x < LOWER {
+            if self.position == self.bytes.len() {
+                return Err(corrupted("rans stream is truncated"))
+            }
+            *x = (*x << 8) | (self.bytes[self.position] as State);
+            self.position += 1;
+        }
+        self.remaining -= 1;
+        self.current += 1;
+        if self.current == self.num_states {
+            self.current = 0;
+        }
+        Ok(())
+    }
+
+    /// Decode a bit
+    pub fn decode(&mut self, model: apm::Bit) -> io::Result<Bit> {
+        let slot = try!(self.peek());
+        let freq0 = zero_frequency(model);
+        if slot >= freq0 {
+            try!(self.pop(freq0, PROB_TOTAL - freq0));
+            Ok(1)
+        }else {
+            try!(self.pop(0, freq0));
+            Ok(0)
+        }
+    }
+
+    /// Decode a value by a given frequency model
+    pub fn decode_value<M: ari::Model<usize>>(&mut self, model: &M) -> io::Result<usize> {
+        let num_values = super::num_values(model);
+        assert!(num_values < PROB_TOTAL as usize);
+        if num_values == 1 {
+            return Ok(0)
+        }
+        let slot = try!(self.peek());
+        // the last value with the scaled start not above the slot
+        let (mut lo, mut hi) = (0, num_values);
+        while hi - lo > 1 {
+            let mid = (lo + hi) >> 1;
+            if scaled_start(model, mid, num_values) <= slot {lo = mid} else {hi = mid}
+        }
+        let start = scaled_start(model, lo, num_values);
+        let end = scaled_start(model, lo + 1, num_values);
+        try!(self.pop(start, end - start));
+        Ok(lo)
+    }
+
+    /// Finish decoding, checking that the whole stream is consumed
+    pub fn finish(mut self) -> (R, io::Result<()>) {
+        let mut result = if self.started {Ok(())} else {self.read_chunk()};
+        if result.is_ok() && !self.is_chunk_over() {
+            result = Err(corrupted("rans stream does not end where expected"));
+        }
+        (self.stream, result)
+    }
+}
+
+impl<W: io::Write> super::BitEncoder for Encoder<W> {
+    fn encode_bit<M: ari::Model<bool>>(&mut self, bit: bool, model: &M) -> io::Result<()> {
+        self.encode(bit as Bit, super::to_probability(model))
+    }
+
+    fn encode_symbol<M: ari::Model<usize>>(&mut self, value: usize, model: &M) -> io::Result<()> {
+        self.encode_value(value, model)
+    }
+}
+
+impl<R: io::Read> super::BitDecoder for Decoder<R> {
+    fn decode_bit<M: ari::Model<bool>>(&mut self, model: &M) -> io::Result<bool> {
+        self.decode(super::to_probability(model)).map(|bit| bit != 0)
+    }
+
+    fn decode_symbol<M: ari::Model<usize>>(&mut self, model: &M) -> io::Result<usize> {
+        self.decode_value(model)
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    #[cfg(feature="unstable")]
+    use test::Bencher;
+    use rand::{Rng, SeedableRng, StdRng};
+    use compress::entropy::ari::apm;
+    use entropy::ari::Bit;
+
+    const NUM_CONTEXTS: usize = 64;
+
+    /// Bits of a few skewed sources, with the source as the context
+    fn gen_bits(count: usize) -> Vec<(usize, Bit)> {
+        let seed: &[_] = &[5, 6, 7, 8];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        (0 .. count).map(|_| {
+            let ctx = rng.gen_range(0, NUM_CONTEXTS);
+            let bit = rng.gen_range(0, NUM_CONTEXTS) < ctx;
+            (ctx, bit as Bit)
+        }).collect()
+    }
+
+    fn encode_rans(input: &[(usize, Bit)], num_states: usize) -> Vec<u8> {
+        let mut bits = vec![apm::Bit::new_equal(); NUM_CONTEXTS];
+        let mut eh = super::Encoder::with_states(Vec::new(), num_states);
+        for &(ctx, bit) in input.iter() {
+            eh.encode(bit, bits[ctx]).unwrap();
+            bits[ctx].update(bit != 0, 5, 0);
+        }
+        let (buf, err) = eh.finish();
+        err.unwrap();
+        buf
+    }
+
+    fn decode_rans(input: &[(usize, Bit)], buf: &[u8]) {
+        let mut bits = vec![apm::Bit::new_equal(); NUM_CONTEXTS];
+        let mut dh = super::Decoder::new(buf);
+        for &(ctx, bit) in input.iter() {
+            assert_eq!(dh.decode(bits[ctx]).unwrap(), bit);
+            bits[ctx].update(bit != 0, 5, 0);
+        }
+        let (rest, err) = dh.finish();
+        err.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    fn encode_range(input: &[(usize, Bit)]) -> Vec<u8> {
+        let mut bits = vec![apm::Bit::new_equal(); NUM_CONTEXTS];
+        let mut eh = ::entropy::Encoder::new(Vec::new());
+        for &(ctx, bit) in input.iter() {
+            eh.encode(bit, bits[ctx]).unwrap();
+            bits[ctx].update(bit != 0, 5, 0);
+        }
+        let (buf, err) = eh.finish();
+        err.unwrap();
+        buf
+    }
+
+    fn decode_range(input: &[(usize, Bit)], buf: &[u8]) {
+        let mut bits = vec![apm::Bit::new_equal(); NUM_CONTEXTS];
+        let mut dh = ::entropy::Decoder::new(io::Cursor::new(buf));
+        for &(ctx, bit) in input.iter() {
+            assert_eq!(dh.decode(bits[ctx]).unwrap(), bit);
+            bits[ctx].update(bit != 0, 5, 0);
+        }
+    }
+
+    #[test]
+    fn roundtrips() {
+        let input = gen_bits(10000);
+        for num_states in 1 .. super::MAX_STATES+1 {
+            let buf = encode_rans(&input, num_states);
+            decode_rans(&input, &buf);
+        }
+        decode_rans(&[], &encode_rans(&[], 3));
+    }
+
+    #[test]
+    fn chunks() {
+        let input = gen_bits(2 * super::CHUNK_SIZE + 10);
+        decode_rans(&input, &encode_rans(&input, 3));
+        let input = gen_bits(super::CHUNK_SIZE);
+        decode_rans(&input, &encode_rans(&input, 2));
+    }
+
+    #[test]
+    fn values() {
+        use compress::entropy::ari::table::Model;
+        use entropy::{BitDecoder, BitEncoder};
+        let input: Vec<usize> = gen_bits(10000).iter().map(|&(ctx, bit)| ctx * bit as usize).collect();
+        let single = Model::new_flat(1, 1<<13);
+        let mut freq = Model::new_flat(NUM_CONTEXTS, 1<<13);
+        let mut eh = super::Encoder::new(Vec::new());
+        for &value in input.iter() {
+            eh.encode_symbol(value, &freq).unwrap();
+            eh.encode_symbol(0, &single).unwrap();
+            freq.update(value, 10, 1);
+        }
+        let (buf, err) = eh.finish();
+        err.unwrap();
+        let mut freq = Model::new_flat(NUM_CONTEXTS, 1<<13);
+        let mut dh = super::Decoder::new(&buf[..]);
+        for &value in input.iter() {
+            assert_eq!(dh.decode_symbol(&freq).unwrap(), value);
+            assert_eq!(dh.decode_symbol(&single).unwrap(), 0);
+            freq.update(value, 10, 1);
+        }
+        dh.finish().1.unwrap();
+    }
+
+    #[test]
+    fn extremes() {
+        // the model is as sure as it can be, and right or wrong about it
+        let sure = |bit: Bit| apm::Bit::from_flat(if bit != 0 {0} else {
+            apm::FLAT_TOTAL as apm::FlatProbability - 1});
+        let mut eh = super::Encoder::new(Vec::new());
+        for i in 0 .. 1000 {
+            let bit = (i & 1) as Bit;
+            eh.encode(bit, sure(bit)).unwrap();
+            eh.encode(bit, sure(1 - bit)).unwrap();
+        }
+        let (buf, err) = eh.finish();
+        err.unwrap();
+        let mut dh = super::Decoder::new(&buf[..]);
+        for i in 0 .. 1000 {
+            let bit = (i & 1) as Bit;
+            assert_eq!(dh.decode(sure(bit)).unwrap(), bit);
+            assert_eq!(dh.decode(sure(1 - bit)).unwrap(), bit);
+        }
+        dh.finish().1.unwrap();
+    }
+
+    #[test]
+    fn corrupted() {
+        let input = gen_bits(1000);
+        let mut buf = encode_rans(&input, 2);
+        buf.pop();
+        let mut dh = super::Decoder::new(&buf[..]);
+        assert!(dh.decode(apm::Bit::new_equal()).is_err());
+    }
+
+    #[test]
+    fn compare_range() {
+        let input = gen_bits(100000);
+        let rans = encode_rans(&input, super::DEFAULT_STATES).len();
+        let range = encode_range(&input).len();
+        assert!(rans * 100 < range * 101 && range * 100 < rans * 101,
+            "rans {} vs range {}", rans, range);
+    }
+
+    #[cfg(feature="unstable")]
+    #[bench]
+    fn decode_bits_rans(bh: &mut Bencher) {
+        let input = gen_bits(1<<16);
+        let buf = encode_rans(&input, super::DEFAULT_STATES);
+        bh.iter(|| decode_rans(&input, &buf));
+        bh.bytes = input.len() as u64 / 8;
+    }
+
+    #[cfg(feature="unstable")]
+    #[bench]
+    fn decode_bits_range(bh: &mut Bencher) {
+        let input = gen_bits(1<<16);
+        let buf = encode_range(&input);
+        bh.iter(|| decode_range(&input, &buf));
+        bh.bytes = input.len() as u64 / 8;
+    }
+}
@@ -0,0 +1,405 @@
+/*!
+
+Multi-symbol range coder
+
+Codes a value in a single step by its cumulative frequency range,
+instead of splitting it into binary decisions for `ari::Range`. Any model
+implementing `compress::entropy::ari::Model` can be used, as long as its
+total frequency doesn't exceed `MAX_TOTAL`, which holds for the frequency
+tables of `compress` with the default threshold.
+
+The range is carry-less (Subbotin): when the top byte can not settle and
+the range gets below `BOTTOM`, it is cut at the nearest `BOTTOM` border.
+
+`Frequencies` is an adaptive table keeping the cumulative frequencies,
+so that the coding of large alphabets doesn't sum up the table every time.
+The `fixed` model codes its exponent histograms with it.
+
+The coder is the `table` entropy backend (`-e table`), coding the frequency
+tables of the models in one step and their bits as two-symbol tables.
+
+*/
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use compress::entropy::ari;
+use std::fmt::Display;
+use std::io;
+
+/// Range border type
+pub type Border = ari::Border;
+
+const TOP: Border = 1<<24;
+const BOTTOM: Border = 1<<16;
+/// Maximum total frequency a model can have
+pub const MAX_TOTAL: Border = BOTTOM;
+
+
+/// Multi-symbol range encoder
+pub struct Encoder<W> {
+    stream: W,
+    lo: Border,
+    range: Border,
+}
+
+impl<W: io::Write> Encoder<W> {
+    /// Create a new encoder on top of a given writer
+    pub fn new(stream: W) -> Encoder<W> {
+        Encoder {
+            stream: stream,
+            lo: 0,
+            range: !0,
+        }
+    }
+
+    /// Encode a frequency range out of a total
+    pub fn encode_range(&mut self, offset: Border, freq: Border, total: Border) -> io::Result<()> {
+        debug_assert!(freq > 0 && offset + freq <= total && total <= MAX_TOTAL);
+        let r = self.range / total;
+        self.lo = self.lo.wrapping_add(r * offset);
+        self.range = r * freq;
+        loop {
+            if (self.lo ^ self.lo.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOTTOM {
+                    break
+                }
+                self.range = self.lo.wrapping_neg() & (BOTTOM - 1);
+            }
+            try!(self.stream.write_u8((self.lo >> 24) as u8));
+            self.lo <<= 8;
+            self.range <<= 8;
+        }
+        Ok(())
+    }
+
+    /// Encode a value with a given model
+    pub fn encode<V: Copy + Display, M: ari::Model<V>>(&mut self, value: V, model: &M) -> io::Result<()> {
+        let (lo, hi) = model.get_range(value);
+        self.encode_range(lo, hi - lo, model.get_denominator())
+    }
+
+    /// Finish encoding by writing the low border
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let mut result = Ok(());
+        for i in 0 .. 4 {
+            result = result.and(self.stream.write_u8((self.lo >> (24 - i*8)) as u8).map_err(From::from));
+        }
+        result = result.and(self.stream.flush());
+        (self.stream, result)
+    }
+}
+
+/// Multi-symbol range decoder
+pub struct Decoder<R> {
+    stream: R,
+    lo: Border,
+    range: Border,
+    code: Border,
+    started: bool,
+}
+
+impl<R: io::Read> Decoder<R> {
+    /// Create a new decoder on top of a given reader
+    pub fn new(stream: R) -> Decoder<R> {
+        Decoder {
+            stream: stream,
+            lo: 0,
+            range: !0,
+            code: 0,
+            started: false,
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            for _ in 0 .. 4 {
+                self.code = (self.code << 8) | (try!(self.stream.read_u8()) as Border);
+            }
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    /// Get the frequency offset of the current code out of a total,
+    /// must be followed by `decode_range` with the range found
+    pub fn decode_offset(&mut self, total: Border) -> io::Result<Border> {
+        debug_assert!(total <= MAX_TOTAL);
+        try!(self.start());
+        self.range /= total;
+        let offset = self.code.wrapping_sub(self.lo) / self.range;
+        Ok(if offset < total {offset} else {total - 1})
+    }
+
+    /// Narrow down to the range of the decoded value
+    pub fn decode_range(&mut self, offset: Border, freq: Border) -> io::Result<()> {
+        self.lo = self.lo.wrapping_add(self.range * offset);
+        self.range *= freq;
+        loop {
+            if (self.lo ^ self.lo.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOTTOM {
+                    break
+                }
+                self.range = self.lo.wrapping_neg() & (BOTTOM - 1);
+            }
+            self.code = (self.code << 8) | (try!(self.stream.read_u8()) as Border);
+            self.lo <<= 8;
+            self.range <<= 8;
+        }
+        Ok(())
+    }
+
+    /// Decode a value with a given model
+    pub fn decode<V: Copy + Display, M: ari::Model<V>>(&mut self, model: &M) -> io::Result<V> {
+        let offset = try!(self.decode_offset(model.get_denominator()));
+        let (value, lo, hi) = model.find_value(offset);
+        try!(self.decode_range(lo, hi - lo));
+        Ok(value)
+    }
+
+    /// Finish decoding
+    pub fn finish(mut self) -> (R, io::Result<()>) {
+        let result = self.start();
+        (self.stream, result)
+    }
+}
+
+
+/// Adaptive frequency table with cumulative frequencies
+#[derive(Clone, Debug)]
+pub struct Frequencies {
+    /// cumulative frequencies, starting with 0 and ending with the total
+    cumulative: Vec<Border>,
+    threshold: Border,
+}
+
+impl Frequencies {
+    /// Create a table with all the values equally probable,
+    /// halving the frequencies when the total reaches a threshold
+    pub fn new_flat(num_values: usize, threshold: Border) -> Frequencies {
+        assert!(num_values as Border <= threshold && threshold <= MAX_TOTAL);
+        Frequencies {
+            cumulative: (0 .. num_values as Border + 1).collect(),
+            threshold: threshold,
+        }
+    }
+
+    /// Number of values in the table
+    pub fn len(&self) -> usize {
+        self.cumulative.len() - 1
+    }
+
+    /// Frequency of a value
+    pub fn get(&self, value: usize) -> Border {
+        self.cumulative[value+1] - self.cumulative[value]
+    }
+
+    /// Add to the frequency of a value
+    pub fn update(&mut self, value: usize, add: Border) {
+        for c in self.cumulative[value+1 ..].iter_mut() {
+            *c += add;
+        }
+        if *self.cumulative.last().unwrap() >= self.threshold {
+            self.downscale();
+        }
+    }
+
+    /// Halve the frequencies, keeping them positive
+    pub fn downscale(&mut self) {
+        let (mut old, mut total) = (0, 0);
+        for c in self.cumulative[1 ..].iter_mut() {
+            let freq = *c - old;
+            old = *c;
+            total += (freq + 1) >> 1;
+            *c = total;
+        }
+    }
+}
+
+impl ari::Model<usize> for Frequencies {
+    fn get_range(&self, value: usize) -> (Border, Border) {
+        (self.cumulative[value], self.cumulative[value+1])
+    }
+
+    fn find_value(&self, offset: Border) -> (usize, Border, Border) {
+        assert!(offset < self.get_denominator());
+        // the last value with the cumulative frequency not above the offset
+        let (mut lo, mut hi) = (0, self.len());
+        while hi - lo > 1 {
+            let mid = (lo + hi) >> 1;
+            if self.cumulative[mid] <= offset {
+                lo = mid;
+            }else {
+                hi = mid;
+            }
+        }
+        (lo, self.cumulative[lo], self.cumulative[lo+1])
+    }
+
+    fn get_denominator(&self) -> Border {
+        *self.cumulative.last().unwrap()
+    }
+}
+
+
+impl<W: io::Write> super::BitEncoder for Encoder<W> {
+    fn encode_bit<M: ari::Model<bool>>(&mut self, bit: bool, model: &M) -> io::Result<()> {
+        self.encode(bit, model)
+    }
+
+    fn encode_symbol<M: ari::Model<usize>>(&mut self, value: usize, model: &M) -> io::Result<()> {
+        self.encode(value, model)
+    }
+}
+
+impl<R: io::Read> super::BitDecoder for Decoder<R> {
+    fn decode_bit<M: ari::Model<bool>>(&mut self, model: &M) -> io::Result<bool> {
+        self.decode(model)
+    }
+
+    fn decode_symbol<M: ari::Model<usize>>(&mut self, model: &M) -> io::Result<usize> {
+        self.decode(model)
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    #[cfg(feature="unstable")]
+    use test::Bencher;
+    use rand::{Rng, SeedableRng, StdRng};
+    use compress::entropy::ari;
+    use compress::entropy::ari::Model;
+
+    const NUM_EXPONENTS: usize = 33;
+    const THRESHOLD: super::Border = 1<<13;
+
+    /// Exponents of geometrically distributed distances
+    fn gen_exponents(count: usize) -> Vec<usize> {
+        let seed: &[_] = &[1, 2, 3, 4];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        (0 .. count).map(|_| {
+            let mut e = 0;
+            while e+1 < NUM_EXPONENTS && rng.gen_range(0, 10) < 7 {
+                e += 1;
+            }
+            e
+        }).collect()
+    }
+
+    fn encode_table(input: &[usize]) -> Vec<u8> {
+        let mut model = super::Frequencies::new_flat(NUM_EXPONENTS, THRESHOLD);
+        let mut eh = super::Encoder::new(Vec::new());
+        for &e in input.iter() {
+            eh.encode(e, &model).unwrap();
+            model.update(e, 32);
+        }
+        let (buf, err) = eh.finish();
+        err.unwrap();
+        buf
+    }
+
+    /// The same values coded bit by bit with the binary range coder,
+    /// as a binary tree of adaptive bit probabilities
+    fn encode_binary(input: &[usize]) -> Vec<u8> {
+        let mut bits = vec![ari::apm::Bit::new_equal(); 64];
+        let mut eh = ::entropy::Encoder::new(Vec::new());
+        for &e in input.iter() {
+            let mut node = 1;
+            for i in (0 .. 6).rev() {
+                let bit = (e >> i) & 1;
+                eh.encode(bit as ::entropy::ari::Bit, bits[node]).unwrap();
+                bits[node].update(bit != 0, 5, 0);
+                node = (node << 1) | bit;
+            }
+        }
+        let (buf, err) = eh.finish();
+        err.unwrap();
+        buf
+    }
+
+    #[test]
+    fn roundtrip_frequencies() {
+        let input = gen_exponents(10000);
+        let buf = encode_table(&input);
+        let mut model = super::Frequencies::new_flat(NUM_EXPONENTS, THRESHOLD);
+        let mut dh = super::Decoder::new(io::Cursor::new(&buf[..]));
+        for &e in input.iter() {
+            assert_eq!(dh.decode(&model).unwrap(), e);
+            model.update(e, 32);
+        }
+        dh.finish().1.unwrap();
+    }
+
+    #[test]
+    fn roundtrip_models() {
+        // tables of `compress` and bit probabilities work as well
+        let mut rng = StdRng::new().unwrap();
+        let input: Vec<(usize, bool)> = (0 .. 10000).map(|_|
+            (rng.gen_range(0, 10) * rng.gen_range(0, 10) % 20, rng.gen_weighted_bool(5))
+            ).collect();
+        let mut table = ari::table::Model::new_flat(20, ari::RANGE_DEFAULT_THRESHOLD);
+        let mut bit = ari::apm::Bit::new_equal();
+        let mut eh = super::Encoder::new(Vec::new());
+        for &(v, b) in input.iter() {
+            eh.encode(v, &table).unwrap();
+            table.update(v, 5, 1);
+            eh.encode(b, &bit).unwrap();
+            bit.update(b, 4, 0);
+        }
+        let (buf, err) = eh.finish();
+        err.unwrap();
+        let mut table = ari::table::Model::new_flat(20, ari::RANGE_DEFAULT_THRESHOLD);
+        let mut bit = ari::apm::Bit::new_equal();
+        let mut dh = super::Decoder::new(io::Cursor::new(&buf[..]));
+        for &(v, b) in input.iter() {
+            assert_eq!(dh.decode(&table).unwrap(), v);
+            table.update(v, 5, 1);
+            assert_eq!(dh.decode(&bit).unwrap(), b);
+            bit.update(b, 4, 0);
+        }
+    }
+
+    #[test]
+    fn frequencies() {
+        let mut model = super::Frequencies::new_flat(5, 100);
+        model.update(1, 10);
+        model.downscale();
+        assert_eq!((0 .. 5).map(|i| model.get(i)).collect::<Vec<_>>(), vec![1, 6, 1, 1, 1]);
+        for i in 0 .. 200 {
+            model.update(i % 3, 4);
+            assert!(model.get_denominator() < 100);
+            for offset in 0 .. model.get_denominator() {
+                let (value, lo, hi) = model.find_value(offset);
+                assert!(lo <= offset && offset < hi);
+                assert_eq!(model.get_range(value), (lo, hi));
+            }
+        }
+        assert!(model.get(4) > 0);
+    }
+
+    #[test]
+    fn compare_binary() {
+        let input = gen_exponents(100000);
+        let table = encode_table(&input).len();
+        let binary = encode_binary(&input).len();
+        // both are close to the entropy, within a few percent of each other
+        assert!(table * 100 < binary * 105 && binary * 100 < table * 105,
+            "table {} vs binary {}", table, binary);
+    }
+
+    #[cfg(feature="unstable")]
+    #[bench]
+    fn encode_exponents_table(bh: &mut Bencher) {
+        let input = gen_exponents(1<<14);
+        bh.iter(|| encode_table(&input));
+        bh.bytes = input.len() as u64;
+    }
+
+    #[cfg(feature="unstable")]
+    #[bench]
+    fn encode_exponents_binary(bh: &mut Bencher) {
+        let input = gen_exponents(1<<14);
+        bh.iter(|| encode_binary(&input));
+        bh.bytes = input.len() as u64;
+    }
+}
@@ -0,0 +1,356 @@
+/*!
+
+Compressed stream header
+
+*/
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+
+use alphabet::Order;
+use entropy::Coder;
+
+
+/// Model names, indexed by their identifiers stored in the header
+pub const MODELS: &'static [&'static str] = &[
+    "exp", "dark", "ybs", "simple", "bbb", "raw", "rawdc", "qlfc", "mtf", "mtf1", "wfc",
+    "mix", "fixed", "dict",
+];
+
+/// Model identifier flag, signalling that model parameters follow
+const PARAMETERS_FLAG: u8 = 0x80;
+/// Model identifier flag, signalling that the block layout follows
+const BLOCKS_FLAG: u8 = 0x40;
+/// Block layout flag: the blocks continue with the previous statistics
+const CONTINUED_FLAG: u8 = 0x1;
+/// Block layout flag: the models are primed, the primer checksum follows
+const PRIMED_FLAG: u8 = 0x2;
+/// Block layout flag: the blocks are coded with a dictionary, its identifier follows
+const DICTIONARY_FLAG: u8 = 0x4;
+/// Block layout bits holding the identifier of the binary entropy coder
+const CODER_SHIFT: u8 = 4;
+
+/// Signature of the archives produced by Dark-0.51
+pub const LEGACY_SIGNATURE: &'static [u8] = b"!dark";
+
+/// Find the identifier of a model by name
+pub fn model_id(name: &str) -> Option<u8> {
+    MODELS.iter().position(|&m| m == name).map(|id| id as u8)
+}
+
+/// Stream header, preceding the encoded block
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// Original block size
+    pub size    : usize,
+    /// Symbol order used for the BWT
+    pub order   : Order,
+    /// Compression model identifier
+    pub model   : u8,
+    /// Model parameter values, empty for the defaults
+    pub parameters  : Vec<isize>,
+    /// Maximum block size, zero if the input is coded as a single block
+    pub block_size  : usize,
+    /// Blocks continue with the model statistics of the previous ones
+    pub continued   : bool,
+    /// Checksum of the primer the models start from, if any
+    pub primer      : Option<u32>,
+    /// Identifier of the dictionary the blocks are coded with, if any
+    pub dictionary  : Option<u32>,
+    /// Binary entropy coder of the stream
+    pub coder       : Coder,
+}
+
+impl Header {
+    /// Return the compression model name
+    pub fn model_name(&self) -> &'static str {
+        MODELS[self.model as usize]
+    }
+
+    /// Return the size of the largest block
+    pub fn block_capacity(&self) -> usize {
+        if self.block_size != 0 && self.block_size < self.size {
+            self.block_size
+        }else {
+            self.size
+        }
+    }
+
+    /// Read the header from a given reader
+    pub fn read<R: io::Read>(reader: &mut R) -> io::Result<Header> {
+        let size = try!(reader.read_u32::<LittleEndian>()) as usize;
+        let order_id = try!(reader.read_u8());
+        let order = match Order::from_id(order_id) {
+            Some(order) => order,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                "unknown alphabet order")),
+        };
+        let model_id = try!(reader.read_u8());
+        let model = model_id & !(PARAMETERS_FLAG | BLOCKS_FLAG);
+        if model as usize >= MODELS.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                "unknown compression model"))
+        }
+        let mut parameters = Vec::new();
+        if model_id & PARAMETERS_FLAG != 0 {
+            let count = try!(reader.read_u8());
+            for _ in 0 .. count {
+                parameters.push(try!(reader.read_u8()) as isize);
+            }
+        }
+        let (mut block_size, mut continued, mut primer, mut dictionary) = (0, false, None, None);
+        let mut coder = Coder::Range;
+        if model_id & BLOCKS_FLAG != 0 {
+            block_size = try!(reader.read_u32::<LittleEndian>()) as usize;
+            let flags = try!(reader.read_u8());
+            continued = flags & CONTINUED_FLAG != 0;
+            if flags & PRIMED_FLAG != 0 {
+                primer = Some(try!(reader.read_u32::<LittleEndian>()));
+            }
+            if flags & DICTIONARY_FLAG != 0 {
+                dictionary = Some(try!(reader.read_u32::<LittleEndian>()));
+            }
+            coder = match Coder::from_id(flags >> CODER_SHIFT) {
+                Some(coder) => coder,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    "unknown entropy coder")),
+            };
+        }
+        Ok(Header {
+            size    : size,
+            order   : order,
+            model   : model,
+            parameters  : parameters,
+            block_size  : block_size,
+            continued   : continued,
+            primer      : primer,
+            dictionary  : dictionary,
+            coder       : coder,
+        })
+    }
+
+    /// Write the header into a given writer
+    pub fn write<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        try!(writer.write_u32::<LittleEndian>(self.size as u32));
+        try!(writer.write_u8(self.order.to_id()));
+        let has_blocks = self.block_size != 0 || self.continued ||
+            self.primer.is_some() || self.dictionary.is_some() || self.coder != Coder::Range;
+        let flags = if has_blocks {BLOCKS_FLAG} else {0};
+        if self.parameters.is_empty() {
+            try!(writer.write_u8(self.model | flags));
+        }else {
+            assert!(self.parameters.len() <= 0xFF);
+            try!(writer.write_u8(self.model | flags | PARAMETERS_FLAG));
+            try!(writer.write_u8(self.parameters.len() as u8));
+            for &value in self.parameters.iter() {
+                assert!(value >= 0 && value <= 0xFF, "parameter {} does not fit a byte", value);
+                try!(writer.write_u8(value as u8));
+            }
+        }
+        if has_blocks {
+            try!(writer.write_u32::<LittleEndian>(self.block_size as u32));
+            let mut layout = self.coder.to_id() << CODER_SHIFT;
+            if self.continued {
+                layout |= CONTINUED_FLAG;
+            }
+            if self.primer.is_some() {
+                layout |= PRIMED_FLAG;
+            }
+            if self.dictionary.is_some() {
+                layout |= DICTIONARY_FLAG;
+            }
+            try!(writer.write_u8(layout));
+            if let Some(checksum) = self.primer {
+                try!(writer.write_u32::<LittleEndian>(checksum));
+            }
+            if let Some(id) = self.dictionary {
+                try!(writer.write_u32::<LittleEndian>(id));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Check if a stream starts with the Dark-0.51 signature, without consuming it
+pub fn is_legacy<R: io::BufRead>(reader: &mut R) -> io::Result<bool> {
+    let buf = try!(reader.fill_buf());
+    Ok(buf.starts_with(LEGACY_SIGNATURE))
+}
+
+/// Dark-0.51 archive header, describing a single file
+#[derive(Clone, Debug, PartialEq)]
+pub struct LegacyHeader {
+    /// Maximum block size
+    pub block_size  : usize,
+    /// Original file name
+    pub name        : String,
+    /// Original file attributes
+    pub attributes  : u32,
+    /// Original file size
+    pub size        : usize,
+}
+
+impl LegacyHeader {
+    /// Read the header from a given reader, including the signature
+    pub fn read<R: io::Read>(reader: &mut R) -> io::Result<LegacyHeader> {
+        let mut sign = [0u8; 5];
+        for b in sign.iter_mut() {
+            *b = try!(reader.read_u8());
+        }
+        if &sign[..] != LEGACY_SIGNATURE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                "invalid Dark-0.51 signature"))
+        }
+        let block_size = try!(reader.read_u32::<LittleEndian>()) as usize;
+        let mut name = Vec::new();
+        loop {
+            match try!(reader.read_u8()) {
+                0 => break,
+                b => name.push(b),
+            }
+        }
+        let attributes = try!(reader.read_u32::<LittleEndian>());
+        let size = try!(reader.read_u32::<LittleEndian>()) as usize;
+        Ok(LegacyHeader {
+            block_size  : block_size,
+            name        : String::from_utf8_lossy(&name).into_owned(),
+            attributes  : attributes,
+            size        : size,
+        })
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    use alphabet::Order;
+    use entropy::Coder;
+    use super::{Header, LegacyHeader, is_legacy, model_id};
+
+    #[test]
+    fn roundtrip() {
+        let header = Header {
+            size    : 12345,
+            order   : Order::Text,
+            model   : model_id("wfc").unwrap(),
+            parameters  : Vec::new(),
+            block_size  : 0,
+            continued   : false,
+            primer      : None,
+            dictionary  : None,
+            coder       : Coder::Range,
+        };
+        let mut buf = Vec::new();
+        header.write(&mut buf).unwrap();
+        let header2 = Header::read(&mut io::Cursor::new(&buf[..])).unwrap();
+        assert_eq!(header, header2);
+        assert_eq!(header2.model_name(), "wfc");
+    }
+
+    #[test]
+    fn parameters() {
+        let header = Header {
+            size    : 1000,
+            order   : Order::Natural,
+            model   : model_id("ybs").unwrap(),
+            parameters  : vec![10, 7, 0, 255],
+            block_size  : 0,
+            continued   : false,
+            primer      : None,
+            dictionary  : None,
+            coder       : Coder::Range,
+        };
+        let mut buf = Vec::new();
+        header.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), 4+1+1+1+4);
+        let header2 = Header::read(&mut io::Cursor::new(&buf[..])).unwrap();
+        assert_eq!(header, header2);
+        assert_eq!(header2.model_name(), "ybs");
+    }
+
+    #[test]
+    fn blocks() {
+        let header = Header {
+            size    : 100000,
+            order   : Order::Natural,
+            model   : model_id("dark").unwrap(),
+            parameters  : vec![1, 2],
+            block_size  : 1<<12,
+            continued   : true,
+            primer      : Some(0xDEADBEEF),
+            dictionary  : None,
+            coder       : Coder::Range,
+        };
+        let mut buf = Vec::new();
+        header.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), 4+1+1+1+2+4+1+4);
+        let header2 = Header::read(&mut io::Cursor::new(&buf[..])).unwrap();
+        assert_eq!(header, header2);
+        assert_eq!(header2.model_name(), "dark");
+    }
+
+    #[test]
+    fn dictionary() {
+        let header = Header {
+            size    : 300,
+            order   : Order::Text,
+            model   : model_id("dict").unwrap(),
+            parameters  : Vec::new(),
+            block_size  : 0,
+            continued   : false,
+            primer      : None,
+            dictionary  : Some(0x12345678),
+            coder       : Coder::Range,
+        };
+        let mut buf = Vec::new();
+        header.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), 4+1+1+4+1+4);
+        let header2 = Header::read(&mut io::Cursor::new(&buf[..])).unwrap();
+        assert_eq!(header, header2);
+    }
+
+    #[test]
+    fn coder() {
+        let header = Header {
+            size    : 5000,
+            order   : Order::Natural,
+            model   : model_id("bbb").unwrap(),
+            parameters  : Vec::new(),
+            block_size  : 0,
+            continued   : false,
+            primer      : None,
+            dictionary  : None,
+            coder       : Coder::Rans,
+        };
+        let mut buf = Vec::new();
+        header.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), 4+1+1+4+1);
+        let header2 = Header::read(&mut io::Cursor::new(&buf[..])).unwrap();
+        assert_eq!(header, header2);
+        buf[10] |= 0xF0;
+        assert!(Header::read(&mut io::Cursor::new(&buf[..])).is_err());
+    }
+
+    #[test]
+    fn legacy() {
+        let buf = b"!dark\x00\x00\x40\x00book1\x00\xa4\x81\x00\x00\x3b\x04\x00\x00";
+        let mut reader = io::BufReader::new(&buf[..]);
+        assert!(is_legacy(&mut reader).unwrap());
+        let header = LegacyHeader::read(&mut reader).unwrap();
+        assert_eq!(header, LegacyHeader {
+            block_size  : 1<<22,
+            name        : "book1".to_string(),
+            attributes  : 0o100644,
+            size        : 1083,
+        });
+        let mut buf = Vec::new();
+        Header {
+            size: 1, order: Order::Natural, model: 0, parameters: Vec::new(),
+            block_size: 0, continued: false, primer: None, dictionary: None,
+            coder: Coder::Range,
+        }.write(&mut buf).unwrap();
+        assert!(!is_legacy(&mut &buf[..]).unwrap());
+    }
+}
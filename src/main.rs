@@ -1,4 +1,5 @@
 #![deny(missing_docs)]
+#![cfg_attr(feature="unstable", feature(test))]
 
 //! Dark compressor prototype
 
@@ -11,30 +12,466 @@ extern crate log;
 extern crate num;
 #[cfg(test)]
 extern crate rand;
+#[cfg(all(test, feature="unstable"))]
+extern crate test;
 
-use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
-use std::{env, io};
-use std::fs::File;
+use std::{cmp, env, io, panic, process};
+use std::any::Any;
+use std::fs::{self, File};
 use std::path;
-use model::Model;
 
+/// Alphabet reordering
+pub mod alphabet;
+/// Analysis of the DC dumps
+pub mod analyze;
+/// Corpus benchmark
+pub mod bench;
 /// Block encoding/decoding logic
 pub mod block;
+/// Synthetic corpus for the ratio tests
+pub mod corpus;
+/// Dictionaries for small similar inputs
+pub mod dict;
 /// Entropy compression (last stage)
 pub mod entropy;
+/// Compressed stream header
+pub mod header;
 /// Compression models
 pub mod model;
+/// Model priming from trained statistics
+pub mod primer;
 /// Suffix Array Construction Algorithm (SACA)
 pub mod saca;
+/// Model parameter tuning
+pub mod tune;
 
 const EXTENSION: &'static str = "dark";
+const WFC_DECAY: u32 = 3;
+const BBB_EXTENSION: &'static str = "bbb";
+/// Models with block coders that can't switch the entropy coder
+const RANGE_ONLY_MODELS: &'static [&'static str] = &["dict", "mtf", "mtf1", "qlfc", "wfc"];
 
 
+#[cfg(feature="tune")]
+fn tune(model: &str, order: alphabet::Order, paths: &[String]) {
+    tune::run(model, order, paths).unwrap();
+}
+
+#[cfg(not(feature="tune"))]
+fn tune(_model: &str, _order: alphabet::Order, _paths: &[String]) {
+    println!("Tuning is not available, rebuild with `--features tune`");
+}
+
+fn unsupported_coder(header: &header::Header) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput,
+        format!("{} coder is not supported by model {}", header.coder, header.model_name()))
+}
+
+/// Encode an input by a block coder, following the block layout of the header
+fn encode_with<E, W>(mut coder: E, input: &[u8], header: &header::Header,
+               primer: Option<&primer::Primer>, stats: bool, writer: W) -> (W, io::Result<()>)
+    where E: block::Encoder + block::Reusable, W: io::Write
+{
+    if !coder.set_coder(header.coder) {
+        return (writer, Err(unsupported_coder(header)))
+    }
+    if stats && !coder.gather_stats() {
+        println!("Statistics are not gathered by the {} model", header.model_name());
+    }
+    if let Some(p) = primer {
+        if let Err(e) = coder.state().load(&mut &p.state[..]) {
+            return (writer, Err(e))
+        }
+    }
+    let result = if header.block_size == 0 {
+        coder.encode(input, writer)
+    }else {
+        block::multi::encode(&mut coder, input, header.block_size, header.continued, writer)
+    };
+    if let Some(report) = coder.stats() {
+        print!("{}", report);
+    }
+    result
+}
+
+/// Decode a stream by a block coder, following the block layout of the header
+fn decode_with<D, R, W>(mut coder: D, header: &header::Header, primer: Option<&primer::Primer>,
+               reader: R, writer: W) -> (R, W, io::Result<()>)
+    where D: block::Decoder + block::Reusable, R: io::Read, W: io::Write
+{
+    if !coder.set_coder(header.coder) {
+        return (reader, writer, Err(unsupported_coder(header)))
+    }
+    if let Some(p) = primer {
+        if let Err(e) = coder.state().load(&mut &p.state[..]) {
+            return (reader, writer, Err(e))
+        }
+    }
+    if header.block_size == 0 {
+        coder.decode(reader, writer)
+    }else {
+        block::multi::decode(&mut coder, header.size, header.block_size, header.continued, reader, writer)
+    }
+}
+
+/// Encode an input by the model of the header
+fn encode<W: io::Write>(header: &header::Header, input: &[u8], primer: Option<&primer::Primer>,
+          dictionary: Option<dict::Dictionary>, stats: bool, dump: Option<String>, writer: W)
+          -> (W, io::Result<()>)
+{
+    let values = &header.parameters;
+    let cap = header.block_capacity();
+    match header.model_name() {
+        "bbb"   => encode_with(block::raw::Encoder::new(cap, model::bbb::Model  ::new()), input, header, primer, stats, writer),
+        "dark"  => encode_with(block::dc::Encoder::new(cap, model::dark::Model::new_custom(tune::load(values).unwrap())), input, header, primer, stats, writer),
+        "dict"  => encode_with(block::dict::Encoder::new(dictionary.unwrap(), model::dict::Model::new()), input, header, primer, stats, writer),
+        "exp"   => encode_with(block::dc::Encoder::new(cap, model::exp::Model::new_custom(tune::load(values).unwrap())), input, header, primer, stats, writer),
+        "fixed" => {
+            // first pass to gather the statistics of every block
+            let mut model = model::fixed::Model::new();
+            for chunk in input.chunks(cmp::max(cap, 1)) {
+                model.start_block();
+                block::dc::transform(chunk, &mut saca::Constructor::new(chunk.len()),
+                    &mut compress::bwt::mtf::MTF::new(), |d, ctx| model.gather(d, ctx));
+            }
+            encode_with(block::dc::Encoder::new(cap, model), input, header, primer, stats, writer)
+        },
+        "mix"   => encode_with(block::dc::Encoder::new(cap, model::mix::Model   ::new()), input, header, primer, stats, writer),
+        "mtf"   => encode_with(block::rank::Encoder::new(cap, block::rank::Mtf::new(),  model::qlfc::Model::new()), input, header, primer, stats, writer),
+        "mtf1"  => encode_with(block::rank::Encoder::new(cap, block::rank::Mtf1::new(), model::qlfc::Model::new()), input, header, primer, stats, writer),
+        "qlfc"  => encode_with(block::qlfc::Encoder::new(cap, model::qlfc::Model::new()), input, header, primer, stats, writer),
+        "raw"   => encode_with(block::raw::Encoder::new(cap, model::raw::Out::new(open_dump(dump).unwrap())), input, header, primer, stats, writer),
+        "rawdc" => encode_with(block::dc::Encoder::new(cap, model::raw::DcOut::new(open_dump(dump).unwrap())), input, header, primer, stats, writer),
+        "simple"=> encode_with(block::dc::Encoder::new(cap, model::simple::Model::new_custom(tune::load(values).unwrap())), input, header, primer, stats, writer),
+        "wfc"   => encode_with(block::rank::Encoder::new(cap, block::rank::Wfc::new(WFC_DECAY), model::qlfc::Model::new()), input, header, primer, stats, writer),
+        "ybs"   => encode_with(block::dc::Encoder::new(cap, model::ybs::Model::new_custom(tune::load(values).unwrap())), input, header, primer, stats, writer),
+        _       => panic!("Unknown encoding model: {}", header.model_name())
+    }
+}
+
+/// Decode a stream by the model of the header, returning the output in the coded alphabet
+fn decode<R: io::Read>(header: &header::Header, primer: Option<&primer::Primer>,
+          dictionary: Option<dict::Dictionary>, dump: Option<String>, reader: R)
+          -> (Vec<u8>, io::Result<()>)
+{
+    let values = &header.parameters;
+    let cap = header.block_capacity();
+    let out = Vec::with_capacity(header.size);
+    let (_, output, err) = match header.model_name() {
+        "bbb"   => decode_with(block::raw::Decoder::new(cap, model::bbb::Model  ::new()), &header, primer, reader, out),
+        "dark"  => decode_with(block::dc::Decoder::new(cap, model::dark::Model::new_custom(tune::load(values).unwrap())), &header, primer, reader, out),
+        "dict"  => decode_with(block::dict::Decoder::new(cap, dictionary.unwrap(), model::dict::Model::new()), &header, primer, reader, out),
+        "exp"   => decode_with(block::dc::Decoder::new(cap, model::exp::Model::new_custom(tune::load(values).unwrap())), &header, primer, reader, out),
+        "fixed" => decode_with(block::dc::Decoder::new(cap, model::fixed::Model ::new()), &header, primer, reader, out),
+        "mix"   => decode_with(block::dc::Decoder::new(cap, model::mix::Model   ::new()), &header, primer, reader, out),
+        "mtf"   => decode_with(block::rank::Decoder::new(cap, block::rank::Mtf::new(),  model::qlfc::Model::new()), &header, primer, reader, out),
+        "mtf1"  => decode_with(block::rank::Decoder::new(cap, block::rank::Mtf1::new(), model::qlfc::Model::new()), &header, primer, reader, out),
+        "qlfc"  => decode_with(block::qlfc::Decoder::new(cap, model::qlfc::Model::new()), &header, primer, reader, out),
+        "raw"   => decode_with(block::raw::Decoder::new(cap, model::raw::Out::new(open_dump(dump).unwrap())), &header, primer, reader, out),
+        "rawdc" => decode_with(block::dc::Decoder::new(cap, model::raw::DcOut::new(open_dump(dump).unwrap())), &header, primer, reader, out),
+        "simple"=> decode_with(block::dc::Decoder::new(cap, model::simple::Model::new_custom(tune::load(values).unwrap())), &header, primer, reader, out),
+        "wfc"   => decode_with(block::rank::Decoder::new(cap, block::rank::Wfc::new(WFC_DECAY), model::qlfc::Model::new()), &header, primer, reader, out),
+        "ybs"   => decode_with(block::dc::Decoder::new(cap, model::ybs::Model::new_custom(tune::load(values).unwrap())), &header, primer, reader, out),
+        _       => panic!("Unknown decoding model: {}", header.model_name())
+    };
+    (output, err)
+}
+
+/// Read a primer file, checking that it belongs to the model
+fn read_primer(path: &str, model: &str) -> io::Result<primer::Primer> {
+    let mut file = io::BufReader::new(try!(File::open(path)));
+    let primer = try!(primer::Primer::read(&mut file));
+    if header::model_id(model) != Some(primer.model) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("primer is trained for model {}", header::MODELS[primer.model as usize])))
+    }
+    Ok(primer)
+}
+
+/// Read a dictionary file, returning its identifier and the dictionary
+/// in the given symbol order
+fn load_dictionary(path: &str, order: alphabet::Order) -> io::Result<(u32, dict::Dictionary)> {
+    let mut data = try!(dict::read(&mut try!(File::open(path))));
+    let id = dict::id(&data);
+    alphabet::Permutation::new(order).encode(&mut data);
+    Ok((id, dict::Dictionary::new(data)))
+}
+
+/// Open the dump of the raw models, discarding the values if no path is given
+fn open_dump(path: Option<String>) -> io::Result<Box<io::Write>> {
+    Ok(match path {
+        Some(path) => Box::new(io::BufWriter::new(try!(File::create(&path)))),
+        None => Box::new(io::sink()),
+    })
+}
+
+/// Run an analysis method on a `rawdc` dump, printing the results
+fn analyze(method: &str, path: &str, args: &[String]) -> io::Result<()> {
+    let reader = model::raw::Reader::new(io::BufReader::new(try!(File::open(path))));
+    let values = try!(analyze::read_values(reader));
+    info!("Got {} values from {}", values.len(), path);
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    match method {
+        "cell"  => {
+            let threshold = match args.first().map(|value| value.parse()) {
+                Some(Ok(threshold)) => threshold,
+                _ => panic!("The cell method needs a merging threshold")
+            };
+            analyze::cell::process(&values, threshold, &mut out)
+        },
+        "cluster" => {
+            let sizes: Vec<usize> = args.iter().map(|value| match value.parse() {
+                Ok(size) => size,
+                Err(_) => panic!("Invalid number of groups: {}", value)
+            }).collect();
+            analyze::cluster::process(&values, &sizes, &mut out)
+        },
+        "print" => analyze::print(&values, &mut out),
+        "report" => {
+            let dir = match args.first() {
+                Some(dir) => dir,
+                None => panic!("The report method needs an output directory")
+            };
+            analyze::report::Report::new(&values).write_all(path::Path::new(dir))
+        },
+        "stat"  => analyze::stat::process(&values, &mut out),
+        _       => panic!("Unknown analysis method: {}", method)
+    }
+}
+
+/// Encode and decode an input in the given symbol order by a model,
+/// verifying the round trip, and return the sizes and timings
+fn measure(model: &str, name: &str, input: &[u8], order: alphabet::Order, coder: entropy::Coder,
+           block_size: usize) -> io::Result<bench::Entry> {
+    use std::time::Instant;
+    let header = header::Header {
+        size    : input.len(),
+        order   : order,
+        model   : match header::model_id(model) {
+            Some(id) => id,
+            None => panic!("Unknown benchmark model: {}", model)
+        },
+        parameters  : Vec::new(),
+        block_size  : block_size,
+        continued   : false,
+        primer      : None,
+        dictionary  : None,
+        coder       : coder,
+    };
+    let start = Instant::now();
+    let mut compressed = Vec::new();
+    try!(header.write(&mut compressed));
+    let (compressed, err) = encode(&header, input, None, None, false, None, compressed);
+    try!(err);
+    let encode_time = bench::get_seconds(start.elapsed());
+    let start = Instant::now();
+    let mut reader = &compressed[..];
+    let header = try!(header::Header::read(&mut reader));
+    let (output, err) = decode(&header, None, None, None, reader);
+    try!(err);
+    let decode_time = bench::get_seconds(start.elapsed());
+    if &output[..] != input {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("roundtrip of model {} failed on {}", model, name)))
+    }
+    Ok(bench::Entry {
+        file        : name.to_string(),
+        model       : model.to_string(),
+        size        : input.len(),
+        compressed  : compressed.len(),
+        encode_time : encode_time,
+        decode_time : decode_time,
+    })
+}
+
+/// Text of a caught panic, when it has one
+fn panic_message(payload: &Box<Any + Send>) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(text) => text.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(text) => text.clone(),
+            None => "unknown cause".to_string(),
+        },
+    }
+}
+
+/// Run the models over every file of a directory, verifying the round trip,
+/// and print the results, exporting them into a JSON file if given.
+/// Failures are reported per file and model, and their number is returned.
+fn bench(models: &[String], order: alphabet::Order, coder: entropy::Coder, block_size: usize,
+         dir: &str, json: Option<String>) -> io::Result<usize> {
+    use std::io::Read;
+    let mut paths = Vec::new();
+    for entry in try!(fs::read_dir(dir)) {
+        let path = try!(entry).path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut entries = Vec::new();
+    let mut failures = Vec::new();
+    for path in paths.iter() {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let mut input = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut input));
+        if input.is_empty() {
+            info!("Skipping empty {}", name);
+            continue
+        }
+        alphabet::Permutation::new(order).encode(&mut input);
+        for model in models.iter() {
+            // the block coders panic on some failures, which are reported as errors
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                measure(model, &name, &input, order, coder, block_size)
+            })).unwrap_or_else(|payload| Err(io::Error::new(io::ErrorKind::Other,
+                format!("coder panicked: {}", panic_message(&payload)))));
+            match result {
+                Ok(entry) => {
+                    info!("Model {} packed {} into {} bytes", model, name, entry.compressed);
+                    entries.push(entry);
+                },
+                Err(e) => failures.push(format!("Model {} failed on {}: {}", model, name, e)),
+            }
+        }
+    }
+    let stdout = io::stdout();
+    try!(bench::write_table(&entries, &mut stdout.lock()));
+    for failure in failures.iter() {
+        println!("{}", failure);
+    }
+    if let Some(path) = json {
+        let mut out = io::BufWriter::new(try!(File::create(&path)));
+        try!(bench::write_json(&entries, &mut out));
+    }
+    Ok(failures.len())
+}
+
+/// Code the distances of a `rawdc` dump by a model, printing their cost
+fn replay(model: &str, path: &str) -> io::Result<()> {
+    use model::raw::Reader;
+    let open = || File::open(path).map(|file| Reader::new(io::BufReader::new(file)));
+    let cost = try!(match model {
+        "dark"  => model::raw::replay(&mut model::dark::Model  ::new(), try!(open())),
+        "exp"   => model::raw::replay(&mut model::exp::Model   ::new(), try!(open())),
+        "fixed" => {
+            let mut model = model::fixed::Model::new();
+            for record in try!(open()) {
+                let (d, ctx) = try!(record);
+                model.gather(d, &ctx);
+            }
+            model::raw::replay(&mut model, try!(open()))
+        },
+        "mix"   => model::raw::replay(&mut model::mix::Model   ::new(), try!(open())),
+        "simple"=> model::raw::replay(&mut model::simple::Model::new(), try!(open())),
+        "ybs"   => model::raw::replay(&mut model::ybs::Model   ::new(), try!(open())),
+        _       => panic!("Model {} can not replay distances", model)
+    });
+    println!("{} distances, {:.1} bytes, {:.3} bits per distance", cost.count,
+        cost.bits / 8.0, if cost.count != 0 {cost.bits / cost.count as f64} else {0.0});
+    Ok(())
+}
+
+/// Build a dictionary from sample files, writing it into the first path
+fn train(size: usize, paths: &[String]) -> io::Result<()> {
+    use std::io::Read;
+    let mut samples = Vec::new();
+    for path in paths[1..].iter() {
+        let mut sample = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut sample));
+        samples.push(sample);
+    }
+    let data = dict::train(&samples, size);
+    let mut out_file = io::BufWriter::new(try!(File::create(&paths[0])));
+    try!(dict::write(&data, &mut out_file));
+    info!("Dictionary {} of {} bytes is written, id: {:x}", paths[0], data.len(), dict::id(&data));
+    Ok(())
+}
+
+/// Train a model on sample files, writing the primer into the first path
+fn learn(model: &str, order: alphabet::Order, paths: &[String]) -> io::Result<()> {
+    use std::io::Read;
+    let model_id = match header::model_id(model) {
+        Some(id) => id,
+        None => panic!("Unknown learning model: {}", model)
+    };
+    let mut inputs = Vec::new();
+    for path in paths[1..].iter() {
+        let mut input = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut input));
+        if !input.is_empty() {
+            alphabet::Permutation::new(order).encode(&mut input);
+            inputs.push(input);
+        }
+    }
+    info!("Learning model {} on {} samples", model, inputs.len());
+    let state = try!(match model {
+        "bbb"   => primer::learn(block::raw::Encoder::new(1, model::bbb::Model  ::new()), &inputs),
+        "dark"  => primer::learn(block::dc::Encoder::new(1, model::dark::Model  ::new()), &inputs),
+        "exp"   => primer::learn(block::dc::Encoder::new(1, model::exp::Model   ::new()), &inputs),
+        "mix"   => primer::learn(block::dc::Encoder::new(1, model::mix::Model   ::new()), &inputs),
+        "mtf"   => primer::learn(block::rank::Encoder::new(1, block::rank::Mtf::new(),  model::qlfc::Model::new()), &inputs),
+        "mtf1"  => primer::learn(block::rank::Encoder::new(1, block::rank::Mtf1::new(), model::qlfc::Model::new()), &inputs),
+        "qlfc"  => primer::learn(block::qlfc::Encoder::new(1, model::qlfc::Model::new()), &inputs),
+        "simple"=> primer::learn(block::dc::Encoder::new(1, model::simple::Model::new()), &inputs),
+        "wfc"   => primer::learn(block::rank::Encoder::new(1, block::rank::Wfc::new(WFC_DECAY), model::qlfc::Model::new()), &inputs),
+        "ybs"   => primer::learn(block::dc::Encoder::new(1, model::ybs::Model   ::new()), &inputs),
+        _       => panic!("Model {} has no statistics to learn", model)
+    });
+    let primer = primer::Primer {
+        model   : model_id,
+        state   : state,
+    };
+    let mut out_file = io::BufWriter::new(try!(File::create(&paths[0])));
+    try!(primer.write(&mut out_file));
+    info!("Primer {} is written, checksum: {:x}", paths[0], primer.checksum());
+    Ok(())
+}
+
+/// Encode or decode a file in the format of `etc/bbb`
+fn run_bbb(input_path: &path::Path) -> io::Result<()> {
+    use std::io::{Read, Write};
+    use block::{Decoder, Encoder};
+    let mut out_path = path::PathBuf::new();
+    out_path.set_file_name(input_path.file_name().unwrap());
+    let in_file = try!(File::open(input_path));
+    if input_path.extension().map_or(false, |ext| ext == BBB_EXTENSION) {
+        out_path.set_extension("orig");
+        let out_file = io::BufWriter::new(try!(File::create(&out_path)));
+        let mut decoder = block::bbb::Decoder::new();
+        let (_, mut out_file, err) = decoder.decode(io::BufReader::new(in_file), out_file);
+        try!(err);
+        info!("Decoded bbb file with block size {}", decoder.block_size);
+        out_file.flush()
+    }else {
+        let mut input = Vec::new();
+        try!(io::BufReader::new(in_file).read_to_end(&mut input));
+        out_path.set_extension(BBB_EXTENSION);
+        let out_file = io::BufWriter::new(try!(File::create(&out_path)));
+        info!("Encoding N: {} into bbb format", input.len());
+        let (_, err) = block::bbb::Encoder::new(block::bbb::BLOCK_SIZE).encode(&input, out_file);
+        err
+    }
+}
+
 /// Program entry point
 pub fn main() {
     env_logger::init().unwrap();
     let mut options = getopts::Options::new();
-    options.optopt("m", "model", "set compression model", "dark|exp|raw|rawdc|simple|ybs");
+    options.optopt("m", "model", "set compression model", "bbb|dark|dict|exp|fixed|mix|mtf|mtf1|qlfc|raw|rawdc|simple|wfc|ybs");
+    options.optopt("a", "alphabet", "set symbol order for BWT", "natural|text");
+    options.optopt("f", "format", "set file format", "dark|bbb");
+    options.optopt("e", "entropy", "set entropy coder of the DC and raw based models", "range|binary|carry|rans|table");
+    options.optflag("O", "optimize", "tune model parameters for the file, storing them in the header");
+    options.optopt("b", "block-size", "split the input into blocks of up to a given size", "BYTES");
+    options.optflag("c", "continue", "continue the model statistics from one block to the next");
+    options.optflag("s", "stats", "print the bits spent per block stage, symbol and exponent");
+    options.optopt("p", "primer", "start from the model statistics learned into a primer file", "FILE");
+    options.optopt("d", "dictionary", "code in the context of a dictionary file, for the dict model", "FILE");
+    options.optopt("", "dictionary-size", "set the size of the trained dictionaries", "BYTES");
+    options.optopt("", "dump", "write the values coded by the raw and rawdc models into a file", "FILE");
+    options.optopt("", "json", "export the benchmark results into a JSON file", "FILE");
     //options.optopt("o", "output", "set output file name", "NAME");
     options.optflag("h", "help", "print this help info");
 
@@ -44,16 +481,117 @@ pub fn main() {
         Err(f)  => panic!(f.to_string())
     };
     if matches.opt_present("h") || matches.free.is_empty() {
-        let brief = format!("Dark compressor usage:\n{} [options] input_file[.dark]", args[0]);
+        let brief = format!("Dark compressor usage:\n{0} [options] input_file[.dark]\n{0} [options] tune input_files..\n{0} [options] learn primer_file sample_files..\n{0} [options] train dictionary_file sample_files..\n{0} [options] replay rawdc_dump_file\n{0} [options] bench corpus_dir\n{0} corpus output_dir\n{0} analyze cluster|cell|print|report|stat rawdc_dump_file [arguments..]", args[0]);
         println!("{}", options.usage(&brief));
         return
     }
 
     let model = matches.opt_str("m").unwrap_or("exp".to_string());
-    info!("Using model: {}", model);
+    let order = match matches.opt_str("a") {
+        Some(name) => match alphabet::Order::from_name(&name) {
+            Some(order) => order,
+            None => panic!("Unknown alphabet order: {}", name)
+        },
+        None => alphabet::Order::Natural,
+    };
+    let block_size = match matches.opt_str("b") {
+        Some(value) => match value.parse() {
+            Ok(size) if size > 0 => size,
+            _ => panic!("Invalid block size: {}", value)
+        },
+        None => 0,
+    };
+    let coder = match matches.opt_str("e") {
+        Some(name) => match entropy::Coder::from_name(&name) {
+            Some(coder) => coder,
+            None => panic!("Unknown entropy coder: {}", name)
+        },
+        None => entropy::Coder::Range,
+    };
+    if matches.free[0] == "corpus" {
+        if matches.free.len() != 2 {
+            println!("Generating the corpus needs an output directory");
+            return
+        }
+        corpus::write_all(path::Path::new(&matches.free[1])).unwrap();
+        return
+    }
+    if matches.free[0] == "bench" {
+        if matches.free.len() != 2 {
+            println!("Benchmarking needs a corpus directory");
+            return
+        }
+        let models: Vec<String> = match matches.opt_str("m") {
+            Some(model) => vec![model],
+            None => bench::MODELS.iter()
+                .filter(|model| coder == entropy::Coder::Range || !RANGE_ONLY_MODELS.contains(model))
+                .map(|model| model.to_string()).collect(),
+        };
+        match bench(&models, order, coder, block_size, &matches.free[1], matches.opt_str("json")) {
+            Ok(0) => (),
+            Ok(_) => process::exit(1),
+            Err(e) => {
+                println!("Benchmark failed: {}", e);
+                process::exit(1);
+            },
+        }
+        return
+    }
+    if matches.free[0] == "tune" {
+        tune(&model, order, &matches.free[1..]);
+        return
+    }
+    if matches.free[0] == "learn" {
+        if matches.free.len() < 3 {
+            println!("Learning needs a primer file name and at least one sample");
+            return
+        }
+        learn(&model, order, &matches.free[1..]).unwrap();
+        return
+    }
+    if matches.free[0] == "train" {
+        if matches.free.len() < 3 {
+            println!("Training needs a dictionary file name and at least one sample");
+            return
+        }
+        let size = match matches.opt_str("dictionary-size") {
+            Some(value) => match value.parse() {
+                Ok(size) => size,
+                Err(_) => panic!("Invalid dictionary size: {}", value)
+            },
+            None => dict::DEFAULT_SIZE,
+        };
+        train(size, &matches.free[1..]).unwrap();
+        return
+    }
+    if matches.free[0] == "analyze" {
+        if matches.free.len() < 3 {
+            println!("Analysis needs a method and a dump file of the rawdc model");
+            return
+        }
+        analyze(&matches.free[1], &matches.free[2], &matches.free[3..]).unwrap();
+        return
+    }
+    if matches.free[0] == "replay" {
+        if matches.free.len() != 2 {
+            println!("Replaying needs a dump file of the rawdc model");
+            return
+        }
+        replay(&model, &matches.free[1]).unwrap();
+        return
+    }
     let input_path = path::Path::new(&matches.free[0]);
+    match matches.opt_str("f") {
+        Some(ref format) if format == "bbb" => {
+            run_bbb(&input_path).unwrap();
+            return
+        },
+        Some(ref format) if format != EXTENSION => panic!("Unknown file format: {}", format),
+        _ => (),
+    }
     let input_ext = input_path.extension();
     if input_ext.is_some() && input_ext.unwrap() == EXTENSION {
+        use std::io::Write;
         use block::Decoder;
         let mut in_file = match File::open(&input_path) {
             Ok(file) => io::BufReader::new(file),
@@ -65,25 +603,92 @@ pub fn main() {
         let mut out_path = path::PathBuf::new();
         out_path.set_file_name(input_path.file_name().unwrap());
         out_path.set_extension("orig");
-        let out_file = io::BufWriter::new(File::create(&out_path).unwrap());
-        // decode the block size
-        let n = in_file.read_u32::<LittleEndian>().unwrap() as usize;
-        info!("Decoding N: {}", n);
-        // decode the block
-        let (_, _, err) = match model.as_ref() {
-            "bbb"   => block::raw::Decoder::new(n, model::bbb::Model  ::new()).decode(in_file, out_file),
-            "dark"  => block::dc::Decoder::new(n, model::dark::Model  ::new()).decode(in_file, out_file),
-            "exp"   => block::dc::Decoder::new(n, model::exp::Model   ::new()).decode(in_file, out_file),
-            "raw"   => block::raw::Decoder::new(n, model::raw::Out    ::new()).decode(in_file, out_file),
-            "rawdc" => block::dc::Decoder::new(n, model::raw::DcOut   ::new()).decode(in_file, out_file),
-            "simple"=> block::dc::Decoder::new(n, model::simple::Model::new()).decode(in_file, out_file),
-            "ybs"   => block::dc::Decoder::new(n, model::ybs::Model   ::new()).decode(in_file, out_file),
-            _       => panic!("Unknown decoding model: {}", model)
+        // archives of the original Dark-0.51
+        if header::is_legacy(&mut in_file).unwrap() {
+            let out_file = io::BufWriter::new(File::create(&out_path).unwrap());
+            let header = header::LegacyHeader::read(&mut in_file).unwrap();
+            info!("Decoding Dark-0.51 file {:?} of N: {}, block size: {}",
+                header.name, header.size, header.block_size);
+            let (_, _, err) = block::dc::LegacyDecoder::new(&header).decode(in_file, out_file);
+            err.unwrap();
+            return;
+        }
+        // decode the header
+        let header = header::Header::read(&mut in_file).unwrap();
+        let n = header.size;
+        let model = header.model_name();
+        info!("Decoding N: {}, alphabet: {}, model: {}, parameters: {:?}, block size: {}, coder: {}",
+            n, header.order, model, header.parameters, header.block_size, header.coder);
+        let primer = match (header.primer, matches.opt_str("p")) {
+            (None, _) => None,
+            (Some(checksum), Some(path)) => match read_primer(&path, model) {
+                Ok(ref primer) if primer.checksum() != checksum => {
+                    println!("Primer {} does not match the one used for encoding", path);
+                    process::exit(1);
+                },
+                Ok(primer) => Some(primer),
+                Err(e) => {
+                    println!("Primer can not be loaded: {}", e);
+                    process::exit(1);
+                },
+            },
+            (Some(_), None) => {
+                println!("Input {:?} is coded with a primer, pass it with --primer", input_path);
+                process::exit(1);
+            },
+        };
+        let primer = primer.as_ref();
+        let dictionary = match (header.dictionary, matches.opt_str("d")) {
+            (None, _) => None,
+            (Some(id), Some(path)) => match load_dictionary(&path, header.order) {
+                Ok((file_id, _)) if file_id != id => {
+                    println!("Dictionary {} does not match the one used for encoding", path);
+                    process::exit(1);
+                },
+                Ok((_, dictionary)) => Some(dictionary),
+                Err(e) => {
+                    println!("Dictionary can not be loaded: {}", e);
+                    process::exit(1);
+                },
+            },
+            (Some(_), None) => {
+                println!("Input {:?} is coded with a dictionary, pass it with --dictionary", input_path);
+                process::exit(1);
+            },
         };
+        let mut out_file = io::BufWriter::new(File::create(&out_path).unwrap());
+        // decode the block
+        let (mut output, err) = decode(&header, primer, dictionary, matches.opt_str("dump"), in_file);
         err.unwrap();
+        // restore the original alphabet
+        alphabet::Permutation::new(header.order).decode(&mut output);
+        out_file.write_all(&output).unwrap();
     }else {
         use std::io::Read;
-        use block::Encoder;
+        if coder != entropy::Coder::Range && RANGE_ONLY_MODELS.contains(&model.as_ref()) {
+            println!("The {} model only supports the range coder", model);
+            process::exit(1);
+        }
+        let primer = match matches.opt_str("p").map(|path| read_primer(&path, &model)) {
+            Some(Ok(primer)) => Some(primer),
+            Some(Err(e)) => {
+                println!("Primer can not be loaded: {}", e);
+                process::exit(1);
+            },
+            None => None,
+        };
+        if (model == "dict") != matches.opt_present("d") {
+            println!("A dictionary is needed by the dict model, and only by it");
+            process::exit(1);
+        }
+        let dictionary = match matches.opt_str("d").map(|path| load_dictionary(&path, order)) {
+            Some(Ok(dictionary)) => Some(dictionary),
+            Some(Err(e)) => {
+                println!("Dictionary can not be loaded: {}", e);
+                process::exit(1);
+            },
+            None => None,
+        };
         let mut input = Vec::new();
         let mut file = match File::open(&input_path) {
             Ok(f) => f,
@@ -93,24 +698,37 @@ pub fn main() {
             }
         };
         let n = file.read_to_end(&mut input).unwrap();
-        // write the block size
+        alphabet::Permutation::new(order).encode(&mut input);
+        // write the header
         let mut out_path = path::PathBuf::new();
         out_path.set_file_name(input_path.file_name().unwrap());
         out_path.set_extension(EXTENSION);
         let mut out_file = io::BufWriter::new(File::create(&out_path).unwrap());
-        info!("Encoding N: {}", n);
-        out_file.write_u32::<LittleEndian>(n as u32).unwrap();
-        // encode the block
-        let (_, err) = match model.as_ref() {
-            "bbb"   => block::raw::Encoder::new(n, model::bbb::Model  ::new()).encode(&input, out_file),
-            "dark"  => block::dc::Encoder::new(n, model::dark::Model  ::new()).encode(&input, out_file),
-            "exp"   => block::dc::Encoder::new(n, model::exp::Model   ::new()).encode(&input, out_file),
-            "raw"   => block::raw::Encoder::new(n, model::raw::Out    ::new()).encode(&input, out_file),
-            "rawdc" => block::dc::Encoder::new(n, model::raw::DcOut   ::new()).encode(&input, out_file),
-            "simple"=> block::dc::Encoder::new(n, model::simple::Model::new()).encode(&input, out_file),
-            "ybs"   => block::dc::Encoder::new(n, model::ybs::Model   ::new()).encode(&input, out_file),
-            _       => panic!("Unknown encoding model: {}", model)
+        let mut header = header::Header {
+            size    : n,
+            order   : order,
+            model   : match header::model_id(&model) {
+                Some(id) => id,
+                None => panic!("Unknown encoding model: {}", model)
+            },
+            parameters  : Vec::new(),
+            block_size  : block_size,
+            continued   : matches.opt_present("c"),
+            primer      : primer.as_ref().map(|p| p.checksum()),
+            dictionary  : dictionary.as_ref().map(|&(id, _)| id),
+            coder       : coder,
         };
+        if matches.opt_present("O") {
+            header.parameters = tune::optimize(&model, &input, &header, primer.as_ref());
+        }
+        info!("Encoding N: {}, alphabet: {}, model: {}, parameters: {:?}, block size: {}",
+            n, order, model, header.parameters, block_size);
+        header.write(&mut out_file).unwrap();
+        let primer = primer.as_ref();
+        let stats = matches.opt_present("s");
+        // encode the block
+        let dictionary = dictionary.map(|(_, dictionary)| dictionary);
+        let (_, err) = encode(&header, &input, primer, dictionary, stats, matches.opt_str("dump"), out_file);
         err.unwrap();
     }
 }
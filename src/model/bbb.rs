@@ -10,7 +10,9 @@ bbb compression model
 
 use std::io;
 use compress::entropy::ari;
+use entropy::{BitDecoder, BitEncoder};
 use super::{Symbol, SymContext};
+use super::state;
 
 
 /// State table:
@@ -164,6 +166,8 @@ pub struct Model {
     gate3: Vec<ari::apm::Gate>,
     gate4: Vec<ari::apm::Gate>,
     gate5: Vec<ari::apm::Gate>,
+    /// Keyed by the BWT bucket symbol
+    gate6: Vec<ari::apm::Gate>,
 }
 
 struct UpdateCookie {
@@ -173,11 +177,13 @@ struct UpdateCookie {
     b3: ari::apm::BinCoords,
     b4: ari::apm::BinCoords,
     b5: ari::apm::BinCoords,
+    b6: ari::apm::BinCoords,
     c1: usize,
     c2: usize,
     c3: usize,
     c4: usize,
     c5: usize,
+    c6: usize,
 }
 
 impl Model {
@@ -195,6 +201,7 @@ impl Model {
             gate3: (0..0x400).map(|_| ari::apm::Gate::new()).collect(),
             gate4: (0..0x2000).map(|_| ari::apm::Gate::new()).collect(),
             gate5: (0..0x4000).map(|_| ari::apm::Gate::new()).collect(),
+            gate6: (0..0x10000).map(|_| ari::apm::Gate::new()).collect(),
         }
     }
 
@@ -229,9 +236,10 @@ impl Model {
         self.gate3[cookie.c3].update(bit!=0, cookie.b3, 4, 0);
         self.gate4[cookie.c4].update(bit!=0, cookie.b4, 3, 0);
         self.gate5[cookie.c5].update(bit!=0, cookie.b5, 3, 0);
+        self.gate6[cookie.c6].update(bit!=0, cookie.b6, 4, 0);
     }
 
-    fn predict(&self) -> (ari::apm::Bit, UpdateCookie) {
+    fn predict(&self, ctx: &SymContext) -> (ari::apm::Bit, UpdateCookie) {
         let p0 = self.state_map.predict(self.ctx_id);
         let bit_context = self.bit_context as usize;
         let last_bytes = self.last_bytes as usize;
@@ -259,7 +267,12 @@ impl Model {
         let p5y = (p5x.to_flat() + p4.to_flat() + 1) >> 1;
         let p5 = ari::apm::Bit::from_flat(p5y);
 
-        let pr = p5;
+        let c6 = bit_context | ((ctx.bucket as usize) << 8);
+        let (p6x, b6) = self.gate6[c6].pass(&p5);
+        let p6y = (p6x.to_flat() + p5.to_flat() + 1) >> 1;
+        let p6 = ari::apm::Bit::from_flat(p6y);
+
+        let pr = p6;
         let cookie = UpdateCookie {
             b11: b11,
             b12: b12,
@@ -267,11 +280,13 @@ impl Model {
             b3: b3,
             b4: b4,
             b5: b5,
+            b6: b6,
             c1: c1,
             c2: c2,
             c3: c3,
             c4: c4,
             c5: c5,
+            c6: c6,
         };
 
         (if pr.predict() {
@@ -289,23 +304,23 @@ impl super::Model<Symbol, SymContext> for Model {
         *self = Model::new();
     }
 
-    fn encode<W: io::Write>(&mut self, sym: Symbol, _ctx: &SymContext,
-              eh: &mut ari::Encoder<W>) -> io::Result<()> {
+    fn encode<E: BitEncoder>(&mut self, sym: Symbol, ctx: &SymContext,
+              eh: &mut E) -> io::Result<()> {
         for i in (0..8).rev() {
             let bit = (sym >> i) & 1;
-            let (prob, cookie) = self.predict();
-            try!(eh.encode(bit != 0, &prob));
+            let (prob, cookie) = self.predict(ctx);
+            try!(eh.encode_bit(bit != 0, &prob));
             self.update(bit, i == 0, cookie);
         }
         Ok(())
     }
 
-    fn decode<R: io::Read>(&mut self, _ctx: &SymContext, dh: &mut ari::Decoder<R>)
+    fn decode<D: BitDecoder>(&mut self, ctx: &SymContext, dh: &mut D)
               -> io::Result<Symbol> {
         let mut sym = 0 as Symbol;
         for i in (0..8).rev() {
-            let (prob, cookie) = self.predict();
-            let bit_b = try!(dh.decode(&prob));
+            let (prob, cookie) = self.predict(ctx);
+            let bit_b = try!(dh.decode_bit(&prob));
             let bit = if bit_b {1} else {0};
             sym |= bit << i;
             self.update(bit, i == 0, cookie);
@@ -313,3 +328,237 @@ impl super::Model<Symbol, SymContext> for Model {
         Ok(sym)
     }
 }
+
+impl super::State for Model {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        for (&st, &pr) in self.state_map.ctx2state.iter().zip(self.state_map.table.iter()) {
+            try!(state::save_number(st as u32, writer));
+            try!(state::save_number(pr as u32, writer));
+        }
+        try!(state::save_number(self.ctx_id as u32, writer));
+        try!(state::save_number(self.bit_context as u32, writer));
+        try!(state::save_number(self.last_bytes, writer));
+        try!(state::save_number(self.run_count as u32, writer));
+        state::save_number((self.run_context >> 8) as u32, writer)
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        // the gates can't be saved, so they start afresh
+        let mut model = Model::new();
+        for (st, pr) in model.state_map.ctx2state.iter_mut().zip(model.state_map.table.iter_mut()) {
+            *st = try!(state::load_index(reader, 0x100)) as u8;
+            *pr = try!(state::load_index(reader, 0x10000)) as u16;
+        }
+        model.ctx_id = try!(state::load_index(reader, 0x100));
+        model.bit_context = try!(state::load_index(reader, 0x100)) as u8;
+        model.last_bytes = try!(state::load_number(reader));
+        model.run_count = try!(state::load_index(reader, 0x10000)) as u16;
+        model.run_context = (try!(state::load_index(reader, 4)) << 8) as u16;
+        *self = model;
+        Ok(())
+    }
+}
+
+
+/// Return p = 1/(1 + exp(-d)), d scaled by 8 bits, p scaled by 12 bits
+fn squash(d: i32) -> i32 {
+    const TABLE: [i32; 33] = [
+        1,2,3,6,10,16,27,45,73,120,194,310,488,747,1101,
+        1546,2047,2549,2994,3348,3607,3785,3901,3975,4022,
+        4050,4068,4079,4085,4089,4092,4093,4094];
+    if d > 2047 {
+        return 4095
+    }
+    if d < -2047 {
+        return 0
+    }
+    let w = d & 127;
+    let i = ((d >> 7) + 16) as usize;
+    (TABLE[i] * (128 - w) + TABLE[i+1] * w + 64) >> 7
+}
+
+/// Inverse of squash: ln(p/(1-p)) in [-2047, 2047], indexed by p in 12 bits
+fn build_stretch() -> Vec<i16> {
+    let mut table = vec![0i16; 4096];
+    let mut pi = 0;
+    for x in -2047 .. 2048 {
+        let i = squash(x) as usize;
+        for t in table[pi .. i+1].iter_mut() {
+            *t = x as i16;
+        }
+        pi = i + 1;
+    }
+    table[4095] = 2047;
+    table
+}
+
+/// Counter state -> probability of 1 in 16 bits, as in the original StateMap
+struct CounterMap {
+    state: usize,
+    table: [u16; 0x100],
+}
+
+impl CounterMap {
+    fn new() -> CounterMap {
+        let mut table = [0; 0x100];
+        for (i, t) in table.iter_mut().enumerate() {
+            let mut n0 = STATE_TABLE[i][2] as u32;
+            let mut n1 = STATE_TABLE[i][3] as u32;
+            if n0 == 0 {
+                n1 *= 128;
+            }
+            if n1 == 0 {
+                n0 *= 128;
+            }
+            *t = (65536 * (n1 + 1) / (n0 + n1 + 2)) as u16;
+        }
+        CounterMap {
+            state: 0,
+            table: table,
+        }
+    }
+
+    /// Train the last state with a bit, return the 12-bit probability of a new state
+    fn refine(&mut self, bit: i32, state: usize) -> i32 {
+        let t = self.table[self.state] as i32;
+        self.table[self.state] = (t + (((bit << 16) - t + 128) >> 8)) as u16;
+        self.state = state;
+        (self.table[state] >> 4) as i32
+    }
+}
+
+/// Adaptive probability map of the original: 33 bins per context,
+/// interpolated in the stretched domain
+struct Apm {
+    index: usize,
+    table: Vec<u16>,
+}
+
+impl Apm {
+    fn new(num_contexts: usize) -> Apm {
+        let mut table = vec![0u16; num_contexts * 33];
+        for j in 0 .. 33 {
+            table[j] = (squash((j as i32 - 16) * 128) * 16) as u16;
+        }
+        for i in 1 .. num_contexts {
+            for j in 0 .. 33 {
+                table[i*33 + j] = table[j];
+            }
+        }
+        Apm {
+            index: 0,
+            table: table,
+        }
+    }
+
+    /// Train the last bins with a bit, return the refined probability
+    fn refine(&mut self, bit: i32, pr: i32, context: usize, rate: usize, stretch: &[i16]) -> i32 {
+        let pr = stretch[pr as usize] as i32;
+        let g = (bit << 16) + (bit << rate) - bit - bit;
+        for t in self.table[self.index .. self.index+2].iter_mut() {
+            let old = *t as i32;
+            *t = (old + ((g - old) >> rate)) as u16;
+        }
+        let w = pr & 127;
+        self.index = ((pr + 2048) >> 7) as usize + context * 33;
+        ((self.table[self.index] as i32) * (128 - w) + (self.table[self.index+1] as i32) * w) >> 11
+    }
+}
+
+
+/// Exact port of the bbb predictor, used for the compatible file format.
+/// Works on plain bytes and predicts the probability of the next bit being 1.
+pub struct Predictor {
+    /// next prediction in 12 bits
+    pr: i32,
+    /// last 0-7 bits of the byte with a leading 1
+    c0: u32,
+    /// last 4 whole bytes, last is in low 8 bits
+    c4: u32,
+    bit_pos: usize,
+    /// context -> state
+    states: [u8; 0x100],
+    /// current context, points into `states`
+    context: usize,
+    state_map: CounterMap,
+    /// count of consecutive identical bytes
+    run: u32,
+    /// 0x100 times (0-3) if run is 0, 1, 2-3, 4+
+    run_context: u32,
+    a11: Apm,
+    a12: Apm,
+    a2: Apm,
+    a3: Apm,
+    a4: Apm,
+    a5: Apm,
+    stretch: Vec<i16>,
+}
+
+impl Predictor {
+    /// Create a new predictor
+    pub fn new() -> Predictor {
+        Predictor {
+            pr: 2048,
+            c0: 1,
+            c4: 0,
+            bit_pos: 0,
+            states: [0; 0x100],
+            context: 0,
+            state_map: CounterMap::new(),
+            run: 0,
+            run_context: 0,
+            a11: Apm::new(0x100),
+            a12: Apm::new(0x100),
+            a2: Apm::new(0x10000),
+            a3: Apm::new(0x400),
+            a4: Apm::new(0x2000),
+            a5: Apm::new(0x4000),
+            stretch: build_stretch(),
+        }
+    }
+
+    /// Return the probability of the next bit being 1, in 12 bits
+    pub fn predict(&self) -> u32 {
+        self.pr as u32
+    }
+
+    /// Update the model with an actual bit
+    pub fn update(&mut self, bit: bool) {
+        let y = bit as i32;
+        // update model
+        let state = self.states[self.context] as usize;
+        self.states[self.context] = STATE_TABLE[state][bit as usize];
+        // update context
+        self.c0 += self.c0 + (bit as u32);
+        self.bit_pos += 1;
+        if self.bit_pos == 8 {
+            self.bit_pos = 0;
+            self.c4 = (self.c4 << 8) | (self.c0 - 0x100);
+            self.c0 = 1;
+            if (self.c4 ^ (self.c4 >> 8)) & 0xFF == 0 {
+                if self.run < 0xFFFF {
+                    self.run += 1;
+                }
+                match self.run {
+                    1 | 2 | 4 => self.run_context += 0x100,
+                    _ => ()
+                }
+            }else {
+                self.run = 0;
+                self.run_context = 0;
+            }
+        }
+        // predict
+        let (c0, c4) = (self.c0 as usize, self.c4 as usize);
+        let stretch = &self.stretch[..];
+        self.context = c0;
+        let mut pr = self.state_map.refine(y, self.states[c0] as usize);
+        pr = (self.a11.refine(y, pr, c0, 5, stretch) + self.a12.refine(y, pr, c0, 9, stretch) + 1) >> 1;
+        pr = self.a2.refine(y, pr, c0 | ((c4 << 8) & 0xFF00), 7, stretch);
+        pr = self.a3.refine(y, pr, (c4 & 0xFF) | (self.run_context as usize), 8, stretch);
+        pr = (self.a4.refine(y, pr, c0 | (c4 & 0x1F00), 7, stretch) * 3 + pr + 2) >> 2;
+        let hash = ((self.c4 & 0xFFFFFF).wrapping_mul(123456791) >> 18) as usize;
+        pr = (self.a5.refine(y, pr, c0 ^ hash, 7, stretch) + pr + 1) >> 1;
+        self.pr = pr;
+    }
+}
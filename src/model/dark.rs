@@ -10,35 +10,60 @@ http://code.google.com/p/adark/
 */
 
 use std::{cmp, io};
-use std::fmt::{Debug, Display};
-use std::marker::PhantomData;
-use num::{Float, NumCast};
 use compress::bwt::dc::Context;
 use compress::entropy::ari;
-use super::Distance;
+use entropy::{BitDecoder, BitEncoder, Part};
+use entropy::legacy;
+use super::{Distance, ParameterInfo, Tunable};
+use super::state;
 
 
-/// Aggregate frequency model of two sources
-pub struct Aggregate<'a, F, X: 'a, Y: 'a> {
+/// Aggregate frequency model of two sources,
+/// using equation: wx * X + wy * Y
+pub struct Aggregate<'a, X: 'a, Y: 'a> {
     x: &'a X,
     y: &'a Y,
-    dummy: PhantomData<F>,
+    wx: ari::Border,
+    wy: ari::Border,
 }
 
-impl<'a, F: Float + Debug + Display, X: ari::Model<F>, Y: ari::Model<F>>
-ari::Model<F> for Aggregate<'a, F, X, Y> {
-    fn get_range(&self, value: F) -> (ari::Border,ari::Border) {
+impl<'a, X: ari::Model<usize>, Y: ari::Model<usize>> Aggregate<'a, X, Y> {
+    /// Create a new aggregate of two weighted sources
+    pub fn new(wx: ari::Border, x: &'a X, wy: ari::Border, y: &'a Y) -> Aggregate<'a, X, Y> {
+        Aggregate {
+            x: x,
+            y: y,
+            wx: wx,
+            wy: wy,
+        }
+    }
+}
+
+impl<'a, X: ari::Model<usize>, Y: ari::Model<usize>>
+ari::Model<usize> for Aggregate<'a, X, Y> {
+    fn get_range(&self, value: usize) -> (ari::Border,ari::Border) {
         let (x1,x2) = self.x.get_range(value);
         let (y1,y2) = self.y.get_range(value);
-        (x1+y1, x2+y2)
+        (self.wx*x1 + self.wy*y1, self.wx*x2 + self.wy*y2)
     }
 
-    fn find_value(&self, _offset: ari::Border) -> (F,ari::Border,ari::Border) {
-        (NumCast::from(0).unwrap(), 0,0)    //TODO
+    fn find_value(&self, offset: ari::Border) -> (usize,ari::Border,ari::Border) {
+        assert!(offset < self.get_denominator(),
+            "Invalid frequency offset {} requested under total {}",
+            offset, self.get_denominator());
+        // both sources are cumulative, so the aggregate ranges are ordered
+        let mut value = 0;
+        loop {
+            let (lo, hi) = self.get_range(value);
+            if hi > offset {
+                return (value, lo, hi)
+            }
+            value += 1;
+        }
     }
 
     fn get_denominator(&self) -> ari::Border {
-        self.x.get_denominator() + self.y.get_denominator()
+        self.wx*self.x.get_denominator() + self.wy*self.y.get_denominator()
     }
 }
 
@@ -49,6 +74,96 @@ const NUM_LAST_LOGS    : u32 = 3;
 const MAX_BIT_CONTEXT  : usize = 3;
 const ADAPT_POWERS     : [isize; 9] = [6,5,4,3,2,1,4,6,4];
 
+/// Tunable parameters of the model
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameters {
+    /// binary log of the frequency threshold
+    pub threshold_log       : usize,
+    /// adaptation powers (in 1/8) of the average distance, by the log difference
+    pub adapt_powers        : [isize; 9],
+    /// update power of the global log tables
+    pub update_log_global   : usize,
+    /// update power of the symbol log tables
+    pub update_log_power    : usize,
+    /// update constant of all log tables
+    pub update_log_add      : ari::Border,
+    /// learning rate of the global log extension bits
+    pub rate_log_bits       : ari::Border,
+    /// learning rate of the symbol log extension bits
+    pub rate_extra          : ari::Border,
+    /// learning rate of the mantissa bits
+    pub rate_mantissa       : ari::Border,
+}
+
+const PARAMETER_INFO: [ParameterInfo; 16] = [
+    ("threshold_log", 8, 12),
+    ("adapt_power0", 0, 8), ("adapt_power1", 0, 8), ("adapt_power2", 0, 8),
+    ("adapt_power3", 0, 8), ("adapt_power4", 0, 8), ("adapt_power5", 0, 8),
+    ("adapt_power6", 0, 8), ("adapt_power7", 0, 8), ("adapt_power8", 0, 8),
+    ("update_log_global", 1, 16),
+    ("update_log_power", 1, 16),
+    ("update_log_add", 0, 16),
+    ("rate_log_bits", 1, 8),
+    ("rate_extra", 1, 8),
+    ("rate_mantissa", 1, 10),
+];
+
+impl Parameters {
+    /// Create the default parameters, matching Dark-0.51
+    pub fn new() -> Parameters {
+        Parameters {
+            threshold_log       : 12,
+            adapt_powers        : ADAPT_POWERS,
+            update_log_global   : 12,
+            update_log_power    : 5,
+            update_log_add      : 5,
+            rate_log_bits       : 2,
+            rate_extra          : 3,
+            rate_mantissa       : 8,
+        }
+    }
+}
+
+impl Default for Parameters {
+    fn default() -> Parameters {
+        Parameters::new()
+    }
+}
+
+impl Tunable for Parameters {
+    fn info() -> &'static [ParameterInfo] {
+        &PARAMETER_INFO
+    }
+
+    fn get(&self, index: usize) -> isize {
+        match index {
+            0 => self.threshold_log as isize,
+            1 ... 9 => self.adapt_powers[index-1],
+            10 => self.update_log_global as isize,
+            11 => self.update_log_power as isize,
+            12 => self.update_log_add as isize,
+            13 => self.rate_log_bits as isize,
+            14 => self.rate_extra as isize,
+            15 => self.rate_mantissa as isize,
+            _ => panic!("Unknown parameter index: {}", index)
+        }
+    }
+
+    fn set(&mut self, index: usize, value: isize) {
+        match index {
+            0 => self.threshold_log = value as usize,
+            1 ... 9 => self.adapt_powers[index-1] = value,
+            10 => self.update_log_global = value as usize,
+            11 => self.update_log_power = value as usize,
+            12 => self.update_log_add = value as ari::Border,
+            13 => self.rate_log_bits = value as ari::Border,
+            14 => self.rate_extra = value as ari::Border,
+            15 => self.rate_mantissa = value as ari::Border,
+            _ => panic!("Unknown parameter index: {}", index)
+        }
+    }
+}
+
 
 struct BinaryMultiplex {
     pub freqs: Vec<ari::bin::Model>,
@@ -91,10 +206,10 @@ impl SymbolContext {
         self.freq_extra.reset();
     }
 
-    fn update(&mut self, dist: Distance, log_diff: isize) {
+    fn update(&mut self, dist: Distance, log_diff: isize, powers: &[isize; 9]) {
         let adapt = if log_diff < -6 {7}
         else if log_diff >= 3 {3}
-        else { ADAPT_POWERS[(6+log_diff) as usize] };
+        else { powers[(6+log_diff) as usize] };
         self.avg_dist += (adapt*((dist as isize) - self.avg_dist)) >> 3;
         debug!("\tUpdated avg_dist to {}, using raz {}, dist {} and power {}",
             self.avg_dist, log_diff, dist, adapt);
@@ -111,14 +226,16 @@ pub struct Model {
     contexts: Vec<SymbolContext>,
     last_log_token: usize,
     /// update parameters
+    adapt_powers: [isize; 9],
     update_log_global: usize,
     update_log_power: usize,
     update_log_add: ari::Border,
 }
 
 impl Model {
-    /// Create a new Model instance
-    pub fn new_custom(threshold: ari::Border) -> Model {
+    /// Create a new Model instance with given parameters
+    pub fn new_custom(params: Parameters) -> Model {
+        let threshold = 1 << params.threshold_log;
         Model {
             freq_log: (0 .. MAX_LOG_CONTEXT + 1).map(|_|
                 (0..NUM_LAST_LOGS).map(|_|
@@ -126,27 +243,28 @@ impl Model {
                     ).collect()
                 ).collect(),
             freq_log_bits: [
-                BinaryMultiplex::new(threshold, 2),
-                BinaryMultiplex::new(threshold, 2)
+                BinaryMultiplex::new(threshold, params.rate_log_bits),
+                BinaryMultiplex::new(threshold, params.rate_log_bits)
                 ],
             freq_mantissa: (0..32).map(|_|
                 (0 .. MAX_BIT_CONTEXT + 1).map(|_|
-                    ari::bin::Model::new_flat(threshold, 8)
+                    ari::bin::Model::new_flat(threshold, params.rate_mantissa)
                     ).collect()
                 ).collect(),
             contexts: (0..0x100).map(|_|
-                SymbolContext::new(threshold, 3)
+                SymbolContext::new(threshold, params.rate_extra)
                 ).collect(),
             last_log_token: 1,
-            update_log_global: 12,
-            update_log_power: 5,
-            update_log_add: 5,
+            adapt_powers: params.adapt_powers,
+            update_log_global: params.update_log_global,
+            update_log_power: params.update_log_power,
+            update_log_add: params.update_log_add,
         }
     }
 
     /// Create a new default Model
     pub fn new() -> Model {
-        Model::new_custom(ari::RANGE_DEFAULT_THRESHOLD >> 2)
+        Model::new_custom(Parameters::new())
     }
 
     fn isize_log(d: Distance) -> usize {
@@ -177,21 +295,22 @@ impl super::Model<Distance, Context> for Model {
         self.last_log_token = 1;
     }
 
-    fn encode<W: io::Write>(&mut self, mut dist: Distance, ctx: &Context,
-              eh: &mut ari::Encoder<W>) -> io::Result<()> {
+    fn encode<E: BitEncoder>(&mut self, mut dist: Distance, ctx: &Context,
+              eh: &mut E) -> io::Result<()> {
         dist += 1;
         let log = Model::isize_log(dist);
         let context = &mut self.contexts[ctx.symbol as usize];
         let avg_log = Model::isize_log(context.avg_dist as Distance);
         let avg_log_capped = cmp::min(MAX_LOG_CONTEXT, avg_log);
         // write exponent
+        eh.set_part(Part::Exponent);
         {   // base part
             let sym_freq = &mut context.freq_log;
             let log_capped = cmp::min(log, MAX_LOG_CODE)-1;
             let global_freq = &mut self.freq_log[avg_log_capped][self.last_log_token];
             debug!("Dark encoding log {} with context[{}][{}] of sym {}",
                 log_capped, avg_log_capped, self.last_log_token, ctx.symbol);
-            try!(eh.encode(log_capped, &ari::table::SumProxy::new(1,sym_freq, 2,global_freq, 0)));
+            try!(eh.encode_symbol(log_capped, &Aggregate::new(1,sym_freq, 2,global_freq)));
             sym_freq.update(log_capped, self.update_log_power, self.update_log_add);
             global_freq.update(log_capped, self.update_log_global, self.update_log_add);
         }
@@ -200,38 +319,39 @@ impl super::Model<Distance, Context> for Model {
             for i in MAX_LOG_CODE .. log {
                 let bc = &mut context.freq_extra.freqs[i-MAX_LOG_CODE];
                 let fc = &mut freq_log_bits.freqs[i-MAX_LOG_CODE];
-                try!(eh.encode(true, &ari::bin::SumProxy::new(1,bc, 1,fc, 1)));
+                try!(eh.encode_bit(true, &ari::bin::SumProxy::new(1,bc, 1,fc, 1)));
                 bc.update(true);
                 fc.update(true);
             }
             let i = log-MAX_LOG_CODE;
             let bc = &mut context.freq_extra.freqs[i];
             let fc = &mut freq_log_bits.freqs[i];
-            try!(eh.encode(false, &ari::bin::SumProxy::new(1,bc, 1,fc, 1)));
+            try!(eh.encode_bit(false, &ari::bin::SumProxy::new(1,bc, 1,fc, 1)));
             bc.update(false);
             fc.update(false);
         }
         self.last_log_token = if log<2 {0} else if log<8 {1} else {2};
         // write mantissa
+        eh.set_part(Part::Mantissa);
         let mantissa_context = &mut self.freq_mantissa[log];
         for i in 1 .. log {
             let bit = (dist>>(log-i-1)) as usize & 1 != 0;
             if i > MAX_BIT_CONTEXT {
                 // just send bits past the model, equally distributed
-                try!(eh.encode(bit, mantissa_context.last().unwrap()));
+                try!(eh.encode_bit(bit, mantissa_context.last().unwrap()));
             }else {
                 let bc = &mut mantissa_context[i-1];
-                try!(eh.encode(bit, bc));
+                try!(eh.encode_bit(bit, bc));
                 bc.update(bit);
             };
         }
         // update the model
         let log_diff = (log as isize) - (avg_log_capped as isize);  //check avg_log
-        context.update(dist-1, log_diff);
+        context.update(dist-1, log_diff, &self.adapt_powers);
         Ok(())
     }
 
-    fn decode<R: io::Read>(&mut self, ctx: &Context, dh: &mut ari::Decoder<R>)
+    fn decode<D: BitDecoder>(&mut self, ctx: &Context, dh: &mut D)
               -> io::Result<Distance> {
         let context = &mut self.contexts[ctx.symbol as usize];
         let avg_log = Model::isize_log(context.avg_dist as Distance);
@@ -240,7 +360,7 @@ impl super::Model<Distance, Context> for Model {
         let log_pre = { // base part
             let sym_freq = &mut context.freq_log;
             let global_freq = &mut self.freq_log[avg_log_capped][self.last_log_token];
-            let log = try!(dh.decode(&ari::table::SumProxy::new(1, sym_freq, 2, global_freq, 0)));
+            let log = try!(dh.decode_symbol(&Aggregate::new(1,sym_freq, 2,global_freq)));
             debug!("Dark decoding log {} with context[{}][{}] of sym {}",
                 log, avg_log_capped, self.last_log_token, ctx.symbol);
             sym_freq.update(log, self.update_log_power, self.update_log_add);
@@ -254,7 +374,7 @@ impl super::Model<Distance, Context> for Model {
             loop {
                 let bc = &mut context.freq_extra.freqs[count];
                 let fc = &mut freq_log_bits.freqs[count];
-                let bit = try!(dh.decode( &ari::bin::SumProxy::new(1,bc, 1,fc, 1) ));
+                let bit = try!(dh.decode_bit( &ari::bin::SumProxy::new(1,bc, 1,fc, 1) ));
                 bc.update(bit);
                 fc.update(bit);
                 if !bit {break}
@@ -270,10 +390,10 @@ impl super::Model<Distance, Context> for Model {
         let mut dist = 1 as Distance;
         for i in 1 .. log {
             let bit = if i > MAX_BIT_CONTEXT {
-                try!(dh.decode(mantissa_context.last().unwrap()))
+                try!(dh.decode_bit(mantissa_context.last().unwrap()))
             }else {
                 let bc = &mut mantissa_context[i-1];
-                let bit = try!(dh.decode(bc));
+                let bit = try!(dh.decode_bit(bc));
                 bc.update(bit);
                 bit
             };
@@ -282,7 +402,207 @@ impl super::Model<Distance, Context> for Model {
         // update model
         let log_diff = (log as isize) - (avg_log_capped as isize);
         dist -= 1;
-        context.update(dist, log_diff);
+        context.update(dist, log_diff, &self.adapt_powers);
+        Ok(dist)
+    }
+}
+
+impl super::State for Model {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        for array in self.freq_log.iter() {
+            try!(state::save_tables(array, writer));
+        }
+        for bm in self.freq_log_bits.iter() {
+            try!(state::save_binaries(&bm.freqs, writer));
+        }
+        for array in self.freq_mantissa.iter() {
+            try!(state::save_binaries(array, writer));
+        }
+        for con in self.contexts.iter() {
+            try!(state::save_number(con.avg_dist as u32, writer));
+            try!(state::save_table(&con.freq_log, writer));
+            try!(state::save_binaries(&con.freq_extra.freqs, writer));
+        }
+        state::save_number(self.last_log_token as u32, writer)
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        for array in self.freq_log.iter_mut() {
+            try!(state::load_tables(array, reader));
+        }
+        for bm in self.freq_log_bits.iter_mut() {
+            try!(state::load_binaries(&mut bm.freqs, reader));
+        }
+        for array in self.freq_mantissa.iter_mut() {
+            try!(state::load_binaries(array, reader));
+        }
+        for con in self.contexts.iter_mut() {
+            con.avg_dist = try!(state::load_number(reader)) as i32 as isize;
+            try!(state::load_table(&mut con.freq_log, reader));
+            try!(state::load_binaries(&mut con.freq_extra.freqs, reader));
+        }
+        self.last_log_token = try!(state::load_index(reader, NUM_LAST_LOGS as usize));
+        Ok(())
+    }
+}
+
+
+const LEGACY_LOGS       : usize = 32;
+const LEGACY_LOG_CODE   : usize = 8;
+const LEGACY_FREQ_BITS  : usize = 12;
+const LEGACY_FREQ_MAX   : u16 = 1<<LEGACY_FREQ_BITS;
+const LEGACY_ADAPT      : (usize, usize, usize, usize) = (12, 5, 2, 3);
+const LEGACY_MANTISSA_UPDATE    : usize = 8;
+const LEGACY_MANTISSA_MODELLED  : usize = 3;
+
+type LegacyTable = [u16; LEGACY_LOG_CODE+1];
+type LegacyBits = [u16; LEGACY_LOGS];
+
+fn legacy_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupted Dark-0.51 stream")
+}
+
+fn legacy_table() -> LegacyTable {
+    let mut table = [1; LEGACY_LOG_CODE+1];
+    table[0] = LEGACY_LOG_CODE as u16;
+    table
+}
+
+/// Add to a frequency table, where `table[0]` holds the total
+fn legacy_update(table: &mut LegacyTable, log: usize, shift: usize) {
+    let add = (table[0] >> shift) + 5;
+    table[log] += add;
+    table[0] += add;
+    if table[0] >= LEGACY_FREQ_MAX {
+        table[0] = 0;
+        for i in 1 .. LEGACY_LOG_CODE+1 {
+            table[i] = (table[i] + 1) >> 1;
+            table[0] += table[i];
+        }
+    }
+}
+
+struct LegacySymbol {
+    avg_dist    : i32,
+    freq_log    : LegacyTable,
+    freq_extra  : LegacyBits,
+}
+
+impl LegacySymbol {
+    fn new() -> LegacySymbol {
+        LegacySymbol {
+            avg_dist    : 1000,
+            freq_log    : legacy_table(),
+            freq_extra  : [LEGACY_FREQ_MAX>>1; LEGACY_LOGS],
+        }
+    }
+}
+
+/// Dark-0.51 model, bit-exact with the original C version.
+/// It drives the legacy range decoder directly, so it can only decode.
+pub struct Legacy {
+    symbols         : Vec<LegacySymbol>,
+    freq_log        : Vec<LegacyTable>,     //[3][MAX_LOG_CONTEXT+1]
+    freq_log_bits   : [LegacyBits; 2],
+    freq_mantissa   : Vec<LegacyBits>,      //[LEGACY_LOGS]
+    last_log        : usize,
+}
+
+impl Legacy {
+    /// Create a new legacy model
+    pub fn new() -> Legacy {
+        Legacy {
+            symbols         : (0..0x100).map(|_| LegacySymbol::new()).collect(),
+            freq_log        : vec![legacy_table(); 3 * (MAX_LOG_CONTEXT+1)],
+            freq_log_bits   : [[LEGACY_FREQ_MAX>>1; LEGACY_LOGS]; 2],
+            freq_mantissa   : vec![[LEGACY_FREQ_MAX>>1; LEGACY_LOGS]; LEGACY_LOGS],
+            last_log        : 2,
+        }
+    }
+
+    /// Reset to the initial state
+    pub fn reset(&mut self) {
+        *self = Legacy::new();
+    }
+
+    /// Decode a distance under the context of a given symbol
+    pub fn decode<R: io::Read>(&mut self, symbol: u8, dh: &mut legacy::Decoder<R>)
+                  -> io::Result<Distance> {
+        let (a0, a1, b0, b1) = LEGACY_ADAPT;
+        let sym = &mut self.symbols[symbol as usize];
+        let avg_log = cmp::min(MAX_LOG_CONTEXT, Model::isize_log(sym.avg_dist as Distance));
+        let token = if self.last_log<2 {0} else if self.last_log<8 {1} else {2};
+        let global = &mut self.freq_log[token * (MAX_LOG_CONTEXT+1) + avg_log];
+        let global_bits = &mut self.freq_log_bits[(avg_log == MAX_LOG_CONTEXT) as usize];
+        // read exponent
+        let total = 2 * global[0] + sym.freq_log[0];
+        let offset = dh.divide(total as legacy::Border);
+        let mut log = 1;
+        let mut low = 0;
+        while low + 2 * global[log] + sym.freq_log[log] <= offset as u16 {
+            low += 2 * global[log] + sym.freq_log[log];
+            log += 1;
+            if log > LEGACY_LOG_CODE {
+                return Err(legacy_error())
+            }
+        }
+        try!(dh.parse(low as legacy::Border, (2 * global[log] + sym.freq_log[log]) as legacy::Border));
+        legacy_update(global, log, a0);
+        legacy_update(&mut sym.freq_log, log, a1);
+        debug!("Legacy decoded log {} with context[{}][{}] of sym {}", log, token, avg_log, symbol);
+        if log == LEGACY_LOG_CODE {  // extension
+            let mut i = 0;
+            let mut cut;
+            loop {
+                dh.shift(LEGACY_FREQ_BITS);
+                let (u, v) = (&mut global_bits[i], &mut sym.freq_extra[i]);
+                cut = (*u + *v) >> 1;
+                if dh.is_below(cut as legacy::Border) {
+                    break
+                }
+                try!(dh.parse(cut as legacy::Border, (LEGACY_FREQ_MAX - cut) as legacy::Border));
+                *u -= *u >> b0;
+                *v -= *v >> b1;
+                i += 1;
+                log += 1;
+                if log >= LEGACY_LOGS {
+                    return Err(legacy_error())
+                }
+            }
+            try!(dh.parse(0, cut as legacy::Border));
+            let (u, v) = (&mut global_bits[i], &mut sym.freq_extra[i]);
+            *u += (LEGACY_FREQ_MAX - *u) >> b0;
+            *v += (LEGACY_FREQ_MAX - *v) >> b1;
+        }
+        // read mantissa
+        let bits = &mut self.freq_mantissa[log];
+        let mut value = 1 as Distance;
+        for (k, u) in bits[.. log-1].iter_mut().enumerate() {
+            let modelled = k < LEGACY_MANTISSA_MODELLED;
+            dh.shift(LEGACY_FREQ_BITS);
+            let bit = !dh.is_below(*u as legacy::Border);
+            if bit {
+                try!(dh.parse(*u as legacy::Border, (LEGACY_FREQ_MAX - *u) as legacy::Border));
+                if modelled {
+                    *u -= *u >> LEGACY_MANTISSA_UPDATE;
+                }
+            }else {
+                try!(dh.parse(0, *u as legacy::Border));
+                if modelled {
+                    *u += (LEGACY_FREQ_MAX - *u) >> LEGACY_MANTISSA_UPDATE;
+                }
+            }
+            value = (value<<1) + (bit as Distance);
+        }
+        // update contexts
+        let dist = value - 1;
+        let log_diff = log as i32 - avg_log as i32;
+        let adapt = if log_diff < -6 {7}
+            else if log_diff >= 3 {3}
+            else {ADAPT_POWERS[(6+log_diff) as usize] as i32};
+        sym.avg_dist = sym.avg_dist.wrapping_add(
+            adapt.wrapping_mul((dist as i32).wrapping_sub(sym.avg_dist)) >> 3);
+        self.last_log = log;
         Ok(dist)
     }
 }
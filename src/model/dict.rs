@@ -0,0 +1,140 @@
+/*!
+
+Dictionary neighbour model
+
+Codes the symbols of a block placed among the sorted suffixes of a dictionary
+and of the block itself. The context of a symbol is given by the symbols
+preceding the closest suffixes (the neighbours in the BWT), ordered by the
+length of their match with the block suffix. The model codes whether the symbol
+equals each of the candidates in turn, and falls back to an order-0 table
+if none of them fits.
+
+*/
+
+use std::{cmp, io};
+use compress::entropy::ari;
+use entropy::{BitDecoder, BitEncoder};
+use super::Symbol;
+use super::state;
+
+
+/// Maximum number of distinct candidate symbols
+pub const NUM_CANDIDATES: usize = 3;
+const MAX_LENGTH_CONTEXT: usize = 16;
+const BIT_UPDATE        : isize = 4;
+const LITERAL_UPDATE    : usize = 6;
+
+/// Symbol coding context, made of the dictionary neighbours
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Context {
+    /// Distinct symbols preceding the closest dictionary suffixes
+    pub symbols : [Symbol; NUM_CANDIDATES],
+    /// Match lengths of the corresponding suffixes
+    pub lengths : [usize; NUM_CANDIDATES],
+    /// Number of the candidates present
+    pub count   : usize,
+}
+
+impl Context {
+    /// Create an empty context, without candidates
+    pub fn new() -> Context {
+        Context {
+            symbols : [0; NUM_CANDIDATES],
+            lengths : [0; NUM_CANDIDATES],
+            count   : 0,
+        }
+    }
+
+    /// Add a candidate, unless its symbol is already there or the list is full
+    pub fn add(&mut self, sym: Symbol, length: usize) {
+        if self.count < NUM_CANDIDATES && !self.symbols[.. self.count].contains(&sym) {
+            self.symbols[self.count] = sym;
+            self.lengths[self.count] = length;
+            self.count += 1;
+        }
+    }
+}
+
+
+/// Coding model for the symbols placed among the dictionary suffixes
+pub struct Model {
+    /// candidate hit: [NUM_CANDIDATES][MAX_LENGTH_CONTEXT+1][2]
+    hits    : Vec<ari::apm::Bit>,
+    literal : ari::table::Model,
+    last_hit: bool,
+}
+
+impl Model {
+    /// Create a new Model instance
+    pub fn new() -> Model {
+        Model {
+            hits    : vec![ari::apm::Bit::new_equal(); NUM_CANDIDATES * (MAX_LENGTH_CONTEXT+1) * 2],
+            literal : ari::table::Model::new_flat(0x100, ari::RANGE_DEFAULT_THRESHOLD >> 2),
+            last_hit: false,
+        }
+    }
+
+    fn hit_context(&self, index: usize, length: usize) -> usize {
+        let length = cmp::min(length, MAX_LENGTH_CONTEXT);
+        (index * (MAX_LENGTH_CONTEXT+1) + length) * 2 + (self.last_hit as usize)
+    }
+}
+
+impl super::Model<Symbol, Context> for Model {
+    fn reset(&mut self) {
+        for bit in self.hits.iter_mut() {
+            *bit = ari::apm::Bit::new_equal();
+        }
+        self.literal.reset_flat();
+        self.last_hit = false;
+    }
+
+    fn encode<E: BitEncoder>(&mut self, sym: Symbol, ctx: &Context,
+              eh: &mut E) -> io::Result<()> {
+        for i in 0 .. ctx.count {
+            let hit = sym == ctx.symbols[i];
+            let index = self.hit_context(i, ctx.lengths[i]);
+            try!(eh.encode_bit(hit, &self.hits[index]));
+            self.hits[index].update(hit, BIT_UPDATE, 0);
+            if hit {
+                self.last_hit = i == 0;
+                return Ok(())
+            }
+        }
+        self.last_hit = false;
+        try!(eh.encode_symbol(sym as usize, &self.literal));
+        self.literal.update(sym as usize, LITERAL_UPDATE, 1);
+        Ok(())
+    }
+
+    fn decode<D: BitDecoder>(&mut self, ctx: &Context, dh: &mut D)
+              -> io::Result<Symbol> {
+        for i in 0 .. ctx.count {
+            let index = self.hit_context(i, ctx.lengths[i]);
+            let hit = try!(dh.decode_bit(&self.hits[index]));
+            self.hits[index].update(hit, BIT_UPDATE, 0);
+            if hit {
+                self.last_hit = i == 0;
+                return Ok(ctx.symbols[i])
+            }
+        }
+        self.last_hit = false;
+        let sym = try!(dh.decode_symbol(&self.literal));
+        self.literal.update(sym, LITERAL_UPDATE, 1);
+        Ok(sym as Symbol)
+    }
+}
+
+impl super::State for Model {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        try!(state::save_bits(&self.hits, writer));
+        state::save_table(&self.literal, writer)
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        try!(state::load_bits(&mut self.hits, reader));
+        try!(state::load_table(&mut self.literal, reader));
+        self.last_hit = false;
+        Ok(())
+    }
+}
@@ -7,30 +7,112 @@ Experimental BWT-DC compression model
 use std::io;
 use compress::bwt::dc::Context;
 use compress::entropy::ari;
-use super::Distance;
+use entropy::{BitDecoder, BitEncoder};
+use super::{Distance, ParameterInfo, Tunable};
+use super::state;
+use super::sse;
 
 
 const FIXED_BASE    : u32 = 8;
 const FIXED_MASK    : u32 = (1<<FIXED_BASE) - 1;
 const LOG_LIMIT     : usize = 10;
 const LOG_DEFAULT   : u32 = 1<<FIXED_BASE;
-const BIT_UPDATE    : isize = 5;
+const NUM_BITS      : usize = 24;
+
+/// Tunable parameters of the model
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameters {
+    /// update rate of the bit probabilities
+    pub bit_update  : isize,
+    /// update rate of the SSE stage
+    pub sse_rate    : isize,
+    /// update rate of the average symbol log
+    pub avg_rate    : u32,
+}
+
+const PARAMETER_INFO: [ParameterInfo; 3] = [
+    ("bit_update", 1, 10),
+    ("sse_rate", 1, 10),
+    ("avg_rate", 1, 6),
+];
+
+impl Parameters {
+    /// Create the default parameters
+    pub fn new() -> Parameters {
+        Parameters {
+            bit_update  : 5,
+            sse_rate    : 4,
+            avg_rate    : 2,
+        }
+    }
+}
+
+impl Default for Parameters {
+    fn default() -> Parameters {
+        Parameters::new()
+    }
+}
+
+impl Tunable for Parameters {
+    fn info() -> &'static [ParameterInfo] {
+        &PARAMETER_INFO
+    }
+
+    fn get(&self, index: usize) -> isize {
+        match index {
+            0 => self.bit_update,
+            1 => self.sse_rate,
+            2 => self.avg_rate as isize,
+            _ => panic!("Unknown parameter index: {}", index)
+        }
+    }
+
+    fn set(&mut self, index: usize, value: isize) {
+        match index {
+            0 => self.bit_update = value,
+            1 => self.sse_rate = value,
+            2 => self.avg_rate = value as u32,
+            _ => panic!("Unknown parameter index: {}", index)
+        }
+    }
+}
 
 /// Coding model for BWT-DC output
 pub struct Model {
     avg_log : [u32; 0x100],   //fixed-point
-    prob    : [[ari::apm::Bit; 24]; LOG_LIMIT],
+    prob    : [[ari::apm::Bit; NUM_BITS]; LOG_LIMIT],
+    /// refinement by [bit][log][has higher bits]
+    sse     : sse::Stage,
+    bit_update  : isize,
+    avg_rate    : u32,
 }
 
 impl Model {
-    /// Create a new model
-    pub fn new() -> Model {
+    /// Create a new model with given parameters
+    pub fn new_custom(params: Parameters) -> Model {
         Model {
             avg_log : [LOG_DEFAULT; 0x100],
-            prob    : [[ari::apm::Bit::new_equal(); 24]; LOG_LIMIT],
+            prob    : [[ari::apm::Bit::new_equal(); NUM_BITS]; LOG_LIMIT],
+            sse     : sse::Stage::new(NUM_BITS * LOG_LIMIT * 2, params.sse_rate),
+            bit_update  : params.bit_update,
+            avg_rate    : params.avg_rate,
         }
     }
 
+    /// Create a new default model
+    pub fn new() -> Model {
+        Model::new_custom(Parameters::new())
+    }
+
+    fn update_avg_log(&mut self, symbol: usize, log: u32, dist: Distance) {
+        let rate = self.avg_rate;
+        self.avg_log[symbol] = (((1<<rate) - 1)*log + Model::get_log(dist)) >> rate;
+    }
+
+    fn sse_context(bit: usize, log: u32, high: bool) -> usize {
+        ((bit * LOG_LIMIT + (log>>FIXED_BASE) as usize) << 1) + (high as usize)
+    }
+
     fn get_log(d: Distance) -> u32 {
         let du = d as u32;
         match d {
@@ -53,10 +135,11 @@ impl super::Model<Distance, Context> for Model {
                 *bit = ari::apm::Bit::new_equal();
             }
         }
+        self.sse.reset();
     }
 
-    fn encode<W: io::Write>(&mut self, dist: Distance, ctx: &Context,
-              eh: &mut ari::Encoder<W>) -> io::Result<()> {
+    fn encode<E: BitEncoder>(&mut self, dist: Distance, ctx: &Context,
+              eh: &mut E) -> io::Result<()> {
         // find context
         let log = self.avg_log[ctx.symbol as usize];
         let w2 = log & FIXED_MASK;
@@ -68,16 +151,19 @@ impl super::Model<Distance, Context> for Model {
             let value = dist & (1<<i) != 0;
             let flat = (w1 * (b1.to_flat() as u32) + w2 * (b2.to_flat() as u32)) >> FIXED_BASE;
             let bit = ari::apm::Bit::from_flat(flat as ari::apm::FlatProbability);
-            try!(eh.encode(value, &bit));
-            b1.update(value, BIT_UPDATE, 0);
-            b2.update(value, BIT_UPDATE, 0);
+            let sse_context = Model::sse_context(i, log, dist>>(i+1) != 0);
+            let (bit_refined, cookie) = self.sse.refine(&bit, sse_context);
+            try!(eh.encode_bit(value, &bit_refined));
+            self.sse.update(value, cookie);
+            b1.update(value, self.bit_update, 0);
+            b2.update(value, self.bit_update, 0);
         }
         // update
-        self.avg_log[ctx.symbol as usize] = (3*log + Model::get_log(dist)) >> 2;
+        self.update_avg_log(ctx.symbol as usize, log, dist);
         Ok(())
     }
 
-    fn decode<R: io::Read>(&mut self, ctx: &Context, dh: &mut ari::Decoder<R>)
+    fn decode<D: BitDecoder>(&mut self, ctx: &Context, dh: &mut D)
               -> io::Result<Distance> {
         // find context
         let log = self.avg_log[ctx.symbol as usize];
@@ -87,16 +173,43 @@ impl super::Model<Distance, Context> for Model {
         let (m1,m2) = (pr1.last_mut().unwrap(), &mut pr2[0]);
         // decode
         let mut dist = 0 as Distance;
-        for (b1, b2) in m1.iter_mut().zip(m2.iter_mut()).rev() {
+        for (i,(b1,b2)) in m1.iter_mut().zip(m2.iter_mut()).enumerate().rev() {
             let flat = (w1 * (b1.to_flat() as u32) + w2 * (b2.to_flat() as u32)) >> FIXED_BASE;
             let bit = ari::apm::Bit::from_flat(flat as ari::apm::FlatProbability);
-            let value = try!(dh.decode(&bit));
-            b1.update(value, BIT_UPDATE, 0);
-            b2.update(value, BIT_UPDATE, 0);
+            let sse_context = Model::sse_context(i, log, dist != 0);
+            let (bit_refined, cookie) = self.sse.refine(&bit, sse_context);
+            let value = try!(dh.decode_bit(&bit_refined));
+            self.sse.update(value, cookie);
+            b1.update(value, self.bit_update, 0);
+            b2.update(value, self.bit_update, 0);
             dist += dist + if value {1} else {0};
         }
         // update
-        self.avg_log[ctx.symbol as usize] = (3*log + Model::get_log(dist)) >> 2;
+        self.update_avg_log(ctx.symbol as usize, log, dist);
         Ok(dist)
     }
 }
+
+impl super::State for Model {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        for &log in self.avg_log.iter() {
+            try!(state::save_number(log, writer));
+        }
+        for bits in self.prob.iter() {
+            try!(state::save_bits(bits, writer));
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        for log in self.avg_log.iter_mut() {
+            *log = try!(state::load_index(reader, (LOG_LIMIT-1) << FIXED_BASE)) as u32;
+        }
+        for bits in self.prob.iter_mut() {
+            try!(state::load_bits(bits, reader));
+        }
+        // the SSE gates can't be saved
+        self.sse.reset();
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+/*!
+
+Semi-static two-pass BWT-DC model
+
+The encoder makes a first pass over the distances of every block to gather
+histograms of their binary exponents, separately for a few classes of
+the last MTF rank. The histograms are quantized to a logarithmic scale and
+transmitted before the first distance of the block, along with the number
+of the distances, by the adaptive cumulative tables of `entropy::table`.
+The distances are then coded with fixed tables: the exponent by the histogram
+of its context, and the mantissa bits as they are. Once the block is over,
+the tables of the next one follow, also when the blocks are continued.
+
+No statistics are learned during coding, so small blocks don't pay for
+the adaptation, and the coding state of any position is known in advance.
+
+*/
+
+use std::collections::VecDeque;
+use std::io;
+use compress::bwt::dc::Context;
+use compress::entropy::ari::{self, Model as AriModel};
+use entropy::{BitDecoder, BitEncoder, Part};
+use entropy::table::Frequencies;
+use super::Distance;
+
+
+const NUM_CONTEXTS  : usize = 8;
+const NUM_EXPONENTS : usize = 33;
+const NUM_LEVELS    : usize = 27;
+const THRESHOLD     : ari::Border = 1<<13;
+const STAT_THRESHOLD: ari::Border = 1<<12;
+const STAT_UPDATE   : usize = 3;
+
+fn get_context(ctx: &Context) -> usize {
+    if (ctx.last_rank as usize) < NUM_CONTEXTS {
+        ctx.last_rank as usize
+    }else {
+        NUM_CONTEXTS - 1
+    }
+}
+
+/// Adapt a table of the transmitted statistics in favor of a value
+fn adapt(freq: &mut Frequencies, value: usize) {
+    let add = (freq.get_denominator() >> STAT_UPDATE) + 1;
+    freq.update(value, add);
+}
+
+fn get_exponent(dist: Distance) -> usize {
+    63 - (dist as u64 + 1).leading_zeros() as usize
+}
+
+/// Frequency of a quantized level, the levels go by half a binary order
+fn dequantize(level: usize) -> ari::table::Frequency {
+    match level {
+        0 => 0,
+        _ => (2 + ((level-1) & 1) as ari::table::Frequency) << ((level-1) >> 1),
+    }
+}
+
+/// The level whose frequency is the closest from below to the doubled count
+fn quantize(count: u32) -> usize {
+    if count == 0 {
+        return 0
+    }
+    let mut level = 1;
+    while level+1 < NUM_LEVELS && (dequantize(level+1) as u64) <= 2*(count as u64) {
+        level += 1;
+    }
+    level
+}
+
+
+fn encode_mantissa<E: BitEncoder>(value: u64, exp: usize, eh: &mut E) -> io::Result<()> {
+    let flat = ari::apm::Bit::new_equal();
+    for i in (0 .. exp).rev() {
+        try!(eh.encode_bit((value>>i) & 1 != 0, &flat));
+    }
+    Ok(())
+}
+
+fn decode_mantissa<D: BitDecoder>(exp: usize, dh: &mut D) -> io::Result<u64> {
+    let flat = ari::apm::Bit::new_equal();
+    let mut value = 1u64;
+    for _ in 0 .. exp {
+        let bit = try!(dh.decode_bit(&flat));
+        value = (value<<1) + (bit as u64);
+    }
+    Ok(value)
+}
+
+fn not_gathered(what: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, what)
+}
+
+
+/// Semi-static model, coding distances with transmitted exponent histograms
+pub struct Model {
+    /// gathered exponent counts of the blocks to come, in the coding order
+    blocks  : VecDeque<Vec<u32>>,
+    tables  : Vec<ari::table::Model>,
+    /// number of the distances left to code by the current tables
+    remaining: usize,
+    ready   : bool,
+}
+
+impl Model {
+    /// Create a new Model with empty statistics
+    pub fn new() -> Model {
+        Model {
+            blocks  : VecDeque::new(),
+            tables  : Vec::new(),
+            remaining: 0,
+            ready   : false,
+        }
+    }
+
+    /// Start gathering the statistics of the next block
+    pub fn start_block(&mut self) {
+        self.blocks.push_back(vec![0; NUM_CONTEXTS * NUM_EXPONENTS]);
+    }
+
+    /// Account for a distance in the statistics of the last block started,
+    /// must be called for every distance of a block before encoding it
+    pub fn gather(&mut self, dist: Distance, ctx: &Context) {
+        if self.blocks.is_empty() {
+            self.start_block();
+        }
+        let counts = self.blocks.back_mut().unwrap();
+        counts[get_context(ctx) * NUM_EXPONENTS + get_exponent(dist)] += 1;
+    }
+
+    fn build(&mut self, levels: &[usize]) {
+        self.tables = levels.chunks(NUM_EXPONENTS).map(|chunk|
+            ari::table::Model::new_custom(NUM_EXPONENTS, THRESHOLD, |i| dequantize(chunk[i]))
+            ).collect();
+        self.ready = true;
+    }
+
+    fn send_tables<E: BitEncoder>(&mut self, eh: &mut E) -> io::Result<()> {
+        let counts = match self.blocks.pop_front() {
+            Some(counts) => counts,
+            None => return Err(not_gathered("no statistics are gathered for the block".to_string())),
+        };
+        // the number of the distances coded by the tables
+        self.remaining = counts.iter().fold(0, |sum, &c| sum + c as usize);
+        let exp = get_exponent(self.remaining as Distance);
+        try!(eh.encode_symbol(exp, &Frequencies::new_flat(NUM_EXPONENTS, STAT_THRESHOLD)));
+        try!(encode_mantissa(self.remaining as u64 + 1, exp, eh));
+        let levels: Vec<usize> = counts.iter().map(|&c| quantize(c)).collect();
+        let mut top_freq = Frequencies::new_flat(NUM_EXPONENTS+1, STAT_THRESHOLD);
+        let mut level_freq = Frequencies::new_flat(NUM_LEVELS, STAT_THRESHOLD);
+        for chunk in levels.chunks(NUM_EXPONENTS) {
+            // the number of exponents in use, followed by their levels
+            let top = chunk.iter().rposition(|&l| l != 0).map_or(0, |e| e+1);
+            try!(eh.encode_symbol(top, &top_freq));
+            adapt(&mut top_freq, top);
+            for &level in chunk[.. top].iter() {
+                try!(eh.encode_symbol(level, &level_freq));
+                adapt(&mut level_freq, level);
+            }
+        }
+        self.build(&levels);
+        Ok(())
+    }
+
+    fn receive_tables<D: BitDecoder>(&mut self, dh: &mut D) -> io::Result<()> {
+        let exp = try!(dh.decode_symbol(&Frequencies::new_flat(NUM_EXPONENTS, STAT_THRESHOLD)));
+        self.remaining = (try!(decode_mantissa(exp, dh)) - 1) as usize;
+        let mut levels = vec![0; NUM_CONTEXTS * NUM_EXPONENTS];
+        let mut top_freq = Frequencies::new_flat(NUM_EXPONENTS+1, STAT_THRESHOLD);
+        let mut level_freq = Frequencies::new_flat(NUM_LEVELS, STAT_THRESHOLD);
+        for chunk in levels.chunks_mut(NUM_EXPONENTS) {
+            let top = try!(dh.decode_symbol(&top_freq));
+            adapt(&mut top_freq, top);
+            for level in chunk[.. top].iter_mut() {
+                *level = try!(dh.decode_symbol(&level_freq));
+                adapt(&mut level_freq, *level);
+            }
+        }
+        self.build(&levels);
+        Ok(())
+    }
+
+    /// Account for a coded distance, the tables end with the block
+    fn advance(&mut self) {
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.ready = false;
+        }
+    }
+}
+
+impl super::Model<Distance, Context> for Model {
+    fn reset(&mut self) {
+        // the gathered statistics are kept, only the tables are to be sent again
+        self.tables.clear();
+        self.remaining = 0;
+        self.ready = false;
+    }
+
+    fn encode<E: BitEncoder>(&mut self, dist: Distance, ctx: &Context,
+              eh: &mut E) -> io::Result<()> {
+        if !self.ready {
+            try!(self.send_tables(eh));
+        }
+        let exp = get_exponent(dist);
+        if self.tables[get_context(ctx)].get_frequencies()[exp] == 0 {
+            return Err(not_gathered(format!("distance {} was not gathered in the statistics", dist)))
+        }
+        eh.set_part(Part::Exponent);
+        try!(eh.encode_symbol(exp, &self.tables[get_context(ctx)]));
+        eh.set_part(Part::Mantissa);
+        try!(encode_mantissa(dist as u64 + 1, exp, eh));
+        self.advance();
+        Ok(())
+    }
+
+    fn decode<D: BitDecoder>(&mut self, ctx: &Context, dh: &mut D)
+              -> io::Result<Distance> {
+        if !self.ready {
+            try!(self.receive_tables(dh));
+        }
+        let exp = try!(dh.decode_symbol(&self.tables[get_context(ctx)]));
+        let value = try!(decode_mantissa(exp, dh));
+        self.advance();
+        Ok((value - 1) as Distance)
+    }
+}
+
+impl super::State for Model {
+    // the tables are transmitted with the data, there is nothing learned to keep
+    fn save(&self, _writer: &mut io::Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load(&mut self, _reader: &mut io::Read) -> io::Result<()> {
+        self.tables.clear();
+        self.remaining = 0;
+        self.ready = false;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    #[test]
+    fn quantize() {
+        assert_eq!(super::quantize(0), 0);
+        for count in 1 .. 10000 {
+            let level = super::quantize(count);
+            assert!(super::dequantize(level) as u32 <= 2*count);
+            assert!(level+1 == super::NUM_LEVELS || super::dequantize(level+1) as u32 > 2*count);
+        }
+        assert_eq!(super::quantize(!0), super::NUM_LEVELS-1);
+    }
+}
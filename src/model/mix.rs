@@ -0,0 +1,462 @@
+/*!
+
+Context mixing model for BWT-DC output
+
+Distances are decomposed into binary decisions: the exponent is coded in unary,
+followed by the mantissa bits. Every decision is predicted by several independent
+predictors, which are combined by a logistic mixer with learned weights.
+The mixed probability is refined by a final APM stage before being coded.
+
+All probabilities here are the ones of a zero bit, following `ari::apm::Bit`.
+
+# Links
+
+* http://mattmahoney.net/dc/dce.html
+
+*/
+
+use std::{cmp, io};
+use compress::bwt::dc::Context;
+use compress::entropy::ari;
+use entropy::{BitDecoder, BitEncoder, Part};
+use super::{Distance, State};
+use super::{sse, state};
+
+
+const MAX_LOG           : usize = 32;
+const MANTISSA_NODES    : usize = 4;
+/// Total number of binary decision nodes
+pub const NUM_NODES     : usize = MAX_LOG + (MAX_LOG+1) * MANTISSA_NODES;
+const BIT_UPDATE        : isize = 5;
+const FLAT_BITS         : usize = 12;
+const FLAT_TOTAL        : i32 = 1<<FLAT_BITS;
+const STRETCH_LIMIT     : i32 = 2047;
+const WEIGHT_ONE        : i32 = 1<<16;
+const MIXER_RATE        : i32 = 6;
+const APM_RATE          : isize = 6;
+
+/// Binary decision node identifier
+pub type Node = usize;
+
+fn exponent_node(step: usize) -> Node {
+    step
+}
+
+fn mantissa_node(log: usize, index: usize) -> Node {
+    MAX_LOG + log * MANTISSA_NODES + cmp::min(index, MANTISSA_NODES-1)
+}
+
+fn get_log(value: Distance) -> usize {
+    let mut log = 0;
+    while value>>log != 0 {log += 1;}
+    log
+}
+
+fn clamp_flat(p: i32) -> ari::apm::FlatProbability {
+    cmp::max(1, cmp::min(FLAT_TOTAL-1, p)) as ari::apm::FlatProbability
+}
+
+
+/// A source of binary predictions for the distance decomposition
+pub trait Predictor: State {
+    /// Reset to the initial state
+    fn reset(&mut self);
+    /// Predict the probability of a zero bit at the given node
+    fn predict(&self, Node, &Context) -> ari::apm::Bit;
+    /// Adapt to the actual bit value at the given node
+    fn update(&mut self, Node, &Context, bool);
+    /// Adapt to the whole coded distance
+    fn learn(&mut self, Distance, &Context);
+}
+
+fn reset_bits(bits: &mut [ari::apm::Bit]) {
+    for b in bits.iter_mut() {
+        *b = ari::apm::Bit::new_equal();
+    }
+}
+
+
+const AVG_FIXED_BASE    : usize = 7;
+const AVG_CONTEXTS      : usize = (MAX_LOG+1) << 1;
+
+/// Predicts by the average distance exponent of the current symbol
+pub struct AvgLogPredictor {
+    avg_log : Vec<u32>,  //fixed-point
+    bits    : Vec<ari::apm::Bit>,
+}
+
+impl AvgLogPredictor {
+    /// Create a new predictor instance
+    pub fn new() -> AvgLogPredictor {
+        AvgLogPredictor {
+            avg_log : vec![0; 0x100],
+            bits    : vec![ari::apm::Bit::new_equal(); AVG_CONTEXTS * NUM_NODES],
+        }
+    }
+
+    fn index(&self, node: Node, ctx: &Context) -> usize {
+        let avg = (self.avg_log[ctx.symbol as usize] >> (AVG_FIXED_BASE-1)) as usize;
+        cmp::min(avg, AVG_CONTEXTS-1) * NUM_NODES + node
+    }
+}
+
+impl Predictor for AvgLogPredictor {
+    fn reset(&mut self) {
+        for avg in self.avg_log.iter_mut() {
+            *avg = 0;
+        }
+        reset_bits(&mut self.bits);
+    }
+
+    fn predict(&self, node: Node, ctx: &Context) -> ari::apm::Bit {
+        self.bits[self.index(node, ctx)]
+    }
+
+    fn update(&mut self, node: Node, ctx: &Context, bit: bool) {
+        let id = self.index(node, ctx);
+        self.bits[id].update(bit, BIT_UPDATE, 0);
+    }
+
+    fn learn(&mut self, dist: Distance, ctx: &Context) {
+        let log = (get_log(dist + 1) as u32) << AVG_FIXED_BASE;
+        let avg = &mut self.avg_log[ctx.symbol as usize];
+        *avg = (*avg * 3 + log) >> 2;
+    }
+}
+
+impl State for AvgLogPredictor {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        for &avg in self.avg_log.iter() {
+            try!(state::save_number(avg, writer));
+        }
+        state::save_bits(&self.bits, writer)
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        for avg in self.avg_log.iter_mut() {
+            *avg = try!(state::load_index(reader, (MAX_LOG+1) << AVG_FIXED_BASE)) as u32;
+        }
+        state::load_bits(&mut self.bits, reader)
+    }
+}
+
+
+/// Predicts by the exponent of the last coded distance, regardless of the symbol
+pub struct LastLogPredictor {
+    last_log: usize,
+    bits    : Vec<ari::apm::Bit>,
+}
+
+impl LastLogPredictor {
+    /// Create a new predictor instance
+    pub fn new() -> LastLogPredictor {
+        LastLogPredictor {
+            last_log: 0,
+            bits    : vec![ari::apm::Bit::new_equal(); (MAX_LOG+1) * NUM_NODES],
+        }
+    }
+}
+
+impl Predictor for LastLogPredictor {
+    fn reset(&mut self) {
+        self.last_log = 0;
+        reset_bits(&mut self.bits);
+    }
+
+    fn predict(&self, node: Node, _ctx: &Context) -> ari::apm::Bit {
+        self.bits[self.last_log * NUM_NODES + node]
+    }
+
+    fn update(&mut self, node: Node, _ctx: &Context, bit: bool) {
+        self.bits[self.last_log * NUM_NODES + node].update(bit, BIT_UPDATE, 0);
+    }
+
+    fn learn(&mut self, dist: Distance, _ctx: &Context) {
+        self.last_log = get_log(dist + 1);
+    }
+}
+
+impl State for LastLogPredictor {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        try!(state::save_number(self.last_log as u32, writer));
+        state::save_bits(&self.bits, writer)
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        self.last_log = try!(state::load_index(reader, MAX_LOG+1));
+        state::load_bits(&mut self.bits, reader)
+    }
+}
+
+
+const RANK_CONTEXTS: usize = 16;
+
+/// Predicts by the last known MTF rank of the symbol
+pub struct RankPredictor {
+    bits    : Vec<ari::apm::Bit>,
+}
+
+impl RankPredictor {
+    /// Create a new predictor instance
+    pub fn new() -> RankPredictor {
+        RankPredictor {
+            bits    : vec![ari::apm::Bit::new_equal(); RANK_CONTEXTS * NUM_NODES],
+        }
+    }
+
+    fn index(node: Node, ctx: &Context) -> usize {
+        cmp::min(ctx.last_rank as usize, RANK_CONTEXTS-1) * NUM_NODES + node
+    }
+}
+
+impl Predictor for RankPredictor {
+    fn reset(&mut self) {
+        reset_bits(&mut self.bits);
+    }
+
+    fn predict(&self, node: Node, ctx: &Context) -> ari::apm::Bit {
+        self.bits[RankPredictor::index(node, ctx)]
+    }
+
+    fn update(&mut self, node: Node, ctx: &Context, bit: bool) {
+        self.bits[RankPredictor::index(node, ctx)].update(bit, BIT_UPDATE, 0);
+    }
+
+    fn learn(&mut self, _dist: Distance, _ctx: &Context) {}
+}
+
+impl State for RankPredictor {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        state::save_bits(&self.bits, writer)
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        state::load_bits(&mut self.bits, reader)
+    }
+}
+
+
+/// Logistic mixer of binary predictions
+pub struct Mixer {
+    /// [num_sets][num_inputs] in 16.16 fixed point
+    weights     : Vec<i32>,
+    num_inputs  : usize,
+    stretch     : Vec<i16>,
+    squash      : Vec<u16>,
+}
+
+impl Mixer {
+    /// Create a new mixer with a given number of inputs and weight sets
+    pub fn new(num_inputs: usize, num_sets: usize) -> Mixer {
+        let stretch = (0 .. FLAT_TOTAL).map(|fp| {
+            let p = (cmp::max(fp, 1) as f32) / (FLAT_TOTAL as f32);
+            let d = (p / (1.0 - p)).ln() * 256.0;
+            d.max(-STRETCH_LIMIT as f32).min(STRETCH_LIMIT as f32) as i16
+        }).collect();
+        let squash = (-STRETCH_LIMIT-1 .. STRETCH_LIMIT+1).map(|x| {
+            let p = 1.0 / (1.0 + (-(x as f32) / 256.0).exp());
+            clamp_flat((p * FLAT_TOTAL as f32) as i32)
+        }).collect();
+        let mut mixer = Mixer {
+            weights     : vec![0; num_inputs * num_sets],
+            num_inputs  : num_inputs,
+            stretch     : stretch,
+            squash      : squash,
+        };
+        mixer.reset();
+        mixer
+    }
+
+    /// Reset the weights to be equal
+    pub fn reset(&mut self) {
+        let w = WEIGHT_ONE / self.num_inputs as i32;
+        for weight in self.weights.iter_mut() {
+            *weight = w;
+        }
+    }
+
+    /// Convert the inputs into the logistic domain
+    pub fn prepare(&self, inputs: &[ari::apm::Bit], stretched: &mut [i32]) {
+        for (s, bit) in stretched.iter_mut().zip(inputs.iter()) {
+            *s = self.stretch[bit.to_flat() as usize] as i32;
+        }
+    }
+
+    /// Mix the stretched inputs with a given weight set
+    pub fn mix(&self, set: usize, stretched: &[i32]) -> ari::apm::Bit {
+        let weights = &self.weights[set * self.num_inputs ..];
+        let dot = stretched.iter().zip(weights.iter()).fold(0i64, |sum, (&s, &w)| {
+            sum + (s as i64) * (w as i64)
+        });
+        let x = cmp::max(-STRETCH_LIMIT-1, cmp::min(STRETCH_LIMIT, (dot >> 16) as i32));
+        ari::apm::Bit::from_flat(self.squash[(x + STRETCH_LIMIT + 1) as usize])
+    }
+
+    /// Adjust the weight set towards the actual bit
+    pub fn update(&mut self, set: usize, stretched: &[i32], mixed: ari::apm::Bit, bit: bool) {
+        let target = if bit {0} else {FLAT_TOTAL};
+        let err = (target - mixed.to_flat() as i32) * MIXER_RATE;
+        let num = self.num_inputs;
+        for (w, &s) in self.weights[set * num .. (set+1) * num].iter_mut().zip(stretched.iter()) {
+            *w += (s * err + 0x8000) >> 16;
+        }
+    }
+}
+
+impl State for Mixer {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        for &w in self.weights.iter() {
+            try!(state::save_number(w as u32, writer));
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        for w in self.weights.iter_mut() {
+            *w = try!(state::load_number(reader)) as i32;
+        }
+        Ok(())
+    }
+}
+
+
+/// Coding model mixing several distance predictors
+pub struct Model {
+    predictors  : Vec<Box<Predictor>>,
+    mixer       : Mixer,
+    sse         : sse::Stage,
+    inputs      : Vec<ari::apm::Bit>,
+    stretched   : Vec<i32>,
+}
+
+impl Model {
+    /// Create a new model from a given set of predictors
+    pub fn with_predictors(predictors: Vec<Box<Predictor>>) -> Model {
+        let num = predictors.len();
+        assert!(num >= 2);
+        Model {
+            predictors  : predictors,
+            mixer       : Mixer::new(num, NUM_NODES),
+            sse         : sse::Stage::new(NUM_NODES, APM_RATE),
+            inputs      : vec![ari::apm::Bit::new_equal(); num],
+            stretched   : vec![0; num],
+        }
+    }
+
+    /// Create a new model with the default predictors
+    pub fn new() -> Model {
+        Model::with_predictors(vec![
+            Box::new(AvgLogPredictor::new()),
+            Box::new(LastLogPredictor::new()),
+            Box::new(RankPredictor::new()),
+        ])
+    }
+
+    fn predict(&mut self, node: Node, ctx: &Context) -> (ari::apm::Bit, ari::apm::Bit, sse::Cookie) {
+        for (input, pred) in self.inputs.iter_mut().zip(self.predictors.iter()) {
+            *input = pred.predict(node, ctx);
+        }
+        self.mixer.prepare(&self.inputs, &mut self.stretched);
+        let mixed = self.mixer.mix(node, &self.stretched);
+        let (refined, cookie) = self.sse.refine(&mixed, node);
+        (refined, mixed, cookie)
+    }
+
+    fn update(&mut self, node: Node, ctx: &Context, bit: bool,
+              mixed: ari::apm::Bit, cookie: sse::Cookie) {
+        for pred in self.predictors.iter_mut() {
+            pred.update(node, ctx, bit);
+        }
+        self.mixer.update(node, &self.stretched, mixed, bit);
+        self.sse.update(bit, cookie);
+    }
+
+    fn encode_bit<E: BitEncoder>(&mut self, bit: bool, node: Node, ctx: &Context,
+                  eh: &mut E) -> io::Result<()> {
+        let (prob, mixed, cookie) = self.predict(node, ctx);
+        try!(eh.encode_bit(bit, &prob));
+        self.update(node, ctx, bit, mixed, cookie);
+        Ok(())
+    }
+
+    fn decode_bit<D: BitDecoder>(&mut self, node: Node, ctx: &Context,
+                  dh: &mut D) -> io::Result<bool> {
+        let (prob, mixed, cookie) = self.predict(node, ctx);
+        let bit = try!(dh.decode_bit(&prob));
+        self.update(node, ctx, bit, mixed, cookie);
+        Ok(bit)
+    }
+}
+
+impl super::Model<Distance, Context> for Model {
+    fn reset(&mut self) {
+        for pred in self.predictors.iter_mut() {
+            pred.reset();
+        }
+        self.mixer.reset();
+        self.sse.reset();
+    }
+
+    fn encode<E: BitEncoder>(&mut self, dist: Distance, ctx: &Context,
+              eh: &mut E) -> io::Result<()> {
+        let value = dist + 1;
+        let log = get_log(value);
+        debug!("Mix encoding distance {} with log {}", dist, log);
+        // exponent in unary
+        eh.set_part(Part::Exponent);
+        for step in 1 .. MAX_LOG {
+            try!(self.encode_bit(step < log, exponent_node(step), ctx, eh));
+            if step >= log {
+                break
+            }
+        }
+        // mantissa
+        eh.set_part(Part::Mantissa);
+        for i in 1 .. log {
+            let bit = (value >> (log-i-1)) & 1 != 0;
+            try!(self.encode_bit(bit, mantissa_node(log, i-1), ctx, eh));
+        }
+        for pred in self.predictors.iter_mut() {
+            pred.learn(dist, ctx);
+        }
+        Ok(())
+    }
+
+    fn decode<D: BitDecoder>(&mut self, ctx: &Context, dh: &mut D)
+              -> io::Result<Distance> {
+        let mut log = 1;
+        while log < MAX_LOG && try!(self.decode_bit(exponent_node(log), ctx, dh)) {
+            log += 1;
+        }
+        let mut value = 1 as Distance;
+        for i in 1 .. log {
+            let bit = try!(self.decode_bit(mantissa_node(log, i-1), ctx, dh));
+            value = (value<<1) + (bit as Distance);
+        }
+        let dist = value - 1;
+        debug!("Mix decoded distance {} with log {}", dist, log);
+        for pred in self.predictors.iter_mut() {
+            pred.learn(dist, ctx);
+        }
+        Ok(dist)
+    }
+}
+
+impl State for Model {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        for pred in self.predictors.iter() {
+            try!(pred.save(writer));
+        }
+        self.mixer.save(writer)
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        for pred in self.predictors.iter_mut() {
+            try!(pred.load(reader));
+        }
+        try!(self.mixer.load(reader));
+        // the SSE gates can't be saved
+        self.sse.reset();
+        Ok(())
+    }
+}
@@ -5,37 +5,138 @@ Various BWT-DC compression models
 */
 
 use compress::bwt::dc;
-use compress::entropy::ari;
-use std::io;
+use entropy::{BitDecoder, BitEncoder};
+use std::{cmp, fmt, io};
 
 /// A copy of `bbb` model
 pub mod bbb;
 /// Old Dark-0.51 model
 pub mod dark;
+/// Dictionary neighbour model
+pub mod dict;
 /// Original BWT-DC compression model
 pub mod exp;
+/// Semi-static two-pass model with transmitted statistics
+pub mod fixed;
+/// Context mixing of several distance predictors
+pub mod mix;
+/// QLFC rank and run length model
+pub mod qlfc;
 /// Raw output for debugging
 pub mod raw;
 /// A simplest model to compare with
 pub mod simple;
-/// A attempt to reproduce YBS model
+/// Secondary symbol estimation stage
+pub mod sse;
+/// Model state persistence helpers
+pub mod state;
+/// YBS-style model with mixed contexts and SSE
 pub mod ybs;
 
 /// Distance type
 pub type Distance = u32;
 /// Symbol type
 pub type Symbol = u8;
-/// Symbol encoding context //TODO
-pub type SymContext = ();
+
+/// Symbol encoding context, tracked by the raw block coder
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SymContext {
+    /// Last 4 BWT output symbols, the latest is in the low 8 bits
+    pub history: u32,
+    /// Number of repeats of the last symbol (0 if it differs from the previous)
+    pub run_length: usize,
+    /// Symbol of the current BWT bucket (the first column symbol)
+    pub bucket: Symbol,
+    /// Position inside the current bucket
+    pub bucket_offset: usize,
+    /// Total size of the current bucket
+    pub bucket_size: usize,
+    /// Number of the symbols in the history, up to 4
+    pub history_length: usize,
+}
+
+impl SymContext {
+    /// Create an empty context
+    pub fn new() -> SymContext {
+        SymContext {
+            history: 0,
+            run_length: 0,
+            bucket: 0,
+            bucket_offset: 0,
+            bucket_size: 0,
+            history_length: 0,
+        }
+    }
+
+    /// Update the symbol history with a newly coded symbol
+    pub fn update(&mut self, sym: Symbol) {
+        if self.history_length != 0 && self.history as Symbol == sym {
+            self.run_length += 1;
+        }else {
+            self.run_length = 0;
+        }
+        self.history = (self.history << 8) | (sym as u32);
+        self.history_length = cmp::min(self.history_length + 1, 4);
+    }
+}
 
 /// An abstract BWT output encoding model (BWT-???-Ari)
 pub trait Model<T, C> {
     /// Reset current estimations
     fn reset(&mut self);
     /// Encode an element
-    fn encode<W: io::Write>(&mut self, T, &C, &mut ari::Encoder<W>) -> io::Result<()>;
+    fn encode<E: BitEncoder>(&mut self, T, &C, &mut E) -> io::Result<()>;
     /// Decode an element
-    fn decode<R: io::Read>(&mut self, &C, &mut ari::Decoder<R>) -> io::Result<T>;
+    fn decode<D: BitDecoder>(&mut self, &C, &mut D) -> io::Result<T>;
+}
+
+/// Name and the inclusive range of values of a tunable parameter
+pub type ParameterInfo = (&'static str, isize, isize);
+
+/// A vector of model parameters, which can be searched over
+pub trait Tunable: Clone + Default + fmt::Debug {
+    /// Describe all the parameters, in the order of their indices
+    fn info() -> &'static [ParameterInfo];
+    /// Get a parameter value by index
+    fn get(&self, index: usize) -> isize;
+    /// Set a parameter value by index
+    fn set(&mut self, index: usize, value: isize);
+    /// Collect the values of all the parameters
+    fn values(&self) -> Vec<isize> {
+        (0 .. Self::info().len()).map(|i| self.get(i)).collect()
+    }
+}
+
+/// A model whose statistics can be written out and restored, in order to
+/// start coding from a learned state instead of the initial one
+pub trait State {
+    /// Write the current statistics
+    fn save(&self, &mut io::Write) -> io::Result<()>;
+    /// Replace the statistics with the ones written by `save`
+    fn load(&mut self, &mut io::Read) -> io::Result<()>;
+}
+
+/// MTF rank type
+pub type Rank = u8;
+/// Run length type
+pub type Length = u32;
+
+/// Rank encoding context
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RankContext {
+    /// Rank of the previous run (or symbol)
+    pub last_rank: Rank,
+    /// Length of the previous run (or the number of zero ranks before)
+    pub last_length: Length,
+}
+
+/// Run length encoding context
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunContext {
+    /// Symbol of the current run
+    pub symbol: Symbol,
+    /// MTF rank of the current run
+    pub rank: Rank,
 }
 
 /// A generic BWT-DC output coding model
@@ -46,6 +147,14 @@ impl<M: Model<Distance, dc::Context>> DistanceModel for M {}
 pub trait RawModel: Model<Symbol, SymContext> {}
 impl<M: Model<Symbol, SymContext>> RawModel for M {}
 
+/// A generic list update rank coding model
+pub trait RankModel: Model<Rank, RankContext> {}
+impl<M: Model<Rank, RankContext>> RankModel for M {}
+
+/// A generic BWT-MTF run coding model, providing both ranks and lengths
+pub trait RunModel: RankModel + Model<Length, RunContext> {}
+impl<M: RankModel + Model<Length, RunContext>> RunModel for M {}
+
 
 #[cfg(test)]
 pub mod test {
@@ -55,6 +164,8 @@ pub mod test {
     use compress::entropy::ari;
     use super::{Distance, DistanceModel};
     use super::{RawModel, Symbol, SymContext};
+    use super::{Length, Model, Rank, RankContext, RunContext, RunModel};
+    use super::State;
 
     fn roundtrip_dc<M: DistanceModel>(m: &mut M, input: &[(Distance, dc::Context)]) {
         let mut eh = ari::Encoder::new(Vec::new());
@@ -92,6 +203,28 @@ pub mod test {
         }
     }
 
+    fn roundtrip_run<M: RunModel>(mut m: M, input: &[(Rank, RankContext, Length, RunContext)]) {
+        let mut eh = ari::Encoder::new(Vec::new());
+        Model::<Rank, RankContext>::reset(&mut m);
+        Model::<Length, RunContext>::reset(&mut m);
+        for &(rank, ref rctx, len, ref lctx) in input.iter() {
+            debug!("Encode: rank {}, length {}", rank, len);
+            Model::<Rank, RankContext>::encode(&mut m, rank, rctx, &mut eh).unwrap();
+            Model::<Length, RunContext>::encode(&mut m, len, lctx, &mut eh).unwrap();
+        }
+        let (mem, err) = eh.finish();
+        err.unwrap();
+        Model::<Rank, RankContext>::reset(&mut m);
+        Model::<Length, RunContext>::reset(&mut m);
+        let mut dh = ari::Decoder::new(io::BufReader::new(io::Cursor::new(&mem[..])));
+        for &(rank, ref rctx, len, ref lctx) in input.iter() {
+            let rank2 = Model::<Rank, RankContext>::decode(&mut m, rctx, &mut dh).unwrap();
+            let len2 = Model::<Length, RunContext>::decode(&mut m, lctx, &mut dh).unwrap();
+            debug!("Actual: ({}, {}), Decoded: ({}, {})", rank, len, rank2, len2);
+            assert_eq!((rank, len), (rank2, len2));
+        }
+    }
+
     fn gen_data_dc(size: usize, max_dist: Distance) -> Vec<(Distance, dc::Context)> {
         let mut rng = StdRng::new().unwrap();
         (0..size).map(|_| {
@@ -103,9 +236,27 @@ pub mod test {
 
     fn gen_data_raw(size: usize) -> Vec<(Symbol, SymContext)> {
         let mut rng = StdRng::new().unwrap();
+        let mut ctx = SymContext::new();
         (0..size).map(|_| {
             let sym: Symbol = rng.gen();
-            (sym, ())
+            let cur = ctx;
+            ctx.update(sym);
+            ctx.bucket = rng.gen();
+            (sym, cur)
+        }).collect()
+    }
+
+    fn gen_data_run(size: usize) -> Vec<(Rank, RankContext, Length, RunContext)> {
+        let mut rng = StdRng::new().unwrap();
+        let mut rctx = RankContext { last_rank: 0, last_length: 0 };
+        (0..size).map(|_| {
+            let rank: Rank = rng.gen();
+            let log = rng.gen_range(1, 20);
+            let length = rng.gen_range(1, 1<<log);
+            let lctx = RunContext { symbol: rng.gen(), rank: rank };
+            let cur = rctx;
+            rctx = RankContext { last_rank: rank, last_length: length };
+            (rank, cur, length, lctx)
         }).collect()
     }
 
@@ -119,15 +270,75 @@ pub mod test {
         roundtrip_dc(&mut m, &gen_data_dc(1000,200));
     }
 
+    fn resave<M: State>(m: &mut M, state: &[u8]) -> Vec<u8> {
+        m.load(&mut &state[..]).unwrap();
+        let mut copy = Vec::new();
+        m.save(&mut copy).unwrap();
+        copy
+    }
+
+    /// Train a model, then check that its saved state survives a reload.
+    /// Binary frequency models are restored approximately, so for them
+    /// the state only has to be stable from the second reload on.
+    fn reload_dc<M: DistanceModel + State>(mut m: M, mut fresh: M, exact: bool) {
+        let input = gen_data_dc(1000, 200);
+        let mut eh = ari::Encoder::new(Vec::new());
+        for &(dist, ref ctx) in input.iter() {
+            m.encode(dist, ctx, &mut eh).unwrap();
+        }
+        let mut state = Vec::new();
+        m.save(&mut state).unwrap();
+        let copy = resave(&mut fresh, &state);
+        if exact {
+            assert!(copy == state);
+        }else {
+            assert_eq!(copy.len(), state.len());
+            assert!(resave(&mut fresh, &copy) == copy);
+        }
+        assert!(fresh.load(&mut &state[.. state.len()-1]).is_err());
+    }
+
     #[test]
     fn roundtrip_bbb() {
         let input = gen_data_raw(1000);
         roundtrip_raw(super::bbb::Model::new(), &input);
     }
     
+    #[test]
+    fn sym_context() {
+        let mut ctx = SymContext::new();
+        // nothing precedes the first symbol, even if it is a zero
+        for &(sym, run_length) in [(0, 0), (0, 1), (0, 2), (5, 0), (5, 1)].iter() {
+            ctx.update(sym);
+            assert_eq!(ctx.run_length, run_length);
+        }
+        assert_eq!(ctx.history_length, 4);
+    }
+
+    #[test]
+    fn aggregate_find_value() {
+        use compress::entropy::ari::Model;
+        let mut rng = StdRng::new().unwrap();
+        let threshold = ari::RANGE_DEFAULT_THRESHOLD >> 2;
+        let mut x = ari::table::Model::new_flat(8, threshold);
+        let mut y = ari::table::Model::new_flat(8, threshold);
+        for _ in 0..100 {
+            x.update(rng.gen_range(0, 8), 5, 1);
+            y.update(rng.gen_range(0, 3), 5, 1);
+        }
+        let agg = super::dark::Aggregate::new(1,&x, 2,&y);
+        for offset in 0 .. agg.get_denominator() {
+            let (value, lo, hi) = agg.find_value(offset);
+            assert!(lo <= offset && offset < hi);
+            assert_eq!(agg.get_range(value), (lo, hi));
+        }
+    }
+
     #[test]
     fn roundtrips_dark() {
         roundtrips_dc(super::dark::Model::new());
+        let mut m = super::dark::Model::new();
+        roundtrip_dc(&mut m, &gen_data_dc(1000, 1<<24));
     }
 
     #[test]
@@ -135,6 +346,41 @@ pub mod test {
         roundtrips_dc(super::exp::Model::new());
     }
 
+    #[test]
+    fn roundtrips_fixed() {
+        let input = gen_data_dc(1000, 200);
+        let mut m = super::fixed::Model::new();
+        for &(dist, ref ctx) in input.iter() {
+            m.gather(dist, ctx);
+        }
+        roundtrip_dc(&mut m, &input);
+        // blocks of their own statistics, coded one after another
+        let mut m = super::fixed::Model::new();
+        for block in input.chunks(300) {
+            m.start_block();
+            for &(dist, ref ctx) in block.iter() {
+                m.gather(dist, ctx);
+            }
+        }
+        roundtrip_dc(&mut m, &input);
+        // a distance out of the statistics
+        let mut m = super::fixed::Model::new();
+        m.gather(1, &input[0].1);
+        m.reset();
+        let mut eh = ari::Encoder::new(Vec::new());
+        assert!(m.encode(1000, &input[0].1, &mut eh).is_err());
+    }
+
+    #[test]
+    fn roundtrips_mix() {
+        roundtrips_dc(super::mix::Model::new());
+    }
+
+    #[test]
+    fn roundtrips_qlfc() {
+        roundtrip_run(super::qlfc::Model::new(), &gen_data_run(1000));
+    }
+
     #[test]
     fn roundtrips_simple() {
         roundtrips_dc(super::simple::Model::new());
@@ -144,4 +390,13 @@ pub mod test {
     fn roundtrips_ybs() {
         roundtrips_dc(super::ybs::Model::new());
     }
+
+    #[test]
+    fn reload_states() {
+        reload_dc(super::dark::Model::new(), super::dark::Model::new(), false);
+        reload_dc(super::exp::Model::new(), super::exp::Model::new(), true);
+        reload_dc(super::mix::Model::new(), super::mix::Model::new(), true);
+        reload_dc(super::simple::Model::new(), super::simple::Model::new(), true);
+        reload_dc(super::ybs::Model::new(), super::ybs::Model::new(), true);
+    }
 }
@@ -0,0 +1,223 @@
+/*!
+
+QLFC (quantized local frequency coding) model
+
+Codes the MTF rank of each run of identical symbols, followed by the run length.
+Both are split into an exponent, coded with adaptive frequency tables,
+and a mantissa, coded bit by bit.
+
+# Links
+
+* https://github.com/IlyaGrebnov/libbsc
+
+*/
+
+use std::{cmp, io};
+use compress::entropy::ari;
+use entropy::{BitDecoder, BitEncoder};
+use super::{Length, Rank, RankContext, RunContext};
+use super::state;
+
+
+const RANK_LOGS         : usize = 9;
+const RANK_CONTEXTS     : usize = 8;
+const LENGTH_LOGS       : usize = 33;
+const LENGTH_CONTEXTS   : usize = 4;
+const MANTISSA_BITS     : usize = 8;
+const BIT_UPDATE        : isize = 5;
+
+fn get_log(value: u32) -> usize {
+    let mut log = 0;
+    while value>>log != 0 {log += 1;}
+    log
+}
+
+/// Quantize the previous run length into [0,3)
+fn length_bucket(length: Length) -> usize {
+    match length {
+        0...1   => 0,
+        2...3   => 1,
+        _       => 2,
+    }
+}
+
+
+/// Coding model for BWT-MTF runs
+pub struct Model {
+    /// rank exponent: [RANK_CONTEXTS*3]
+    rank_log    : Vec<ari::table::Model>,
+    rank_bits   : [[ari::apm::Bit; MANTISSA_BITS]; RANK_LOGS],
+    /// length exponent, by symbol: [0x100]
+    length_sym  : Vec<ari::table::Model>,
+    /// length exponent, by rank: [LENGTH_CONTEXTS]
+    length_rank : Vec<ari::table::Model>,
+    length_bits : [[ari::apm::Bit; MANTISSA_BITS]; LENGTH_LOGS],
+}
+
+impl Model {
+    /// Create a new Model instance
+    pub fn new_custom(threshold: ari::Border) -> Model {
+        Model {
+            rank_log    : (0 .. RANK_CONTEXTS*3).map(|_|
+                ari::table::Model::new_flat(RANK_LOGS, threshold)
+                ).collect(),
+            rank_bits   : [[ari::apm::Bit::new_equal(); MANTISSA_BITS]; RANK_LOGS],
+            length_sym  : (0..0x100).map(|_|
+                ari::table::Model::new_flat(LENGTH_LOGS-1, threshold)
+                ).collect(),
+            length_rank : (0..LENGTH_CONTEXTS).map(|_|
+                ari::table::Model::new_flat(LENGTH_LOGS-1, threshold)
+                ).collect(),
+            length_bits : [[ari::apm::Bit::new_equal(); MANTISSA_BITS]; LENGTH_LOGS],
+        }
+    }
+
+    /// Create a new default Model
+    pub fn new() -> Model {
+        Model::new_custom(ari::RANGE_DEFAULT_THRESHOLD >> 2)
+    }
+
+    fn rank_context(ctx: &RankContext) -> usize {
+        cmp::min(ctx.last_rank as usize, RANK_CONTEXTS-1) * 3 + length_bucket(ctx.last_length)
+    }
+
+    fn encode_mantissa<E: BitEncoder>(value: u32, log: usize, bits: &mut [ari::apm::Bit; MANTISSA_BITS],
+                       eh: &mut E) -> io::Result<()> {
+        let flat = ari::apm::Bit::new_equal();
+        for i in 1 .. log {
+            let bit = (value>>(log-i-1)) & 1 != 0;
+            if i > MANTISSA_BITS {
+                try!(eh.encode_bit(bit, &flat));
+            }else {
+                let bc = &mut bits[i-1];
+                try!(eh.encode_bit(bit, bc));
+                bc.update(bit, BIT_UPDATE, 0);
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_mantissa<D: BitDecoder>(log: usize, bits: &mut [ari::apm::Bit; MANTISSA_BITS],
+                       dh: &mut D) -> io::Result<u32> {
+        let flat = ari::apm::Bit::new_equal();
+        let mut value = 1u32;
+        for i in 1 .. log {
+            let bit = if i > MANTISSA_BITS {
+                try!(dh.decode_bit(&flat))
+            }else {
+                let bc = &mut bits[i-1];
+                let bit = try!(dh.decode_bit(bc));
+                bc.update(bit, BIT_UPDATE, 0);
+                bit
+            };
+            value = (value<<1) + (bit as u32);
+        }
+        Ok(value)
+    }
+}
+
+impl super::Model<Rank, RankContext> for Model {
+    fn reset(&mut self) {
+        for table in self.rank_log.iter_mut() {
+            table.reset_flat();
+        }
+        for bits in self.rank_bits.iter_mut() {
+            for bit in bits.iter_mut() {
+                *bit = ari::apm::Bit::new_equal();
+            }
+        }
+    }
+
+    fn encode<E: BitEncoder>(&mut self, rank: Rank, ctx: &RankContext,
+              eh: &mut E) -> io::Result<()> {
+        let log = get_log(rank as u32);
+        let freq = &mut self.rank_log[Model::rank_context(ctx)];
+        debug!("QLFC encoding rank {} with log {}", rank, log);
+        try!(eh.encode_symbol(log, freq));
+        freq.update(log, 5, 1);
+        Model::encode_mantissa(rank as u32, log, &mut self.rank_bits[log], eh)
+    }
+
+    fn decode<D: BitDecoder>(&mut self, ctx: &RankContext, dh: &mut D)
+              -> io::Result<Rank> {
+        let freq = &mut self.rank_log[Model::rank_context(ctx)];
+        let log = try!(dh.decode_symbol(freq));
+        freq.update(log, 5, 1);
+        if log == 0 {
+            return Ok(0)
+        }
+        let rank = try!(Model::decode_mantissa(log, &mut self.rank_bits[log], dh));
+        debug!("QLFC decoded rank {} with log {}", rank, log);
+        Ok(rank as Rank)
+    }
+}
+
+impl super::Model<Length, RunContext> for Model {
+    fn reset(&mut self) {
+        for table in self.length_sym.iter_mut() {
+            table.reset_flat();
+        }
+        for table in self.length_rank.iter_mut() {
+            table.reset_flat();
+        }
+        for bits in self.length_bits.iter_mut() {
+            for bit in bits.iter_mut() {
+                *bit = ari::apm::Bit::new_equal();
+            }
+        }
+    }
+
+    fn encode<E: BitEncoder>(&mut self, length: Length, ctx: &RunContext,
+              eh: &mut E) -> io::Result<()> {
+        debug_assert!(length > 0);
+        let log = get_log(length);
+        let sym_freq = &mut self.length_sym[ctx.symbol as usize];
+        let rank_freq = &mut self.length_rank[cmp::min(ctx.rank as usize, LENGTH_CONTEXTS-1)];
+        debug!("QLFC encoding length {} with log {}", length, log);
+        try!(eh.encode_symbol(log-1, &ari::table::SumProxy::new(1,sym_freq, 1,rank_freq, 0)));
+        sym_freq.update(log-1, 5, 1);
+        rank_freq.update(log-1, 6, 1);
+        Model::encode_mantissa(length, log, &mut self.length_bits[log], eh)
+    }
+
+    fn decode<D: BitDecoder>(&mut self, ctx: &RunContext, dh: &mut D)
+              -> io::Result<Length> {
+        let sym_freq = &mut self.length_sym[ctx.symbol as usize];
+        let rank_freq = &mut self.length_rank[cmp::min(ctx.rank as usize, LENGTH_CONTEXTS-1)];
+        let log_pre = try!(dh.decode_symbol(&ari::table::SumProxy::new(1,sym_freq, 1,rank_freq, 0)));
+        sym_freq.update(log_pre, 5, 1);
+        rank_freq.update(log_pre, 6, 1);
+        let log = log_pre + 1;
+        let length = try!(Model::decode_mantissa(log, &mut self.length_bits[log], dh));
+        debug!("QLFC decoded length {} with log {}", length, log);
+        Ok(length)
+    }
+}
+
+impl super::State for Model {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        try!(state::save_tables(&self.rank_log, writer));
+        for bits in self.rank_bits.iter() {
+            try!(state::save_bits(bits, writer));
+        }
+        try!(state::save_tables(&self.length_sym, writer));
+        try!(state::save_tables(&self.length_rank, writer));
+        for bits in self.length_bits.iter() {
+            try!(state::save_bits(bits, writer));
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        try!(state::load_tables(&mut self.rank_log, reader));
+        for bits in self.rank_bits.iter_mut() {
+            try!(state::load_bits(bits, reader));
+        }
+        try!(state::load_tables(&mut self.length_sym, reader));
+        try!(state::load_tables(&mut self.length_rank, reader));
+        for bits in self.length_bits.iter_mut() {
+            try!(state::load_bits(bits, reader));
+        }
+        Ok(())
+    }
+}
@@ -1,76 +1,291 @@
-use byteorder::{LittleEndian, WriteBytesExt};
+/*!
+
+Raw dump models
+
+These models write every value they code into a dump, in order to study
+the output of a block transform outside of the compressor. The values are
+also coded into the stream with flat probabilities, so the stream can be
+decoded, and the decoder writes the same dump as the encoder.
+
+`DcOut` dumps the distances coded by the DC block coder with their
+contexts, in the coding order: the initial distances with the runs of the
+present symbols, the DC distances and the BWT origin. Each is a 10-byte
+record:
+
+| Bytes | Value                                  |
+|-------|----------------------------------------|
+| 0-3   | distance, 32 bits                      |
+| 4     | context symbol                         |
+| 5     | last rank of the context               |
+| 6-9   | distance limit of the context, 32 bits |
+
+The 32-bit numbers of these records are little endian.
+
+`Out` dumps the bytes coded by the raw block coder: the BWT origin as
+4 bytes in big endian order, as the raw coder codes them, then the BWT
+output symbols. In both dumps, the records of all the blocks of a stream
+follow each other without separators.
+
+A dump of `DcOut` can be read back by `Reader` and replayed through any
+distance model with `replay`, measuring the cost of the distances without
+repeating the BWT.
+
+*/
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use compress::bwt::dc;
 use compress::entropy::ari;
-use std::fs::File;
+use entropy::{BitDecoder, BitEncoder};
+use entropy::meter::Meter;
 use std::io;
 
-use super::{Distance, Model};
+use block::stats::{Cost, get_exponent};
+use super::{Distance, DistanceModel, Model, State};
 use super::{Symbol, SymContext};
 
+/// Size of a `DcOut` record in bytes
+pub const RECORD_SIZE: usize = 10;
+
+/// Bits coding the exponent of a distance
+const EXPONENT_BITS: usize = 6;
+
+fn encode_flat<E: BitEncoder>(value: u64, num_bits: usize, eh: &mut E) -> io::Result<()> {
+    let flat = ari::apm::Bit::new_equal();
+    for i in (0 .. num_bits).rev() {
+        try!(eh.encode_bit((value>>i) & 1 != 0, &flat));
+    }
+    Ok(())
+}
+
+fn decode_flat<D: BitDecoder>(num_bits: usize, dh: &mut D) -> io::Result<u64> {
+    let flat = ari::apm::Bit::new_equal();
+    let mut value = 0;
+    for _ in 0 .. num_bits {
+        value = (value<<1) + (try!(dh.decode_bit(&flat)) as u64);
+    }
+    Ok(value)
+}
+
+/// Write a record of a distance with its context
+pub fn write_record<W: io::Write>(writer: &mut W, d: Distance, c: &dc::Context) -> io::Result<()> {
+    try!(writer.write_u32::<LittleEndian>(d));
+    try!(writer.write_u8(c.symbol));
+    try!(writer.write_u8(c.last_rank));
+    try!(writer.write_u32::<LittleEndian>(c.distance_limit as u32));
+    Ok(())
+}
+
 
 /// Raw (Sym, Dist) pairs output
-pub struct DcOut {
-    out: File,
+pub struct DcOut<W> {
+    out: W,
 }
 
-impl DcOut {
-    /// Create a new raw output model
-    pub fn new() -> DcOut {
+impl<W: io::Write> DcOut<W> {
+    /// Create a new raw output model, dumping into a given writer
+    pub fn new(out: W) -> DcOut<W> {
         DcOut {
-            out: File::create("out-dc.raw").unwrap(),
+            out: out,
         }
     }
 }
 
-impl Model<Distance, dc::Context> for DcOut {
+impl<W: io::Write> Model<Distance, dc::Context> for DcOut<W> {
     fn reset(&mut self) {}
 
-    fn encode<W: io::Write>(&mut self, d: Distance, c: &dc::Context,
-              _enc: &mut ari::Encoder<W>) -> io::Result<()>
+    fn encode<E: BitEncoder>(&mut self, d: Distance, c: &dc::Context,
+              eh: &mut E) -> io::Result<()>
     {
         debug!("Encoding raw distance {} for symbol {}", d, c.symbol);
-        try!(self.out.write_u32::<LittleEndian>(d));
-        try!(self.out.write_u8(c.symbol));
-        try!(self.out.write_u8(c.last_rank));
-        try!(self.out.write_u32::<LittleEndian>(c.distance_limit as u32));
-        Ok(())
+        try!(write_record(&mut self.out, d, c));
+        // the exponent, then the bits of d+1 below the highest one
+        let exp = get_exponent(d);
+        try!(encode_flat(exp as u64, EXPONENT_BITS, eh));
+        encode_flat(d as u64 + 1, exp, eh)
     }
 
-    fn decode<R: io::Read>(&mut self, _c: &dc::Context,
-              _dec: &mut ari::Decoder<R>) -> io::Result<Distance>
+    fn decode<D: BitDecoder>(&mut self, c: &dc::Context,
+              dh: &mut D) -> io::Result<Distance>
     {
-        Ok(0) //not supported
+        let exp = try!(decode_flat(EXPONENT_BITS, dh)) as usize;
+        let d = ((1u64<<exp) + try!(decode_flat(exp, dh)) - 1) as Distance;
+        try!(write_record(&mut self.out, d, c));
+        Ok(d)
+    }
+}
+
+impl<W> State for DcOut<W> {
+    fn save(&self, _writer: &mut io::Write) -> io::Result<()> {
+        Ok(()) //stateless
+    }
+
+    fn load(&mut self, _reader: &mut io::Read) -> io::Result<()> {
+        Ok(())
     }
 }
 
 /// Raw sym output
-pub struct Out {
-    out: File,
+pub struct Out<W> {
+    out: W,
 }
 
-impl Out {
-    /// Create a new raw output model
-    pub fn new() -> Out {
+impl<W: io::Write> Out<W> {
+    /// Create a new raw output model, dumping into a given writer
+    pub fn new(out: W) -> Out<W> {
         Out {
-            out: File::create("out.raw").unwrap(),
+            out: out,
         }
     }
 }
 
-impl Model<Symbol, SymContext> for Out {
+impl<W: io::Write> Model<Symbol, SymContext> for Out<W> {
     fn reset(&mut self) {}
 
-    fn encode<W: io::Write>(&mut self, sym: Symbol, _: &SymContext,
-              _enc: &mut ari::Encoder<W>) -> io::Result<()>
+    fn encode<E: BitEncoder>(&mut self, sym: Symbol, _: &SymContext,
+              eh: &mut E) -> io::Result<()>
     {
         debug!("Encoding raw symbol {}", sym);
         try!(self.out.write_u8(sym));
+        encode_flat(sym as u64, 8, eh)
+    }
+
+    fn decode<D: BitDecoder>(&mut self, _c: &SymContext,
+              dh: &mut D) -> io::Result<Symbol>
+    {
+        let sym = try!(decode_flat(8, dh)) as Symbol;
+        try!(self.out.write_u8(sym));
+        Ok(sym)
+    }
+}
+
+impl<W> State for Out<W> {
+    fn save(&self, _writer: &mut io::Write) -> io::Result<()> {
+        Ok(()) //stateless
+    }
+
+    fn load(&mut self, _reader: &mut io::Read) -> io::Result<()> {
         Ok(())
     }
+}
+
+
+/// Reader of the `DcOut` records
+pub struct Reader<R> {
+    input: R,
+}
+
+impl<R: io::Read> Reader<R> {
+    /// Create a new reader of a given dump
+    pub fn new(input: R) -> Reader<R> {
+        Reader {
+            input: input,
+        }
+    }
+
+    /// Read the next record, return `None` at the end of the dump
+    pub fn read(&mut self) -> io::Result<Option<(Distance, dc::Context)>> {
+        let mut buf = [0u8; RECORD_SIZE];
+        let mut num = 0;
+        while num < RECORD_SIZE {
+            match try!(self.input.read(&mut buf[num..])) {
+                0 if num == 0 => return Ok(None),
+                0 => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    "dump ends inside a record")),
+                n => num += n,
+            }
+        }
+        let mut record = &buf[..];
+        let d = try!(record.read_u32::<LittleEndian>());
+        let symbol = try!(record.read_u8());
+        let last_rank = try!(record.read_u8());
+        let limit = try!(record.read_u32::<LittleEndian>());
+        Ok(Some((d, dc::Context {
+            symbol: symbol,
+            last_rank: last_rank,
+            distance_limit: limit as usize,
+        })))
+    }
+}
+
+impl<R: io::Read> Iterator for Reader<R> {
+    type Item = io::Result<(Distance, dc::Context)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
 
-    fn decode<R: io::Read>(&mut self, _c: &SymContext,
-              _dec: &mut ari::Decoder<R>) -> io::Result<Symbol>
+/// Code the distances of a dump with a given model,
+/// returning the number of them and their cost under the model
+pub fn replay<M: DistanceModel, R: io::Read>(model: &mut M, reader: Reader<R>) -> io::Result<Cost> {
+    let mut eh = ari::Encoder::new(io::sink());
+    let mut cost = Cost::new();
     {
-        Ok(0) //not supported
+        let mut meter = Meter::new(&mut eh);
+        for record in reader {
+            let (d, ctx) = try!(record);
+            let before = meter.total();
+            try!(model.encode(d, &ctx, &mut meter));
+            cost.add(meter.total() - before);
+        }
+    }
+    eh.finish().1.map(|_| cost)
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    use block::{Decoder, Encoder};
+    use block::dc;
+    use model::exp;
+
+    const TEXT: &'static [u8] = include_bytes!("../../LICENSE");
+
+    #[test]
+    fn dump_replay() {
+        let mut enc = dc::Encoder::new(TEXT.len(), super::DcOut::new(Vec::new()));
+        let (stream, err) = enc.encode(TEXT, Vec::new());
+        err.unwrap();
+        let dump = enc.model.out;
+        assert_eq!(dump.len() % super::RECORD_SIZE, 0);
+        // the decoder writes the same dump
+        let mut dec = dc::Decoder::new(TEXT.len(), super::DcOut::new(Vec::new()));
+        let (_, output, err) = dec.decode(io::Cursor::new(&stream[..]), Vec::new());
+        err.unwrap();
+        assert_eq!(&output[..], TEXT);
+        assert!(dec.model.out == dump);
+        // replaying gives the cost of coding the block by the model
+        let reader = super::Reader::new(&dump[..]);
+        let cost = super::replay(&mut exp::Model::new(), reader).unwrap();
+        assert_eq!(cost.count, dump.len() / super::RECORD_SIZE);
+        let mut enc = dc::Encoder::new(TEXT.len(), exp::Model::new());
+        let (stream, err) = enc.encode(TEXT, Vec::new());
+        err.unwrap();
+        assert!((cost.bits / 8.0 - stream.len() as f64).abs() < 8.0,
+            "replayed {} bits, coded {} bytes", cost.bits, stream.len());
+        // a cut record is an error
+        let mut reader = super::Reader::new(&dump[.. super::RECORD_SIZE + 3]);
+        assert!(reader.read().unwrap().is_some());
+        assert!(reader.read().is_err());
+    }
+
+    #[test]
+    fn symbols() {
+        use block::raw;
+        let mut enc = raw::Encoder::new(TEXT.len(), super::Out::new(Vec::new()));
+        let (stream, err) = enc.encode(TEXT, Vec::new());
+        err.unwrap();
+        // the origin goes first
+        assert_eq!(enc.model.out.len(), 4 + TEXT.len());
+        let mut dec = raw::Decoder::new(TEXT.len(), super::Out::new(Vec::new()));
+        let (_, output, err) = dec.decode(io::Cursor::new(&stream[..]), Vec::new());
+        err.unwrap();
+        assert_eq!(&output[..], TEXT);
+        assert!(dec.model.out == enc.model.out);
     }
 }
@@ -7,7 +7,9 @@ A simple coding model to be a baseline for comparison
 use std::{cmp, io};
 use compress::bwt::dc::Context;
 use compress::entropy::ari;
-use super::Distance;
+use entropy::{BitDecoder, BitEncoder};
+use super::{Distance, ParameterInfo, Tunable};
+use super::state;
 
 
 /// A pass-though byte frequency model
@@ -23,6 +25,62 @@ impl ari::Model<u8> for Raw {
 }
 
 
+/// Tunable parameters of the model
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameters {
+    /// binary log of the frequency threshold
+    pub threshold_log   : usize,
+    /// update powers of the base and the following bytes
+    pub up              : [usize; 4],
+}
+
+const PARAMETER_INFO: [ParameterInfo; 5] = [
+    ("threshold_log", 9, 14),
+    ("up_base", 1, 16),
+    ("up_byte0", 1, 16),
+    ("up_byte1", 1, 16),
+    ("up_byte2", 1, 16),
+];
+
+impl Parameters {
+    /// Create the default parameters
+    pub fn new() -> Parameters {
+        Parameters {
+            threshold_log   : 12,
+            up              : [10,8,7,6],
+        }
+    }
+}
+
+impl Default for Parameters {
+    fn default() -> Parameters {
+        Parameters::new()
+    }
+}
+
+impl Tunable for Parameters {
+    fn info() -> &'static [ParameterInfo] {
+        &PARAMETER_INFO
+    }
+
+    fn get(&self, index: usize) -> isize {
+        match index {
+            0 => self.threshold_log as isize,
+            1 ... 4 => self.up[index-1] as isize,
+            _ => panic!("Unknown parameter index: {}", index)
+        }
+    }
+
+    fn set(&mut self, index: usize, value: isize) {
+        match index {
+            0 => self.threshold_log = value as usize,
+            1 ... 4 => self.up[index-1] = value as usize,
+            _ => panic!("Unknown parameter index: {}", index)
+        }
+    }
+}
+
+
 /// A simple DC model, coding up to 0xFF distances as-is, and with following 3 bytes otherwise
 pub struct Model {
     freq: Vec<ari::table::Model>,
@@ -30,14 +88,19 @@ pub struct Model {
 }
 
 impl Model {
-    /// Create a new Model
-    pub fn new() -> Model {
-        let threshold = ari::RANGE_DEFAULT_THRESHOLD >> 2;
+    /// Create a new Model with given parameters
+    pub fn new_custom(params: Parameters) -> Model {
+        let threshold = 1 << params.threshold_log;
         Model {
             freq: (0..4).map(|_| ari::table::Model::new_flat(0x100, threshold)).collect(),
-            up  : [10,8,7,6],
+            up  : params.up,
         }
     }
+
+    /// Create a new Model
+    pub fn new() -> Model {
+        Model::new_custom(Parameters::new())
+    }
 }
 
 impl super::Model<Distance, Context> for Model {
@@ -47,30 +110,30 @@ impl super::Model<Distance, Context> for Model {
         }
     }
 
-    fn encode<W: io::Write>(&mut self, dist: Distance, _ctx: &Context,
-              eh: &mut ari::Encoder<W>) -> io::Result<()> {
+    fn encode<E: BitEncoder>(&mut self, dist: Distance, _ctx: &Context,
+              eh: &mut E) -> io::Result<()> {
         let val = cmp::min(0xFF, dist) as usize;
-        try!(eh.encode(val, &self.freq[0]));
+        try!(eh.encode_symbol(val, &self.freq[0]));
         self.freq[0].update(val, self.up[0], 1);
         if val == 0xFF {
             let rest = (dist - 0xFF) as usize;
             for i in 0usize .. 3 {
                 let b = (rest>>(i*8))&0xFF;
-                try!(eh.encode(b, &self.freq[i+1]));
+                try!(eh.encode_symbol(b, &self.freq[i+1]));
                 self.freq[i+1].update(b, self.up[i+1], 1);
             }
         }
         Ok(())
     }
 
-    fn decode<R: io::Read>(&mut self, _ctx: &Context, dh: &mut ari::Decoder<R>)
+    fn decode<D: BitDecoder>(&mut self, _ctx: &Context, dh: &mut D)
               -> io::Result<Distance> {
-        let base = try!(dh.decode(&self.freq[0]));
+        let base = try!(dh.decode_symbol(&self.freq[0]));
         self.freq[0].update(base, self.up[0], 1);
         let d = if base == 0xFF {
             let mut u = base;
             for i in 0 .. 3 {
-                let b = try!(dh.decode(&self.freq[i+1]));
+                let b = try!(dh.decode_symbol(&self.freq[i+1]));
                 self.freq[i+1].update(b, self.up[i+1], 1);
                 u += b << (i * 8);
             }
@@ -79,3 +142,13 @@ impl super::Model<Distance, Context> for Model {
         Ok(d as Distance)
     }
 }
+
+impl super::State for Model {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        state::save_tables(&self.freq, writer)
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        state::load_tables(&mut self.freq, reader)
+    }
+}
@@ -0,0 +1,95 @@
+/*!
+
+Secondary symbol estimation (SSE)
+
+An adaptive probability map, refining the bit predictions of a model.
+The input probability is interpolated between the bins of a gate,
+selected by a context that is defined by the model itself.
+The refined probability is averaged with the input one.
+
+# Links
+
+* http://mattmahoney.net/dc/bbb.cpp
+
+*/
+
+use std::cmp;
+use compress::entropy::ari;
+
+
+const FLAT_TOTAL: u32 = 1<<12;
+
+/// Convert any binary frequency model into a bit probability
+pub fn to_bit<M: ari::Model<bool>>(model: &M) -> ari::apm::Bit {
+    let (_, zero) = model.get_range(false);
+    let total = model.get_denominator();
+    let flat = ((zero as u64) * (FLAT_TOTAL as u64) / (total as u64)) as u32;
+    ari::apm::Bit::from_flat(cmp::max(1, cmp::min(FLAT_TOTAL-1, flat)) as ari::apm::FlatProbability)
+}
+
+
+/// Update information for the SSE stage
+#[derive(Clone, Copy, Debug)]
+pub struct Cookie {
+    context : usize,
+    coords  : ari::apm::BinCoords,
+}
+
+/// SSE stage, holding a gate per context
+pub struct Stage {
+    gates   : Vec<ari::apm::Gate>,
+    rate    : isize,
+}
+
+impl Stage {
+    /// Create a new stage with a given number of contexts and adaptation rate
+    pub fn new(num_contexts: usize, rate: isize) -> Stage {
+        Stage {
+            gates   : (0..num_contexts).map(|_| ari::apm::Gate::new()).collect(),
+            rate    : rate,
+        }
+    }
+
+    /// Reset all the gates to the identity mapping
+    pub fn reset(&mut self) {
+        for gate in self.gates.iter_mut() {
+            *gate = ari::apm::Gate::new();
+        }
+    }
+
+    /// Refine a bit probability under a given context
+    pub fn refine(&self, bit: &ari::apm::Bit, context: usize) -> (ari::apm::Bit, Cookie) {
+        let (refined, coords) = self.gates[context].pass(bit);
+        let flat = (bit.to_flat() as u32 + 3 * refined.to_flat() as u32 + 2) >> 2;
+        let fp = cmp::max(1, cmp::min(FLAT_TOTAL-1, flat)) as ari::apm::FlatProbability;
+        (ari::apm::Bit::from_flat(fp), Cookie {
+            context : context,
+            coords  : coords,
+        })
+    }
+
+    /// Adapt to the actual bit value
+    pub fn update(&mut self, value: bool, cookie: Cookie) {
+        self.gates[cookie.context].update(value, cookie.coords, self.rate, 0);
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use compress::entropy::ari;
+    use super::Stage;
+
+    #[test]
+    fn learns() {
+        let mut stage = Stage::new(2, 5);
+        let input = ari::apm::Bit::new_equal();
+        for _ in 0..100 {
+            let (_, cookie) = stage.refine(&input, 1);
+            stage.update(true, cookie);
+        }
+        let (p0, _) = stage.refine(&input, 0);
+        let (p1, _) = stage.refine(&input, 1);
+        assert!(p1.to_flat() < p0.to_flat());
+    }
+}
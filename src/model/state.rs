@@ -0,0 +1,166 @@
+/*!
+
+Model state persistence helpers
+
+Building blocks for the `State` implementations of the models.
+All the values are written in little endian, without any framing:
+the reader is expected to know the shapes of the tables.
+
+Frequency tables and bit probabilities are restored exactly.
+The counters of `ari::bin::Model` are not exposed by `compress`,
+so they are brought as close to the saved probability as their updates allow.
+
+*/
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+use compress::entropy::ari;
+
+
+fn corrupted(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Write a frequency table
+pub fn save_table(table: &ari::table::Model, writer: &mut io::Write) -> io::Result<()> {
+    for &freq in table.get_frequencies().iter() {
+        try!(writer.write_u16::<LittleEndian>(freq));
+    }
+    Ok(())
+}
+
+/// Read a frequency table of the same size
+pub fn load_table(table: &mut ari::table::Model, reader: &mut io::Read) -> io::Result<()> {
+    let num = table.get_frequencies().len();
+    table.reset_flat();
+    for value in 0 .. num {
+        let freq = try!(reader.read_u16::<LittleEndian>());
+        if freq == 0 {
+            return Err(corrupted("zero frequency in a saved table"))
+        }
+        // a huge `add_log` makes the update add exactly the constant
+        table.update(value, 31, freq as ari::Border - 1);
+    }
+    Ok(())
+}
+
+/// Write a list of frequency tables
+pub fn save_tables(tables: &[ari::table::Model], writer: &mut io::Write) -> io::Result<()> {
+    for table in tables.iter() {
+        try!(save_table(table, writer));
+    }
+    Ok(())
+}
+
+/// Read a list of frequency tables
+pub fn load_tables(tables: &mut [ari::table::Model], reader: &mut io::Read) -> io::Result<()> {
+    for table in tables.iter_mut() {
+        try!(load_table(table, reader));
+    }
+    Ok(())
+}
+
+/// Write a list of bit probabilities
+pub fn save_bits(bits: &[ari::apm::Bit], writer: &mut io::Write) -> io::Result<()> {
+    for bit in bits.iter() {
+        try!(writer.write_u16::<LittleEndian>(bit.to_flat()));
+    }
+    Ok(())
+}
+
+/// Read a list of bit probabilities
+pub fn load_bits(bits: &mut [ari::apm::Bit], reader: &mut io::Read) -> io::Result<()> {
+    for bit in bits.iter_mut() {
+        let flat = try!(reader.read_u16::<LittleEndian>());
+        if flat == 0 || flat as isize >= ari::apm::FLAT_TOTAL {
+            return Err(corrupted("saved bit probability is out of range"))
+        }
+        *bit = ari::apm::Bit::from_flat(flat);
+    }
+    Ok(())
+}
+
+/// Write a list of binary frequency models
+pub fn save_binaries(models: &[ari::bin::Model], writer: &mut io::Write) -> io::Result<()> {
+    for model in models.iter() {
+        try!(writer.write_u32::<LittleEndian>(model.get_probability_zero()));
+    }
+    Ok(())
+}
+
+/// Read a list of binary frequency models, approximately
+pub fn load_binaries(models: &mut [ari::bin::Model], reader: &mut io::Read) -> io::Result<()> {
+    for model in models.iter_mut() {
+        let zero = try!(reader.read_u32::<LittleEndian>());
+        model.reset_flat();
+        loop {
+            let current = model.get_probability_zero();
+            if current > zero {
+                model.update_one();
+            }else if current < zero {
+                model.update_zero();
+            }
+            let next = model.get_probability_zero();
+            // stop when the target is reached, passed, or can't be approached
+            if next == current || (next > zero) != (current > zero) || next == zero {
+                break
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a plain number
+pub fn save_number(value: u32, writer: &mut io::Write) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(value).map_err(From::from)
+}
+
+/// Read a plain number
+pub fn load_number(reader: &mut io::Read) -> io::Result<u32> {
+    reader.read_u32::<LittleEndian>().map_err(From::from)
+}
+
+/// Read a number used as an index, checking it against a limit
+pub fn load_index(reader: &mut io::Read, limit: usize) -> io::Result<usize> {
+    let index = try!(load_number(reader)) as usize;
+    if index < limit {
+        Ok(index)
+    }else {
+        Err(corrupted("saved index is out of range"))
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    use compress::entropy::ari;
+
+    #[test]
+    fn tables() {
+        let mut table = ari::table::Model::new_flat(10, 1<<12);
+        for i in 0 .. 100 {
+            table.update(i*i % 10, 4, 1);
+        }
+        let mut buf = Vec::new();
+        super::save_table(&table, &mut buf).unwrap();
+        let mut copy = ari::table::Model::new_flat(10, 1<<12);
+        super::load_table(&mut copy, &mut io::Cursor::new(&buf[..])).unwrap();
+        assert_eq!(table.get_frequencies(), copy.get_frequencies());
+    }
+
+    #[test]
+    fn binaries() {
+        let mut model = ari::bin::Model::new_flat(1<<12, 4);
+        for i in 0 .. 100 {
+            model.update(i % 7 == 0);
+        }
+        let mut buf = Vec::new();
+        super::save_binaries(&[model], &mut buf).unwrap();
+        let mut copy = [ari::bin::Model::new_flat(1<<12, 4)];
+        super::load_binaries(&mut copy, &mut io::Cursor::new(&buf[..])).unwrap();
+        let target = super::load_number(&mut io::Cursor::new(&buf[..])).unwrap() as i32;
+        let diff = copy[0].get_probability_zero() as i32 - target;
+        assert!(diff.abs() < (1<<12) >> 4, "restored {} is too far from {}", diff, target);
+    }
+}
@@ -2,7 +2,12 @@
 
 YBS-like compression model
 
-Work in progress, based on the notes from Vadim.
+Based on the notes from Vadim on the YBS implementation.
+Distances are decomposed into an exponent, coded in unary, and a mantissa.
+Every binary decision is predicted by adaptive counters under several contexts:
+the running average log of the symbol, the last log of the symbol,
+and the last log coded globally. Counters adapt quickly while young
+and slow down with age. The combined prediction is refined by an SSE stage.
 
 # Links
 
@@ -17,150 +22,382 @@ Vadim Yoockin for sharing details of YBS implementation.
 use std::{cmp, io};
 use compress::bwt::dc::Context;
 use compress::entropy::ari;
-use super::Distance;
+use entropy::{BitDecoder, BitEncoder, Part};
+use super::{Distance, ParameterInfo, Tunable};
+use super::mix::Mixer;
+use super::{state, State};
+use super::sse;
+
+
+const MAX_LOG       : usize = 32;
+const AVG_LOGS      : usize = 32;
+const LAST_LOGS     : usize = 12;
+const AVG_FIXED     : usize = 4;
+const TREE_BITS     : usize = 3;
+const TREE_NODES    : usize = 1<<TREE_BITS;
+const FLAT_TOTAL    : u32 = 1<<12;
+
+fn to_bit(flat: u32) -> ari::apm::Bit {
+    ari::apm::Bit::from_flat(cmp::max(1, cmp::min(FLAT_TOTAL-1, flat)) as ari::apm::FlatProbability)
+}
+
+fn get_log(value: Distance) -> usize {
+    let mut log = 0;
+    while value>>log != 0 {log += 1;}
+    log
+}
+
+
+/// Adaptive probability counter, its rate slows down with age
+#[derive(Clone, Copy)]
+struct Counter {
+    /// probability of a zero bit in 16 bits
+    prob    : u32,
+    age     : u32,
+}
+
+impl Counter {
+    fn new() -> Counter {
+        Counter { prob: 1<<15, age: 0 }
+    }
+
+    fn to_flat(&self) -> u32 {
+        self.prob >> 4
+    }
+
+    fn to_bit(&self) -> ari::apm::Bit {
+        to_bit(self.to_flat())
+    }
+
+    fn update(&mut self, bit: bool, limit: u32) {
+        let target = if bit {0} else {1<<16};
+        let rate = self.age + 2;
+        if target > self.prob {
+            self.prob += (target - self.prob) / rate;
+        }else {
+            self.prob -= (self.prob - target) / rate;
+        }
+        if self.age < limit {
+            self.age += 1;
+        }
+    }
+}
+
+fn save_counters(counters: &[Counter], writer: &mut io::Write) -> io::Result<()> {
+    for c in counters.iter() {
+        try!(state::save_number(c.prob, writer));
+        try!(state::save_number(c.age, writer));
+    }
+    Ok(())
+}
+
+fn load_counters(counters: &mut [Counter], reader: &mut io::Read) -> io::Result<()> {
+    for c in counters.iter_mut() {
+        c.prob = try!(state::load_index(reader, (1<<16) + 1)) as u32;
+        c.age = try!(state::load_number(reader));
+    }
+    Ok(())
+}
 
 
 struct SymbolContext {
+    /// fixed-point running average of the log
     pub avg_log     : usize,
-    pub last_diff   : usize,
+    pub last_log    : usize,
 }
 
 impl SymbolContext {
     fn new() -> SymbolContext {
-        SymbolContext{ avg_log:0, last_diff:0 }
+        SymbolContext{ avg_log: 0, last_log: 0 }
+    }
+
+    fn update(&mut self, log: usize, params: &Parameters) {
+        let value = log << AVG_FIXED;
+        // follow big jumps faster than the noise
+        let diff = (value as isize - self.avg_log as isize).abs() as usize;
+        let shift = if diff >= params.avg_jump<<AVG_FIXED {params.avg_rate_fast} else {params.avg_rate_slow};
+        self.avg_log = (value + (self.avg_log << shift) - self.avg_log) >> shift;
+        self.last_log = log;
+    }
+}
+
+
+/// Tunable parameters of the model
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameters {
+    /// binary log of the age limit of the counters
+    pub counter_limit_log   : usize,
+    /// update rate of the SSE stage
+    pub sse_rate            : isize,
+    /// log difference considered a jump of the symbol average
+    pub avg_jump            : usize,
+    /// update rate of the symbol average on jumps
+    pub avg_rate_fast       : usize,
+    /// update rate of the symbol average otherwise
+    pub avg_rate_slow       : usize,
+}
+
+const PARAMETER_INFO: [ParameterInfo; 5] = [
+    ("counter_limit_log", 2, 16),
+    ("sse_rate", 1, 10),
+    ("avg_jump", 1, 8),
+    ("avg_rate_fast", 1, 6),
+    ("avg_rate_slow", 1, 6),
+];
+
+impl Parameters {
+    /// Create the default parameters
+    pub fn new() -> Parameters {
+        Parameters {
+            counter_limit_log   : 10,
+            sse_rate            : 7,
+            avg_jump            : 3,
+            avg_rate_fast       : 2,
+            avg_rate_slow       : 3,
+        }
+    }
+}
+
+impl Default for Parameters {
+    fn default() -> Parameters {
+        Parameters::new()
+    }
+}
+
+impl Tunable for Parameters {
+    fn info() -> &'static [ParameterInfo] {
+        &PARAMETER_INFO
+    }
+
+    fn get(&self, index: usize) -> isize {
+        match index {
+            0 => self.counter_limit_log as isize,
+            1 => self.sse_rate,
+            2 => self.avg_jump as isize,
+            3 => self.avg_rate_fast as isize,
+            4 => self.avg_rate_slow as isize,
+            _ => panic!("Unknown parameter index: {}", index)
+        }
     }
 
-    fn update(&mut self, log: usize) {
-        let a = if self.last_diff>3 {2usize} else {1usize};
-        let b = 1usize;
-        self.last_diff = ((log as isize) - (self.avg_log as isize)).abs() as usize;
-        self.avg_log = (a*log + b*self.avg_log) / (a+b);
+    fn set(&mut self, index: usize, value: isize) {
+        match index {
+            0 => self.counter_limit_log = value as usize,
+            1 => self.sse_rate = value,
+            2 => self.avg_jump = value as usize,
+            3 => self.avg_rate_fast = value as usize,
+            4 => self.avg_rate_slow = value as usize,
+            _ => panic!("Unknown parameter index: {}", index)
+        }
     }
 }
 
 
 /// Coding model for BWT-DC output
 pub struct Model {
-    table_log   : Vec<ari::table::Model>,
-    table_high  : ari::table::Model,
-    bin_rest    : Vec<ari::bin::Model>,
+    /// exponent by [avg_log in half-logs][step]
+    exp_avg     : Vec<Counter>,
+    /// exponent by [last_log][step]
+    exp_last    : Vec<Counter>,
+    /// exponent by [global last_log][step]
+    exp_global  : Vec<Counter>,
+    /// exponent mixing by [step]
+    exp_mixer   : Mixer,
+    inputs      : [ari::apm::Bit; 3],
+    stretched   : [i32; 3],
+    /// exponent refinement by [avg_log][step]
+    exp_sse     : sse::Stage,
+    /// mantissa head by [log][tree node]
+    mant_tree   : Vec<Counter>,
+    /// mantissa tail by [log][bit]
+    mant_rest   : Vec<Counter>,
     /// specific context tracking
     contexts    : Vec<SymbolContext>,
+    last_log    : usize,
+    counter_limit   : u32,
+    params      : Parameters,
 }
 
 impl Model {
-    /// Create a new Model instance
-    pub fn new_custom(threshold: ari::Border) -> Model {
-        let low_groups = 13usize;
+    /// Create a new Model instance with given parameters
+    pub fn new_custom(params: Parameters) -> Model {
         Model {
-            table_log   : (0..low_groups).map(|_|
-                ari::table::Model::new_flat(low_groups+1, threshold)
-                ).collect(),
-            table_high  : ari::table::Model::new_flat(32-low_groups, threshold),
-            bin_rest    : (0..3).map(|_|
-                ari::bin::Model::new_flat(threshold, 5)
-                ).collect(),
+            exp_avg     : vec![Counter::new(); AVG_LOGS * MAX_LOG],
+            exp_last    : vec![Counter::new(); LAST_LOGS * MAX_LOG],
+            exp_global  : vec![Counter::new(); LAST_LOGS * MAX_LOG],
+            exp_mixer   : Mixer::new(3, MAX_LOG),
+            inputs      : [ari::apm::Bit::new_equal(); 3],
+            stretched   : [0; 3],
+            exp_sse     : sse::Stage::new(AVG_LOGS * MAX_LOG, params.sse_rate),
+            mant_tree   : vec![Counter::new(); (MAX_LOG+1) * TREE_NODES],
+            mant_rest   : vec![Counter::new(); (MAX_LOG+1) * MAX_LOG],
             contexts    : (0..0x100).map(|_| SymbolContext::new()).collect(),
+            last_log    : 0,
+            counter_limit   : 1 << params.counter_limit_log,
+            params      : params,
         }
     }
 
-    /// Create a new default Model
+    /// Create a new default Model instance
     pub fn new() -> Model {
-        Model::new_custom(ari::RANGE_DEFAULT_THRESHOLD >> 2)
+        Model::new_custom(Parameters::new())
+    }
+
+    fn exp_contexts(&self, step: usize, ctx: &Context) -> [usize; 3] {
+        let sc = &self.contexts[ctx.symbol as usize];
+        let avg = cmp::min(sc.avg_log >> (AVG_FIXED-1), AVG_LOGS-1);
+        let last = cmp::min(sc.last_log, LAST_LOGS-1);
+        let global = cmp::min(self.last_log, LAST_LOGS-1);
+        [avg * MAX_LOG + step, last * MAX_LOG + step, global * MAX_LOG + step]
+    }
+
+    fn predict_exponent(&mut self, step: usize, ctx: &Context)
+                        -> ([usize; 3], ari::apm::Bit, ari::apm::Bit, sse::Cookie) {
+        let coords = self.exp_contexts(step, ctx);
+        self.inputs = [
+            self.exp_avg[coords[0]].to_bit(),
+            self.exp_last[coords[1]].to_bit(),
+            self.exp_global[coords[2]].to_bit(),
+            ];
+        self.exp_mixer.prepare(&self.inputs, &mut self.stretched);
+        let mixed = self.exp_mixer.mix(step, &self.stretched);
+        let (refined, cookie) = self.exp_sse.refine(&mixed, coords[0]);
+        (coords, refined, mixed, cookie)
+    }
+
+    fn update_exponent(&mut self, step: usize, bit: bool, coords: [usize; 3],
+                       mixed: ari::apm::Bit, cookie: sse::Cookie) {
+        self.exp_sse.update(bit, cookie);
+        self.exp_mixer.update(step, &self.stretched, mixed, bit);
+        let limit = self.counter_limit;
+        self.exp_avg[coords[0]].update(bit, limit);
+        self.exp_last[coords[1]].update(bit, limit);
+        self.exp_global[coords[2]].update(bit, limit);
+    }
+
+    fn mantissa_counter(&mut self, log: usize, i: usize, prefix: usize) -> &mut Counter {
+        if i < TREE_BITS {
+            &mut self.mant_tree[log * TREE_NODES + (1<<i) + prefix]
+        }else {
+            &mut self.mant_rest[log * MAX_LOG + i]
+        }
+    }
+
+    fn update_contexts(&mut self, log: usize, ctx: &Context) {
+        self.contexts[ctx.symbol as usize].update(log, &self.params);
+        self.last_log = log;
     }
 }
 
 impl super::Model<Distance, Context> for Model {
     fn reset(&mut self) {
-        for table in self.table_log.iter_mut() {
-            table.reset_flat();
+        for c in self.exp_avg.iter_mut().chain(self.exp_last.iter_mut()).chain(self.exp_global.iter_mut()) {
+            *c = Counter::new();
         }
-        self.table_high.reset_flat();
-        for bm in self.bin_rest.iter_mut() {
-            bm.reset_flat();
+        self.exp_mixer.reset();
+        for c in self.mant_tree.iter_mut().chain(self.mant_rest.iter_mut()) {
+            *c = Counter::new();
         }
+        self.exp_sse.reset();
         for con in self.contexts.iter_mut() {
-            con.avg_log = 0;
-            con.last_diff = 0;
-        }
-    }
-
-    fn encode<W: io::Write>(&mut self, dist: Distance, ctx: &Context,
-              eh: &mut ari::Encoder<W>) -> io::Result<()> {
-        let max_low_log = self.table_log.len()-1;
-        let group = if dist<4 {
-            dist as usize
-        } else {
-            let mut log = 3;
-            while dist>>log !=0 {log+=1;}
-            (log+1) as usize
-        };
-        let context = &mut self.contexts[ctx.symbol as usize];
-        let con_log = cmp::min(context.avg_log, max_low_log);
-        let freq_log = &mut self.table_log[con_log];
-        // write exponent
-        let log_encoded = cmp::min(group, max_low_log);
-        try!(eh.encode(log_encoded, freq_log));
-        // update model
-        freq_log.update(log_encoded, 10, 1);
-        context.update(log_encoded);    //use log?
-        if group<4 {
-            return Ok(())
-        }
-        if group >= max_low_log {
-            let add = group - max_low_log;
-            try!(eh.encode(add, &self.table_high));
-            self.table_high.update(add, 10, 1);
+            *con = SymbolContext::new();
+        }
+        self.last_log = 0;
+    }
+
+    fn encode<E: BitEncoder>(&mut self, dist: Distance, ctx: &Context,
+              eh: &mut E) -> io::Result<()> {
+        let value = dist + 1;
+        let log = get_log(value);
+        debug!("YBS encoding distance {} with log {}", dist, log);
+        // write exponent in unary
+        eh.set_part(Part::Exponent);
+        for step in 1 .. MAX_LOG {
+            let bit = step < log;
+            let (coords, prob, mixed, cookie) = self.predict_exponent(step, ctx);
+            try!(eh.encode_bit(bit, &prob));
+            self.update_exponent(step, bit, coords, mixed, cookie);
+            if !bit {
+                break
+            }
         }
         // write mantissa
-        let log = group-1;
-        for i in 1 .. log {
-            let bit = (dist>>(log-i-1)) as usize & 1 != 0;
-            if i >= self.bin_rest.len() {
-                // just send bits past the model, equally distributed
-                try!(eh.encode(bit, self.bin_rest.last().unwrap()));
-            }else {
-                let bc = &mut self.bin_rest[i-1];
-                try!(eh.encode(bit, bc));
-                bc.update(bit);
-            };
+        eh.set_part(Part::Mantissa);
+        let mut prefix = 0;
+        let limit = self.counter_limit;
+        for i in 0 .. log-1 {
+            let bit = (value >> (log-i-2)) & 1 != 0;
+            let counter = self.mantissa_counter(log, i, prefix);
+            let prob = counter.to_bit();
+            try!(eh.encode_bit(bit, &prob));
+            counter.update(bit, limit);
+            prefix = (prefix<<1) + (bit as usize);
         }
+        self.update_contexts(log, ctx);
         Ok(())
     }
 
-    fn decode<R: io::Read>(&mut self, ctx: &Context, dh: &mut ari::Decoder<R>)
+    fn decode<D: BitDecoder>(&mut self, ctx: &Context, dh: &mut D)
               -> io::Result<Distance> {
-        let max_low_log = self.table_log.len()-1;
-        let context = &mut self.contexts[ctx.symbol as usize];
-        let con_log = cmp::min(context.avg_log, max_low_log);
-        let freq_log = &mut self.table_log[con_log];
         // read exponent
-        let log_decoded = try!(dh.decode(freq_log));
-        // update model
-        context.update(log_decoded);
-        freq_log.update(log_decoded, 10, 1);
-        if log_decoded < 4 {
-            return Ok(log_decoded as Distance)
-        }
-        let group = if log_decoded == max_low_log {
-            let add = try!(dh.decode(&self.table_high));
-            self.table_high.update(add, 10, 1);
-            max_low_log + add
-        }else {log_decoded};
+        let mut log = 1;
+        while log < MAX_LOG {
+            let (coords, prob, mixed, cookie) = self.predict_exponent(log, ctx);
+            let bit = try!(dh.decode_bit(&prob));
+            self.update_exponent(log, bit, coords, mixed, cookie);
+            if !bit {
+                break
+            }
+            log += 1;
+        }
         // read mantissa
-        let log = group - 1;
-        let mut dist = 1 as super::Distance;
-        for i in 1 .. log {
-            let bit = if i >= self.bin_rest.len() {
-                try!(dh.decode( self.bin_rest.last().unwrap() ))
-            }else {
-                let bc = &mut self.bin_rest[i-1];
-                let bit = try!(dh.decode(bc));
-                bc.update(bit);
-                bit
-            };
-            dist = (dist<<1) + (bit as Distance);
+        let mut value = 1 as Distance;
+        let mut prefix = 0;
+        let limit = self.counter_limit;
+        for i in 0 .. log-1 {
+            let counter = self.mantissa_counter(log, i, prefix);
+            let prob = counter.to_bit();
+            let bit = try!(dh.decode_bit(&prob));
+            counter.update(bit, limit);
+            prefix = (prefix<<1) + (bit as usize);
+            value = (value<<1) + (bit as Distance);
         }
+        let dist = value - 1;
+        debug!("YBS decoded distance {} with log {}", dist, log);
+        self.update_contexts(log, ctx);
         Ok(dist)
     }
 }
+
+impl State for Model {
+    fn save(&self, writer: &mut io::Write) -> io::Result<()> {
+        try!(save_counters(&self.exp_avg, writer));
+        try!(save_counters(&self.exp_last, writer));
+        try!(save_counters(&self.exp_global, writer));
+        try!(self.exp_mixer.save(writer));
+        try!(save_counters(&self.mant_tree, writer));
+        try!(save_counters(&self.mant_rest, writer));
+        for con in self.contexts.iter() {
+            try!(state::save_number(con.avg_log as u32, writer));
+            try!(state::save_number(con.last_log as u32, writer));
+        }
+        state::save_number(self.last_log as u32, writer)
+    }
+
+    fn load(&mut self, reader: &mut io::Read) -> io::Result<()> {
+        try!(load_counters(&mut self.exp_avg, reader));
+        try!(load_counters(&mut self.exp_last, reader));
+        try!(load_counters(&mut self.exp_global, reader));
+        try!(self.exp_mixer.load(reader));
+        try!(load_counters(&mut self.mant_tree, reader));
+        try!(load_counters(&mut self.mant_rest, reader));
+        for con in self.contexts.iter_mut() {
+            con.avg_log = try!(state::load_index(reader, (MAX_LOG+1) << AVG_FIXED));
+            con.last_log = try!(state::load_index(reader, MAX_LOG+1));
+        }
+        self.last_log = try!(state::load_index(reader, MAX_LOG+1));
+        // the SSE gates can't be saved
+        self.exp_sse.reset();
+        Ok(())
+    }
+}
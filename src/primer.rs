@@ -0,0 +1,131 @@
+/*!
+
+Trained model statistics for priming
+
+A primer file holds the statistics of a model, learned on sample inputs,
+so that many small similar files can be coded starting from them instead of
+the initial state. The file consists of a signature, the model identifier,
+and the state written by `model::State::save`.
+
+The checksum of the primer is recorded in the header of every stream coded
+with it, so that decoding with a different primer is detected.
+
+*/
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::io;
+
+use block::{Encoder, Reusable};
+use tune::SizeCounter;
+
+
+/// Signature of the primer files
+pub const SIGNATURE: &'static [u8] = b"DARKPRIM";
+
+/// Adler-32 checksum of a byte sequence
+pub fn checksum(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Learned statistics of a model
+#[derive(Clone, Debug, PartialEq)]
+pub struct Primer {
+    /// Compression model identifier
+    pub model   : u8,
+    /// Model state
+    pub state   : Vec<u8>,
+}
+
+impl Primer {
+    /// Read a primer file
+    pub fn read<R: io::Read>(reader: &mut R) -> io::Result<Primer> {
+        let mut sign = [0u8; 8];
+        for b in sign.iter_mut() {
+            *b = try!(reader.read_u8());
+        }
+        if &sign[..] != SIGNATURE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                "invalid primer signature"))
+        }
+        let model = try!(reader.read_u8());
+        let mut state = Vec::new();
+        try!(reader.read_to_end(&mut state));
+        Ok(Primer {
+            model   : model,
+            state   : state,
+        })
+    }
+
+    /// Write a primer file
+    pub fn write<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        try!(writer.write_all(SIGNATURE));
+        try!(writer.write_u8(self.model));
+        writer.write_all(&self.state)
+    }
+
+    /// Checksum identifying the primer in the stream headers
+    pub fn checksum(&self) -> u32 {
+        let mut data = vec![self.model];
+        data.extend(self.state.iter().cloned());
+        checksum(&data)
+    }
+}
+
+/// Train the model of a block coder on a set of inputs, one block each,
+/// and return the resulting state
+pub fn learn<E: Encoder + Reusable>(mut coder: E, inputs: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+    for input in inputs.iter() {
+        coder.resize(input.len());
+        let (counter, err) = coder.encode(input, SizeCounter(0));
+        try!(err);
+        info!("Learned on N: {}, coded size: {}", input.len(), counter.0);
+    }
+    let mut state = Vec::new();
+    try!(coder.state().save(&mut state));
+    Ok(state)
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+    use block::{dc, multi};
+    use model::exp;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(super::checksum(b""), 1);
+        assert_eq!(super::checksum(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn learn() {
+        let samples: Vec<Vec<u8>> = (0 .. 10).map(|i|
+            format!("{{\"id\": {}, \"name\": \"record {}\", \"tags\": [\"a\", \"b\"]}}", i, i*7).into_bytes()
+            ).collect();
+        let state = super::learn(dc::Encoder::new(1, exp::Model::new()), &samples).unwrap();
+        let primer = super::Primer { model: 0, state: state };
+        let mut buf = Vec::new();
+        primer.write(&mut buf).unwrap();
+        let primer2 = super::Primer::read(&mut io::Cursor::new(&buf[..])).unwrap();
+        assert_eq!(primer, primer2);
+        // a similar record is coded better by the primed model
+        let input = b"{\"id\": 77, \"name\": \"record 539\", \"tags\": [\"a\", \"b\"]}";
+        let coded_size = |primed: bool| {
+            use block::Reusable;
+            let mut encoder = dc::Encoder::new(input.len(), exp::Model::new());
+            if primed {
+                encoder.state().load(&mut &primer.state[..]).unwrap();
+            }
+            let (buf, err) = multi::encode(&mut encoder, input, input.len(), false, Vec::new());
+            err.unwrap();
+            buf.len()
+        };
+        assert!(coded_size(true) < coded_size(false));
+    }
+}
@@ -367,7 +367,12 @@ impl Constructor {
     /// Compute the suffix array for a given input
     pub fn compute<'a>(&'a mut self, input: &[Symbol]) -> &'a [Suffix] {
         assert_eq!(input.len(), self.n);
-        if true {
+        if self.n < 2 {
+            // nothing to sort, and too small for the reduced problem
+            for (i, suf) in self.suffixes[.. self.n].iter_mut().enumerate() {
+                *suf = i as Suffix;
+            }
+        }else if true {
             saca(input, 0x100, &mut self.suffixes[..]);
         }else {
             sort_direct(input, &mut self.suffixes[..]);
@@ -410,6 +415,7 @@ pub mod test {
     fn detailed() {
         some_detail(b"abracadabra", &[10,7,0,3,5,8,1,4,6,9,2], 2, b"rdarcaaaabb");
         some_detail(b"banana", &[5,3,1,0,4,2], 3, b"nnbaaa");
+        some_detail(b"a", &[0], 0, b"a");
     }
 
     fn some_roundtrip(input: &[super::Symbol]) {
@@ -0,0 +1,387 @@
+/*!
+
+Model parameter tuning
+
+The search is a coordinate descent: every parameter in turn is moved
+by one step in each direction, for as long as the total compressed size
+goes down. Passes are repeated until none of the parameters can be improved.
+The result is a local optimum near the initial settings.
+
+The BWT-DC output of each block is computed once, only the distance coding
+is repeated for every set of parameters tried. When optimizing the parameters
+of a stream, the size is measured the way the stream is written: split into
+the blocks of the header, with its entropy coder, and the model starting
+from the primer.
+
+*/
+
+use std::io;
+use compress::bwt;
+use compress::entropy::ari;
+
+use block;
+use entropy::{self, carry, rans, table, BitEncoder, Coder};
+use header::Header;
+use model::{self, Distance, DistanceModel, State, Tunable};
+use primer::Primer;
+use saca;
+
+
+/// BWT-DC output of a block: distances with their contexts, in the coding order
+pub type Stream = Vec<(Distance, bwt::dc::Context)>;
+
+/// A writer that only counts the bytes
+pub struct SizeCounter(pub usize);
+
+impl io::Write for SizeCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compute the BWT-DC output of a block
+pub fn collect(input: &[u8]) -> Stream {
+    let mut sac = saca::Constructor::new(input.len());
+    let mut mtf = bwt::mtf::MTF::new();
+    let mut stream = Vec::new();
+    block::dc::transform(input, &mut sac, &mut mtf, |d, ctx| {
+        let ctx_copy = bwt::dc::Context::new(ctx.symbol, ctx.last_rank, ctx.distance_limit);
+        stream.push((d, ctx_copy));
+    });
+    stream
+}
+
+/// Compute the BWT-DC output of every block of an input,
+/// split by a given block size, or as a single block if it is zero
+pub fn collect_blocks(input: &[u8], block_size: usize) -> Vec<Stream> {
+    if block_size == 0 {
+        vec![collect(input)]
+    }else {
+        input.chunks(block_size).map(collect).collect()
+    }
+}
+
+/// Compute the compressed size of a BWT-DC stream with a given model
+pub fn measure<M: DistanceModel>(stream: &[(Distance, bwt::dc::Context)], mut model: M) -> usize {
+    model.reset();
+    let mut eh = ari::Encoder::new(SizeCounter(0));
+    for &(d, ref ctx) in stream.iter() {
+        model.encode(d, ctx, &mut eh).unwrap();
+    }
+    let (counter, err) = eh.finish();
+    err.unwrap();
+    counter.0
+}
+
+fn encode_stream<M: DistanceModel, E: BitEncoder>(stream: &[(Distance, bwt::dc::Context)],
+                 model: &mut M, eh: &mut E) {
+    for &(d, ref ctx) in stream.iter() {
+        model.encode(d, ctx, eh).unwrap();
+    }
+}
+
+/// Compute the compressed size of a BWT-DC stream with a given model
+/// and entropy coder backend, continuing from the current model state
+pub fn measure_coded<M: DistanceModel>(stream: &[(Distance, bwt::dc::Context)], model: &mut M,
+                     coder: Coder) -> usize {
+    let (counter, err) = match coder {
+        Coder::Range => {
+            let mut eh = ari::Encoder::new(SizeCounter(0));
+            encode_stream(stream, model, &mut eh);
+            eh.finish()
+        },
+        Coder::Rans => {
+            let mut eh = rans::Encoder::new(SizeCounter(0));
+            encode_stream(stream, model, &mut eh);
+            eh.finish()
+        },
+        Coder::Binary => {
+            let mut eh = entropy::Encoder::new(SizeCounter(0));
+            encode_stream(stream, model, &mut eh);
+            eh.finish()
+        },
+        Coder::Carry => {
+            let mut eh = carry::Encoder::new(SizeCounter(0));
+            encode_stream(stream, model, &mut eh);
+            eh.finish()
+        },
+        Coder::Table => {
+            let mut eh = table::Encoder::new(SizeCounter(0));
+            encode_stream(stream, model, &mut eh);
+            eh.finish()
+        },
+    };
+    err.unwrap();
+    counter.0
+}
+
+/// Compute the size of the blocks coded as described by a header:
+/// with its entropy coder, the model starting from a primer,
+/// and its statistics restored for every block unless they are continued
+pub fn measure_blocks<M: DistanceModel + State>(streams: &[Stream], mut model: M,
+                      header: &Header, primer: Option<&Primer>) -> usize {
+    model.reset();
+    if let Some(p) = primer {
+        model.load(&mut &p.state[..]).unwrap();
+    }
+    let mut initial = Vec::new();
+    if !header.continued {
+        model.save(&mut initial).unwrap();
+    }
+    let mut size = 0;
+    for (i, stream) in streams.iter().enumerate() {
+        if i > 0 && !header.continued {
+            model.load(&mut &initial[..]).unwrap();
+        }
+        size += measure_coded(stream, &mut model, header.coder);
+        if header.block_size != 0 {
+            size += 4; // block length
+        }
+    }
+    size
+}
+
+/// Search for the parameters minimizing a given cost function,
+/// return them together with the cost
+pub fn descend<P: Tunable, F: FnMut(&P) -> usize>(initial: P, mut cost: F) -> (P, usize) {
+    let mut best_cost = cost(&initial);
+    let mut best = initial;
+    info!("Initial cost: {}", best_cost);
+    loop {
+        let mut improved = false;
+        for (index, &(name, min, max)) in P::info().iter().enumerate() {
+            for &step in [-1, 1].iter() {
+                let mut moved = false;
+                loop {
+                    let value = best.get(index) + step;
+                    if value < min || value > max {
+                        break
+                    }
+                    let mut params = best.clone();
+                    params.set(index, value);
+                    let c = cost(&params);
+                    debug!("\t{} = {} costs {}", name, value, c);
+                    if c >= best_cost {
+                        break
+                    }
+                    info!("Improved {} to {} with cost {}", name, value, c);
+                    best = params;
+                    best_cost = c;
+                    moved = true;
+                }
+                if moved {
+                    improved = true;
+                    break
+                }
+            }
+        }
+        if !improved {
+            break
+        }
+    }
+    (best, best_cost)
+}
+
+/// Search for the parameters of a model, minimizing the total size of given streams
+pub fn search<P, M, F>(streams: &[Stream], initial: P, make: F) -> (P, usize) where
+    P: Tunable,
+    M: DistanceModel,
+    F: Fn(P) -> M,
+{
+    descend(initial, |params| streams.iter().fold(0, |sum, stream|
+        sum + measure(stream, make(params.clone()))
+        ))
+}
+
+fn optimize_blocks<P, M, F>(streams: &[Stream], header: &Header, primer: Option<&Primer>,
+                   make: F) -> Vec<isize> where
+    P: Tunable,
+    M: DistanceModel + State,
+    F: Fn(P) -> M,
+{
+    let base_size = measure_blocks(streams, make(P::default()), header, primer);
+    let (best, best_size) = descend(P::default(), |params|
+        measure_blocks(streams, make(params.clone()), header, primer)
+        );
+    let values = best.values();
+    // each value takes a byte in the header, plus one for the count
+    if best_size + values.len() + 1 < base_size {
+        info!("Optimized size: {} -> {}", base_size, best_size);
+        values
+    }else {
+        info!("Default parameters are good enough");
+        Vec::new()
+    }
+}
+
+/// Find the best parameters of a given model for an input coded as described
+/// by a header, starting from a given primer, return their values,
+/// or nothing if the defaults are better
+pub fn optimize(model: &str, input: &[u8], header: &Header, primer: Option<&Primer>) -> Vec<isize> {
+    let streams = match model {
+        "dark" | "exp" | "simple" | "ybs" => collect_blocks(input, header.block_size),
+        _ => Vec::new(),
+    };
+    match model {
+        "dark"  => optimize_blocks(&streams, header, primer, model::dark::Model::new_custom),
+        "exp"   => optimize_blocks(&streams, header, primer, model::exp::Model::new_custom),
+        "simple"=> optimize_blocks(&streams, header, primer, model::simple::Model::new_custom),
+        "ybs"   => optimize_blocks(&streams, header, primer, model::ybs::Model::new_custom),
+        _       => {
+            info!("Model {} has no tunable parameters", model);
+            Vec::new()
+        },
+    }
+}
+
+/// Construct the parameters from stored values, using the defaults if there are none
+pub fn load<P: Tunable>(values: &[isize]) -> io::Result<P> {
+    let mut params = P::default();
+    if values.is_empty() {
+        return Ok(params)
+    }
+    if values.len() != P::info().len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            "wrong number of model parameters"))
+    }
+    for (index, (&value, &(name, min, max))) in values.iter().zip(P::info().iter()).enumerate() {
+        if value < min || value > max {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("model parameter {} is out of range: {}", name, value)))
+        }
+        params.set(index, value);
+    }
+    Ok(params)
+}
+
+#[cfg(feature="tune")]
+fn tune<P, M, F>(streams: &[Stream], initial: P, make: F) -> io::Result<()> where
+    P: Tunable,
+    M: DistanceModel,
+    F: Fn(P) -> M,
+{
+    let initial_size = streams.iter().fold(0, |sum, stream|
+        sum + measure(stream, make(initial.clone()))
+        );
+    let (best, best_size) = search(streams, initial, make);
+    println!("Compressed size: {} -> {}", initial_size, best_size);
+    for (index, &(name, _, _)) in P::info().iter().enumerate() {
+        println!("{} = {}", name, best.get(index));
+    }
+    Ok(())
+}
+
+/// Tune the parameters of a given model on a set of files,
+/// printing the best settings found
+#[cfg(feature="tune")]
+pub fn run(model: &str, order: ::alphabet::Order, paths: &[String]) -> io::Result<()> {
+    use std::fs::File;
+    use std::io::Read;
+    let mut streams = Vec::with_capacity(paths.len());
+    for path in paths.iter() {
+        let mut input = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut input));
+        ::alphabet::Permutation::new(order).encode(&mut input);
+        streams.push(collect(&input));
+    }
+    match model {
+        "dark"  => tune(&streams, model::dark::Parameters::new(), model::dark::Model::new_custom),
+        "exp"   => tune(&streams, model::exp::Parameters::new(), model::exp::Model::new_custom),
+        "simple"=> tune(&streams, model::simple::Parameters::new(), model::simple::Model::new_custom),
+        "ybs"   => tune(&streams, model::ybs::Parameters::new(), model::ybs::Model::new_custom),
+        _       => Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("model {} has no tunable parameters", model))),
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use alphabet::Order;
+    use entropy::Coder;
+    use header::{self, Header};
+    use model::Tunable;
+    use model::exp::Parameters;
+
+    #[test]
+    fn descend() {
+        let target = [3, 8, 5];
+        let cost = |p: &Parameters| (0..3).fold(0, |sum, i|
+            sum + (p.get(i) - target[i]).abs() as usize
+            );
+        let (best, best_cost) = super::descend(Parameters::new(), cost);
+        assert_eq!(best_cost, 0);
+        assert_eq!((best.bit_update, best.sse_rate, best.avg_rate), (3, 8, 5));
+    }
+
+    #[test]
+    fn measure() {
+        use block::Encoder;
+        use block::dc;
+        use model::exp::Model;
+        let input = b"abracadabra, abracadabra";
+        let (output, err) = dc::Encoder::new(input.len(), Model::new()).encode(input, Vec::new());
+        err.unwrap();
+        let stream = super::collect(input);
+        assert_eq!(super::measure(&stream, Model::new()), output.len());
+    }
+
+    fn header(size: usize, block_size: usize, continued: bool, coder: Coder) -> Header {
+        Header {
+            size        : size,
+            order       : Order::Natural,
+            model       : header::model_id("simple").unwrap(),
+            parameters  : Vec::new(),
+            block_size  : block_size,
+            continued   : continued,
+            primer      : None,
+            dictionary  : None,
+            coder       : coder,
+        }
+    }
+
+    #[test]
+    fn measure_blocks() {
+        use block::{dc, multi, Reusable};
+        use model::simple::Model;
+        let input: Vec<u8> = (0 .. 5000u32).map(|i| (i*i % 7 + i % 13) as u8).collect();
+        for &(continued, coder) in [(false, Coder::Binary), (true, Coder::Range), (false, Coder::Rans)].iter() {
+            let header = header(input.len(), 1000, continued, coder);
+            let mut encoder = dc::Encoder::new(1000, Model::new());
+            encoder.set_coder(coder);
+            let (output, err) = multi::encode(&mut encoder, &input, 1000, continued, Vec::new());
+            err.unwrap();
+            let streams = super::collect_blocks(&input, 1000);
+            assert_eq!(super::measure_blocks(&streams, Model::new(), &header, None), output.len());
+        }
+    }
+
+    #[test]
+    fn optimize() {
+        use block::Encoder;
+        use block::dc;
+        use model::simple::{Model, Parameters};
+        let input: Vec<u8> = (0 .. 5000u32).map(|i| (i*i % 7 + i % 13) as u8).collect();
+        let header = header(input.len(), 0, false, Coder::Range);
+        let values = super::optimize("simple", &input, &header, None);
+        let params: Parameters = super::load(&values).unwrap();
+        let (out_default, _) = dc::Encoder::new(input.len(), Model::new()).encode(&input, Vec::new());
+        let (out_custom, _) = dc::Encoder::new(input.len(), Model::new_custom(params)).encode(&input, Vec::new());
+        assert!(out_custom.len() + values.len() <= out_default.len());
+    }
+
+    #[test]
+    fn load() {
+        let params: Parameters = super::load(&[]).unwrap();
+        assert_eq!(params, Parameters::new());
+        let params: Parameters = super::load(&[3, 8, 5]).unwrap();
+        assert_eq!(params.values(), vec![3, 8, 5]);
+        assert!(super::load::<Parameters>(&[3, 8]).is_err());
+        assert!(super::load::<Parameters>(&[3, 8, 50]).is_err());
+    }
+}